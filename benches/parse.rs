@@ -0,0 +1,70 @@
+//per-message parse latency of the public feeds, run with `cargo bench --bench parse`
+//the value_tree group is the previous serde_json::Value based parser kept as a baseline
+use anyhow::{anyhow, Result};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_decimal::Decimal;
+use sdla::utils::{
+    ids::{SymbolId, VenueId},
+    tick::Tick,
+};
+use serde_json::Value;
+
+const KRAKEN_SPREAD: &str = r#"[340,["0.000011480","0.000011490","1713447297.462361","137016937.12845706","149874.85683800"],"spread","PEPE/USD"]"#;
+const BINANCE_TRADE: &str = r#"{"stream":"pepeusdt@trade","data":{"e":"trade","E":1713447297463,"s":"PEPEUSDT","t":153289011,"p":"0.00001148","q":"4356445.00","b":3893716671,"a":3893716590,"T":1713447297462,"m":true,"M":true}}"#;
+
+fn value_tree_kraken(json_string: &str) -> Result<Decimal> {
+    let value: Value = serde_json::from_str(json_string)?;
+    let prices = value[1]
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid format for Kraken message"))?;
+    let bid: Decimal = prices[0]
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid format for bid price"))?;
+    let ask: Decimal = prices[1]
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid format for ask price"))?;
+    Ok((bid + ask) / Decimal::TWO)
+}
+
+fn value_tree_binance(message: &str) -> Result<Decimal> {
+    let value: Value = serde_json::from_str(message)?;
+    let data = value.get("data").ok_or_else(|| anyhow!("Missing data"))?;
+    data["p"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing price"))?
+        .parse::<Decimal>()
+        .map_err(|_| anyhow!("Invalid price format"))
+}
+
+fn bench_kraken(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kraken_spread");
+    group.bench_function("value_tree", |b| {
+        b.iter(|| value_tree_kraken(black_box(KRAKEN_SPREAD)).unwrap())
+    });
+    let (venue, symbol) = (VenueId::intern("Kraken"), SymbolId::intern("PEPE/USD"));
+    group.bench_function("typed", |b| {
+        b.iter(|| {
+            Tick::deserialize_tick_kraken(black_box(KRAKEN_SPREAD), venue, symbol, 0).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_binance(c: &mut Criterion) {
+    let mut group = c.benchmark_group("binance_trade");
+    group.bench_function("value_tree", |b| {
+        b.iter(|| value_tree_binance(black_box(BINANCE_TRADE)).unwrap())
+    });
+    let (venue, symbol) = (VenueId::intern("Binance"), SymbolId::intern("PEPE/USDT"));
+    group.bench_function("typed", |b| {
+        b.iter(|| {
+            Tick::deserialize_tick_binance(black_box(BINANCE_TRADE), venue, symbol, 0).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_kraken, bench_binance);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use sdla::utils::{
+    analytics::{AnalysisParams, LeadLagReport},
+    store,
+};
+
+const USAGE: &str =
+    "usage: analyze KRAKEN_TICKS BINANCE_TICKS [--out DIR] [--norm-gap X] [--fee X] \
+[--time-gap-ms N] [--max-lag-ms N] [--grid-ms N] [--min-move X]";
+
+//defaults match what main.rs runs oneleg with
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Err(anyhow!(USAGE));
+    }
+    let (kraken_path, binance_path) = (&args[0], &args[1]);
+
+    let mut out = "data/analysis".to_string();
+    let mut norm_gap = 0.00001;
+    let mut fee = 0.0052;
+    let mut params = AnalysisParams {
+        grid_ms: 100,
+        max_lag_ms: 5000,
+        gap_threshold: 0.0,
+        time_gap_ms: 1000,
+        min_move: 0.0001,
+    };
+    for option in args[2..].chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (name.as_str(), value),
+            _ => return Err(anyhow!(USAGE)),
+        };
+        match name {
+            "--out" => out = value.clone(),
+            "--norm-gap" => norm_gap = value.parse()?,
+            "--fee" => fee = value.parse()?,
+            "--time-gap-ms" => params.time_gap_ms = value.parse()?,
+            "--max-lag-ms" => params.max_lag_ms = value.parse()?,
+            "--grid-ms" => params.grid_ms = value.parse()?,
+            "--min-move" => params.min_move = value.parse()?,
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+    params.gap_threshold = norm_gap + fee;
+
+    let kraken = store::load_ticks(kraken_path)?;
+    let binance = store::load_ticks(binance_path)?;
+    let report = LeadLagReport::build(&kraken, &binance, params)?;
+    report.print();
+    report.save(&out)?;
+    println!("Report written to {}", out);
+    Ok(())
+}
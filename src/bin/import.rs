@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use sdla::utils::importer::{Importer, Source};
+
+const USAGE: &str = "usage: import SOURCE PAIR [--file PATH] [--store DIR] [--from DATE|MS] \
+[--to DATE|MS] [--interval MINUTES] [--delay-ms N]
+sources: kraken-csv, kraken-trades, kraken-spread, kraken-ohlc, binance-aggtrades, binance-klines";
+
+//YYYY-MM-DD at midnight utc, or ms since the epoch
+fn parse_time(value: &str) -> Result<u64> {
+    if value.contains('-') {
+        let date = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")?;
+        Ok(date
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis() as u64)
+    } else {
+        Ok(value.parse()?)
+    }
+}
+
+//a second run with the same source and pair resumes where the first one stopped,
+//without --to it keeps the first run's range
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Err(anyhow!(USAGE));
+    }
+    let source = Source::parse(&args[0]).ok_or_else(|| anyhow!(USAGE))?;
+    let asset = &args[1];
+
+    let mut file = None;
+    let mut store = "data/store".to_string();
+    let mut from = 0;
+    let mut to = None;
+    let mut interval = 1;
+    let mut delay_ms = None;
+    for option in args[2..].chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (name.as_str(), value),
+            _ => return Err(anyhow!(USAGE)),
+        };
+        match name {
+            "--file" => file = Some(value.clone()),
+            "--store" => store = value.clone(),
+            "--from" => from = parse_time(value)?,
+            "--to" => to = Some(parse_time(value)?),
+            "--interval" => interval = value.parse()?,
+            "--delay-ms" => delay_ms = Some(value.parse()?),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+
+    let mut importer = Importer::new(source, asset, file.as_deref(), &store, from, to)?;
+    importer.interval_minutes = interval;
+    if let Some(delay_ms) = delay_ms {
+        importer.page_delay_ms = delay_ms;
+    }
+    if !importer.state.cursor.is_empty() {
+        println!(
+            "Resuming {} {} at {}, {} rows imported before",
+            source.name(),
+            asset,
+            importer.state.cursor,
+            importer.state.rows
+        );
+    }
+    let rows = importer.run().await?;
+    println!(
+        "{} {} imported into {}, {} rows in total",
+        source.name(),
+        asset,
+        store,
+        rows
+    );
+    Ok(())
+}
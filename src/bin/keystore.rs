@@ -0,0 +1,104 @@
+use anyhow::{anyhow, Result};
+use sdla::utils::api_key_man::{Credentials, Keystore};
+use zeroize::Zeroizing;
+
+const USAGE: &str =
+    "usage: keystore [--file PATH] <add VENUE | rotate VENUE | remove VENUE | list | passwd>";
+
+fn prompt_secret(prompt: &str) -> Result<Zeroizing<String>> {
+    Ok(Zeroizing::new(rpassword::prompt_password(prompt)?))
+}
+
+//asked twice when creating or changing the passphrase, a typo would lock the store
+fn new_passphrase() -> Result<Zeroizing<String>> {
+    let first = prompt_secret("New keystore passphrase: ")?;
+    let second = prompt_secret("Repeat passphrase: ")?;
+    if *first != *second {
+        return Err(anyhow!("Passphrases do not match"));
+    }
+    if first.is_empty() {
+        return Err(anyhow!("Passphrase must not be empty"));
+    }
+    Ok(first)
+}
+
+fn open(path: &str, create: bool) -> Result<(Keystore, Zeroizing<String>)> {
+    if std::path::Path::new(path).exists() {
+        let passphrase = prompt_secret("Keystore passphrase: ")?;
+        let keystore = Keystore::load(path, &passphrase)?;
+        Ok((keystore, passphrase))
+    } else if create {
+        println!("Creating new keystore at {}", path);
+        Ok((Keystore::new(), new_passphrase()?))
+    } else {
+        Err(anyhow!("No keystore at {}", path))
+    }
+}
+
+fn read_credentials(venue: &str) -> Result<Credentials> {
+    let api_key = prompt_secret(&format!("{} api_key: ", venue))?;
+    let api_sec = prompt_secret(&format!("{} api_sec: ", venue))?;
+    let credentials = Credentials::new(api_key.trim().to_string(), api_sec.trim().to_string());
+    credentials.validate(venue)?;
+    Ok(credentials)
+}
+
+fn main() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut path =
+        std::env::var("SDLA_KEYSTORE").unwrap_or_else(|_| "config/keystore.json".to_string());
+    if args.first().map(String::as_str) == Some("--file") {
+        if args.len() < 2 {
+            return Err(anyhow!(USAGE));
+        }
+        path = args.remove(1);
+        args.remove(0);
+    }
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["add", venue] => {
+            let (mut keystore, passphrase) = open(&path, true)?;
+            if keystore.get(venue).is_some() {
+                return Err(anyhow!("{} already in keystore, use rotate", venue));
+            }
+            keystore.set(venue, read_credentials(venue)?);
+            keystore.save(&path, &passphrase)?;
+            println!("Added {}", venue);
+        }
+        ["rotate", venue] => {
+            let (mut keystore, passphrase) = open(&path, false)?;
+            if keystore.get(venue).is_none() {
+                return Err(anyhow!("{} not in keystore, use add", venue));
+            }
+            keystore.set(venue, read_credentials(venue)?);
+            keystore.save(&path, &passphrase)?;
+            println!("Rotated {}", venue);
+        }
+        ["remove", venue] => {
+            let (mut keystore, passphrase) = open(&path, false)?;
+            if keystore.remove(venue).is_none() {
+                return Err(anyhow!("{} not in keystore", venue));
+            }
+            keystore.save(&path, &passphrase)?;
+            println!("Removed {}", venue);
+        }
+        ["list"] => {
+            let (keystore, _) = open(&path, false)?;
+            for venue in keystore.venues() {
+                println!("{}", venue);
+            }
+        }
+        ["passwd"] => {
+            let (keystore, _) = open(&path, false)?;
+            keystore.save(&path, &new_passphrase()?)?;
+            println!("Passphrase changed");
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+    Ok(())
+}
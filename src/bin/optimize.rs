@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use sdla::{
+    strats::{
+        backtest::{BacktestConfig, MarketData},
+        optimize::{self, ParamGrid, ParamRanges},
+    },
+    utils::store,
+};
+
+const USAGE: &str =
+    "usage: optimize KRAKEN_TICKS BINANCE_TICKS [--random N] [--seed N] [--splits N] \
+[--threads N] [--fee X] [--quote X] [--base X] [--out FILE]";
+
+//the grid and ranges are centred on what main.rs runs oneleg with
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        return Err(anyhow!(USAGE));
+    }
+    let (kraken_path, binance_path) = (&args[0], &args[1]);
+
+    let mut random = 0usize;
+    let mut seed = 0u64;
+    let mut splits = 3usize;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut out = "data/optimize/sweep.csv".to_string();
+    let mut config = BacktestConfig {
+        round_trip_fee: dec!(0.0052),
+        initial_quote: dec!(1000),
+        initial_base: dec!(100000000),
+        sharpe_bucket_ms: 60 * 1000,
+    };
+    for option in args[2..].chunks(2) {
+        let (name, value) = match option {
+            [name, value] => (name.as_str(), value),
+            _ => return Err(anyhow!(USAGE)),
+        };
+        match name {
+            "--random" => random = value.parse()?,
+            "--seed" => seed = value.parse()?,
+            "--splits" => splits = value.parse()?,
+            "--threads" => threads = value.parse()?,
+            "--fee" => config.round_trip_fee = value.parse()?,
+            "--quote" => config.initial_quote = value.parse()?,
+            "--base" => config.initial_base = value.parse()?,
+            "--out" => out = value.clone(),
+            _ => return Err(anyhow!(USAGE)),
+        }
+    }
+
+    let candidates = if random > 0 {
+        ParamRanges {
+            norm_trade_size: (dec!(0.001), dec!(0.05)),
+            norm_gap: (Decimal::ZERO, dec!(0.005)),
+            time_gap_ms: (100, 5000),
+            max_time_diff_ms: (10, 1000),
+        }
+        .sample(random, seed)
+    } else {
+        ParamGrid {
+            norm_trade_size: vec![dec!(0.005), dec!(0.01), dec!(0.02)],
+            norm_gap: vec![dec!(0.00001), dec!(0.0005), dec!(0.001), dec!(0.002)],
+            time_gap_ms: vec![250, 500, 1000, 2000],
+            max_time_diff_ms: vec![50, 100, 250, 500],
+        }
+        .combinations()
+    };
+
+    //Binance is the fast leg, Kraken the slow one
+    let kraken = store::load_ticks(kraken_path)?;
+    let binance = store::load_ticks(binance_path)?;
+    let data = MarketData::new(&binance, &kraken);
+    println!(
+        "Backtesting {} parameter sets over {} walk-forward splits on {} threads",
+        candidates.len(),
+        splits,
+        threads
+    );
+    let report = optimize::sweep(&data, &candidates, &config, splits, threads)?;
+
+    for step in &report.walk_forward {
+        println!(
+            "split {}..{}: best in sample {:?}, out of sample pnl {} sharpe {:.2} trades {}",
+            step.split.test_from,
+            step.split.test_to,
+            step.params,
+            step.test.pnl.round_dp(4),
+            step.test.sharpe,
+            step.test.trades
+        );
+    }
+    for (rank, row) in report.rows.iter().take(10).enumerate() {
+        println!(
+            "{:>3}. {:?} pnl {} sharpe {:.2} drawdown {:.4} trades {}",
+            rank + 1,
+            row.params,
+            row.test.pnl.round_dp(4),
+            row.test.sharpe,
+            row.test.max_drawdown,
+            row.test.trades
+        );
+    }
+    report.save_csv(&out)?;
+    report.save_json(&out.replace(".csv", ".json"))?;
+    println!("Ranked table written to {}", out);
+    Ok(())
+}
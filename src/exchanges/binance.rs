@@ -1,37 +1,36 @@
 use super::*;
 
-use futures_util::TryFutureExt;
 use hmac::{Hmac, Mac};
 
 use serde::Deserialize;
 use sha2::Sha256;
 
+use crate::api_key_man::Credentials;
+use crate::order::{AccountEvent, OrderUpdate};
+use anyhow::anyhow;
+use std::sync::{Arc, Mutex};
+
 const TIME_OFFSET_MS_BINANCE: u64 = 36880;
 
+//clones share the credentials, they are wiped when the last one is dropped
+#[derive(Clone)]
 pub struct Binance {
-    api_key: String,
-    api_sec: String,
-}
-
-impl Binance {
-    pub fn get_api_key(&self) -> &String {
-        &self.api_key
-    }
-    pub fn get_api_sec(&self) -> &String {
-        &self.api_sec
-    }
+    credentials: Arc<Credentials>,
+    listen_key: Arc<Mutex<String>>,
 }
 
 impl Client for Binance {
-    fn new(api_key: String, api_sec: String) -> Binance {
-        BinanceConfig::set_binance_api_credentials(api_key.clone(), api_sec.clone());
-        Binance { api_key, api_sec }
+    fn new(credentials: Credentials) -> Binance {
+        Binance {
+            credentials: Arc::new(credentials),
+            listen_key: Arc::new(Mutex::new(String::new())),
+        }
     }
 }
 
 impl WebsocketClient for Binance {
     fn ws_url(&self) -> String {
-        "wss://stream.binance.com:9443/stream".to_string()
+        format!("{}/stream", BinanceConfig::get_binance_ws_url())
     }
 
     fn subscription_message(&self, asset: &str) -> String {
@@ -45,20 +44,136 @@ impl WebsocketClient for Binance {
         })
         .to_string()
     }
+
+    //trades are the hot path and tried first, anything else is a control message or a book
+    fn parse_message(
+        &self,
+        text: &str,
+        venue: VenueId,
+        symbol: SymbolId,
+        timestamp2: u64,
+    ) -> ExchangeEvent {
+        match Tick::deserialize_tick_binance(text, venue, symbol, timestamp2) {
+            Ok(tick) => ExchangeEvent::Trade(tick),
+            Err(_) => BinanceUtils::parse_control_message(text)
+                .unwrap_or_else(|e| ExchangeEvent::unparsed(e, text)),
+        }
+    }
+}
+
+impl PrivateWebsocketClient for Binance {
+    //every connection gets a fresh listenKey, an expired one cannot be revived
+    async fn private_ws_url(&self) -> anyhow::Result<String> {
+        let response = BinanceClient::user_data_stream_request(
+            reqwest::Method::POST,
+            "",
+            &self.credentials.api_key,
+        )
+        .await?;
+        let value: serde_json::Value = serde_json::from_str(&response).map_err(|e| {
+            anyhow!(
+                "Failed to parse JSON: {}\n The response is: {}",
+                e,
+                response
+            )
+        })?;
+        let listen_key = value["listenKey"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Failed to create listenKey: {}", response))?
+            .to_string();
+
+        *self.listen_key.lock().unwrap() = listen_key.clone();
+        Ok(format!(
+            "{}/ws/{}",
+            BinanceConfig::get_binance_ws_url(),
+            listen_key
+        ))
+    }
+
+    async fn private_subscription_messages(&self) -> anyhow::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn parse_account_events(&self, text: &str) -> anyhow::Result<Vec<AccountEvent>> {
+        AccountEvent::deserialize_binance(text)
+    }
+
+    //listenKeys expire after 60 minutes without a keepalive
+    fn keepalive_interval(&self) -> Option<Duration> {
+        Some(BinanceConfig::get_listen_key_keepalive())
+    }
+
+    async fn keepalive(&self) -> anyhow::Result<()> {
+        let listen_key = self.listen_key.lock().unwrap().clone();
+        let response = BinanceClient::user_data_stream_request(
+            reqwest::Method::PUT,
+            &listen_key,
+            &self.credentials.api_key,
+        )
+        .await?;
+        let value: serde_json::Value = serde_json::from_str(&response).map_err(|e| {
+            anyhow!(
+                "Failed to parse JSON: {}\n The response is: {}",
+                e,
+                response
+            )
+        })?;
+        match value.get("code") {
+            Some(_) => Err(anyhow!("Failed to keep listenKey alive: {}", response)),
+            None => Ok(()),
+        }
+    }
+
+    fn is_session_expired(&self, text: &str) -> bool {
+        serde_json::from_str::<serde_json::Value>(text)
+            .map(|value| value["e"] == "listenKeyExpired")
+            .unwrap_or(false)
+    }
+
+    async fn open_orders(&self) -> anyhow::Result<Vec<OrderUpdate>> {
+        let response = self.query("openOrders", "").await;
+        OrderUpdate::deserialize_orders_binance_rest(&response)
+    }
+}
+
+impl Binance {
+    //{"ipRestrict": false, "enableWithdrawals": false, "enableReading": true,
+    // "enableSpotAndMarginTrading": true, ...}
+    pub async fn key_permissions(&self) -> anyhow::Result<KeyPermissions> {
+        let response = self.query("apiRestrictions", "").await;
+        let value: serde_json::Value = serde_json::from_str(&response)
+            .map_err(|e| anyhow!("apiRestrictions failed: {}, response: {}", e, response))?;
+        if value.get("code").is_some() {
+            return Err(anyhow!("apiRestrictions rejected: {}", response));
+        }
+        let flag = |name: &str| value[name].as_bool().unwrap_or(false);
+        Ok(KeyPermissions {
+            venue: "Binance",
+            query_funds: flag("enableReading"),
+            query_orders: flag("enableReading"),
+            trade: flag("enableSpotAndMarginTrading"),
+            withdraw: flag("enableWithdrawals"),
+            ip_restricted: Some(flag("ipRestrict")),
+        })
+    }
 }
 
 impl RestClient for Binance {
-    async fn query(method: &str, url_encoded_body: &str) -> String {
-        BinanceClient::get_binance_api_response(method.to_string(), url_encoded_body.to_string())
+    async fn query(&self, method: &str, url_encoded_body: &str) -> String {
+        BinanceClient::get_binance_api_response(method, url_encoded_body, Some(&self.credentials))
             .await
     }
+
+    async fn public_query(method: &str, url_encoded_body: &str) -> String {
+        BinanceClient::get_binance_api_response(method, url_encoded_body, None).await
+    }
 }
 
-struct BinanceClient;
+pub struct BinanceClient;
 
 impl BinanceClient {
-    fn get_signature(query_string: &str) -> String {
-        let api_secret: String = BinanceConfig::get_binance_api_secret();
+    //signature = hex(HMAC-SHA256(secret, query string)), over the query exactly as sent
+    pub fn get_signature(query_string: &str, api_secret: &str) -> String {
         let key = api_secret.as_bytes();
         let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
         mac.update(query_string.as_bytes());
@@ -67,28 +182,48 @@ impl BinanceClient {
         hex::encode(code_bytes)
     }
 
-    async fn get_server_time() -> Result<i64, Box<dyn std::error::Error>> {
+    //timestamp goes last before the signature, the url is built from the payload unchanged
+    pub fn build_signed_query(
+        url_encoded_body: &str,
+        timestamp: i64,
+        api_secret: &str,
+    ) -> SignedPayload {
+        let payload = if url_encoded_body.is_empty() {
+            format!("timestamp={}", timestamp)
+        } else {
+            format!("{}&timestamp={}", url_encoded_body, timestamp)
+        };
+        let signature = BinanceClient::get_signature(&payload, api_secret);
+        SignedPayload { payload, signature }
+    }
+
+    async fn get_server_time() -> anyhow::Result<i64> {
         let client = reqwest::Client::new();
         let res = client
-            .get("https://api.binance.com/api/v3/time")
+            .get(format!(
+                "{}/api/{}/time",
+                BinanceConfig::get_binance_api_url(),
+                BinanceConfig::get_binance_api_version()
+            ))
+            .timeout(BinanceConfig::get_binance_api_timeout())
             .send()
             .await?
             .json::<serde_json::Value>()
             .await?;
 
-        Ok(res["serverTime"].as_i64().unwrap())
+        res["serverTime"]
+            .as_i64()
+            .ok_or_else(|| anyhow!("Missing serverTime in response: {}", res))
     }
 
+    //private methods need credentials, public ones ignore them
     pub async fn api_request(
         method: &str,
         url_encoded_body: &str,
-    ) -> Result<String, reqwest::Error> {
+        credentials: Option<&Credentials>,
+    ) -> anyhow::Result<String> {
         let method_type = BinanceUtils::get_method_type(method);
-        let api_path = format!(
-            "/api/{}/{}",
-            BinanceConfig::get_binance_api_version(),
-            method
-        );
+        let api_path = BinanceUtils::get_api_path(method);
         let mut api_endpoint = format!("{}{}", BinanceConfig::get_binance_api_url(), api_path);
         let api_timeout = BinanceConfig::get_binance_api_timeout();
 
@@ -104,33 +239,37 @@ impl BinanceClient {
                     .timeout(api_timeout)
                     .send()
                     .await?;
-                response.text().await
+                Ok(response.text().await?)
             }
             "private" => {
-                let server_time = BinanceClient::get_server_time().await.unwrap();
-                let mut signature = String::new();
-
-                if !url_encoded_body.is_empty() {
-                    api_endpoint = format!("{}?{}", api_endpoint, url_encoded_body);
-
-                    let query_string = format!("{}&timestamp={}", url_encoded_body, server_time);
-                    signature = BinanceClient::get_signature(&query_string);
+                let credentials = match credentials {
+                    Some(credentials) => credentials,
+                    None => panic!("void credentials"),
+                };
+                let server_time = BinanceClient::get_server_time().await?;
+                let request = BinanceClient::build_signed_query(
+                    url_encoded_body,
+                    server_time,
+                    &credentials.api_sec,
+                );
+                api_endpoint = format!(
+                    "{}?{}&signature={}",
+                    api_endpoint, request.payload, request.signature
+                );
+
+                let http_method = if BinanceUtils::is_method_post(method) {
+                    reqwest::Method::POST
                 } else {
-                    let query_string = format!("timestamp={}", server_time);
-                    signature = BinanceClient::get_signature(&query_string);
-                }
-
+                    reqwest::Method::GET
+                };
                 let client = reqwest::Client::new();
                 let res = client
-                    .get(api_endpoint)
-                    .query(&[
-                        ("timestamp", server_time.to_string().as_str()),
-                        ("signature", signature.as_str()),
-                    ])
-                    .header("X-MBX-APIKEY", BinanceConfig::get_binance_api_key())
+                    .request(http_method, api_endpoint)
+                    .header("X-MBX-APIKEY", credentials.api_key.as_str())
+                    .timeout(api_timeout)
                     .send()
                     .await?;
-                res.text().await
+                Ok(res.text().await?)
             }
             _ => {
                 panic!("Invalid method type");
@@ -138,12 +277,41 @@ impl BinanceClient {
         }
     }
 
+    //listenKey management only needs the api key, no signature
+    pub async fn user_data_stream_request(
+        http_method: reqwest::Method,
+        listen_key: &str,
+        api_key: &str,
+    ) -> Result<String, reqwest::Error> {
+        let mut api_endpoint = format!(
+            "{}/api/{}/userDataStream",
+            BinanceConfig::get_binance_api_url(),
+            BinanceConfig::get_binance_api_version()
+        );
+        if !listen_key.is_empty() {
+            api_endpoint = format!("{}?listenKey={}", api_endpoint, listen_key);
+        }
+
+        let client = reqwest::Client::new();
+        let res = client
+            .request(http_method, api_endpoint)
+            .header("X-MBX-APIKEY", api_key)
+            .timeout(BinanceConfig::get_binance_api_timeout())
+            .send()
+            .await?;
+        res.text().await
+    }
+
     // let account_info: AccountInfo = res.json()?;
     // println!("{:#?}", account_info);
 
     // Ok(())
-    pub async fn get_binance_api_response(api_method: String, url_encoded_body: String) -> String {
-        match BinanceClient::api_request(&api_method, &url_encoded_body).await {
+    pub async fn get_binance_api_response(
+        api_method: &str,
+        url_encoded_body: &str,
+        credentials: Option<&Credentials>,
+    ) -> String {
+        match BinanceClient::api_request(api_method, url_encoded_body, credentials).await {
             Ok(result) => result,
             Err(error) => error.to_string(),
         }
@@ -152,44 +320,58 @@ impl BinanceClient {
 
 //binance conf
 
-static mut BINANCE_API_KEY: String = String::new();
-static mut BINANCE_API_SECRET: String = String::new();
 const BINANCE_API_URL: &str = "https://api.binance.com";
+const BINANCE_WS_URL: &str = "wss://stream.binance.com:9443";
+//empty unless the clients are pointed at another server, e.g. the mock exchange in tests
+static BINANCE_API_URL_OVERRIDE: RwLock<String> = RwLock::new(String::new());
+static BINANCE_WS_URL_OVERRIDE: RwLock<String> = RwLock::new(String::new());
+const BINANCE_LISTEN_KEY_KEEPALIVE_SECS: u64 = 30 * 60;
+//None unless shortened, e.g. so tests see a keepalive without waiting half an hour
+static BINANCE_LISTEN_KEY_KEEPALIVE_OVERRIDE: RwLock<Option<Duration>> = RwLock::new(None);
 const BINANCE_API_VERSION: &str = "v3";
 const BINANCE_API_TIMEOUT: u64 = 5000;
 
+use std::sync::RwLock;
 use std::time::Duration;
 
-struct BinanceConfig;
+pub struct BinanceConfig;
 
 impl BinanceConfig {
-    pub fn set_binance_api_credentials(api_key: String, api_secret: String) {
-        BinanceConfig::set_binance_api_key(api_key);
-        BinanceConfig::set_binance_api_secret(api_secret);
+    fn set(value: &RwLock<String>, new: String) {
+        *value.write().unwrap() = new;
     }
 
-    pub fn set_binance_api_key(api_key: String) {
-        unsafe {
-            BINANCE_API_KEY = api_key;
+    pub fn set_binance_urls(api_url: String, ws_url: String) {
+        BinanceConfig::set(&BINANCE_API_URL_OVERRIDE, api_url);
+        BinanceConfig::set(&BINANCE_WS_URL_OVERRIDE, ws_url);
+    }
+
+    fn url_or_default(url: &RwLock<String>, default: &str) -> String {
+        let url = url.read().unwrap();
+        if url.is_empty() {
+            default.to_string()
+        } else {
+            url.to_string()
         }
     }
 
-    pub fn get_binance_api_key() -> String {
-        unsafe { BINANCE_API_KEY.to_string() }
+    pub fn get_binance_api_url() -> String {
+        BinanceConfig::url_or_default(&BINANCE_API_URL_OVERRIDE, BINANCE_API_URL)
     }
 
-    pub fn set_binance_api_secret(api_secret: String) {
-        unsafe {
-            BINANCE_API_SECRET = api_secret;
-        }
+    pub fn get_binance_ws_url() -> String {
+        BinanceConfig::url_or_default(&BINANCE_WS_URL_OVERRIDE, BINANCE_WS_URL)
     }
 
-    pub fn get_binance_api_secret() -> String {
-        unsafe { BINANCE_API_SECRET.to_owned() }
+    pub fn set_listen_key_keepalive(interval: Duration) {
+        *BINANCE_LISTEN_KEY_KEEPALIVE_OVERRIDE.write().unwrap() = Some(interval);
     }
 
-    pub fn get_binance_api_url() -> String {
-        BINANCE_API_URL.to_string()
+    pub fn get_listen_key_keepalive() -> Duration {
+        BINANCE_LISTEN_KEY_KEEPALIVE_OVERRIDE
+            .read()
+            .unwrap()
+            .unwrap_or(Duration::from_secs(BINANCE_LISTEN_KEY_KEEPALIVE_SECS))
     }
 
     pub fn get_binance_api_version() -> String {
@@ -203,18 +385,77 @@ impl BinanceConfig {
 
 #[derive(Debug, Deserialize)]
 struct BinanceTime {
-    serverTime: i64,
+    #[serde(rename = "serverTime")]
+    server_time: i64,
 }
 
 pub struct BinanceUtils;
 
 impl BinanceUtils {
+    //replies to SUBSCRIBE: {"result": null, "id": 1} or {"error": {"code": 2, "msg": "..."}, "id": 1}
+    //depth: {"stream": "pepeusdt@depth", "data": {"e": "depthUpdate", "b": [["price", "qty"]], "a": [...]}}
+    pub fn parse_control_message(text: &str) -> anyhow::Result<ExchangeEvent> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+
+        if let Some(error) = value.get("error") {
+            return Ok(ExchangeEvent::SubscriptionStatus {
+                status: "error".to_string(),
+                error: Some(error["msg"].as_str().unwrap_or_default().to_string()),
+            });
+        }
+        if value.get("result").is_some() && value.get("id").is_some() {
+            return Ok(ExchangeEvent::SubscriptionStatus {
+                status: "subscribed".to_string(),
+                error: None,
+            });
+        }
+
+        let data = &value["data"];
+        match data["e"].as_str() {
+            Some("depthUpdate") => Ok(ExchangeEvent::BookUpdate {
+                bids: ExchangeEvent::parse_price_levels(&data["b"])?,
+                asks: ExchangeEvent::parse_price_levels(&data["a"])?,
+            }),
+            _ => Err(anyhow!("Unknown Binance message")),
+        }
+    }
+
     pub fn is_method_public(method: &str) -> bool {
-        ["Time"].contains(&method)
+        ["Time", "exchangeInfo", "aggTrades", "klines"].contains(&method)
     }
 
     pub fn is_method_private(method: &str) -> bool {
-        ["account", "myTrades"].contains(&method)
+        [
+            "account",
+            "myTrades",
+            "openOrders",
+            "order",
+            "apiRestrictions",
+            "depositHistory",
+            "withdraw",
+            "withdrawHistory",
+        ]
+        .contains(&method)
+    }
+
+    //private methods that change something are POSTs, parameters still go in the query
+    pub fn is_method_post(method: &str) -> bool {
+        ["withdraw"].contains(&method)
+    }
+
+    //key and wallet endpoints live under /sapi
+    pub fn get_api_path(method: &str) -> String {
+        match method {
+            "apiRestrictions" => "/sapi/v1/account/apiRestrictions".to_string(),
+            "depositHistory" => "/sapi/v1/capital/deposit/hisrec".to_string(),
+            "withdraw" => "/sapi/v1/capital/withdraw/apply".to_string(),
+            "withdrawHistory" => "/sapi/v1/capital/withdraw/history".to_string(),
+            _ => format!(
+                "/api/{}/{}",
+                BinanceConfig::get_binance_api_version(),
+                method
+            ),
+        }
     }
 
     pub fn get_method_type(method: &str) -> &str {
@@ -234,12 +475,16 @@ impl BinanceUtils {
     }
 
     async fn binance_time_millis() -> Result<i64, reqwest::Error> {
-        let response = reqwest::get("https://api.binance.com/api/v3/time")
-            .await?
-            .json::<BinanceTime>()
-            .await?;
-
-        Ok(response.serverTime)
+        let response = reqwest::get(format!(
+            "{}/api/{}/time",
+            BinanceConfig::get_binance_api_url(),
+            BinanceConfig::get_binance_api_version()
+        ))
+        .await?
+        .json::<BinanceTime>()
+        .await?;
+
+        Ok(response.server_time)
     }
 
     // Make get_time_difference async and handle asynchronous call to binance_time_millis
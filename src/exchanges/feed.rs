@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, Notify};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+use crate::{
+    exchanges::{
+        binance::BinanceUtils, ExchangeEvent, ExchangeMessage, PrivateWebsocketClient,
+        WebsocketClient,
+    },
+    ids::{SymbolId, VenueId},
+    order::AccountEvent,
+};
+
+//public feed subscriptions the venue rejected are retried with backoff
+const SUBSCRIBE_RETRY_SECS: u64 = 1;
+const SUBSCRIBE_RETRY_MAX_SECS: u64 = 60;
+
+//the clock timestamp2 is stamped on, anything compared against timestamp2 must read it too
+pub fn feed_time_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64 - BinanceUtils::get_time_offset_millis()
+}
+
+pub async fn connect_and_run<T: WebsocketClient>(
+    exchange_name: &str,
+    exchange: T,
+    tx: mpsc::Sender<ExchangeMessage>,
+    asset: &str,
+    notify: Arc<Notify>,
+) {
+    let (ws_stream, _) = connect_async(exchange.ws_url())
+        .await
+        .expect("Failed to connect");
+    println!("WebSocket connected to {}", exchange_name);
+
+    // Notify that the exchange is connected
+    notify.notify_one();
+
+    let (mut write, mut read) = ws_stream.split();
+
+    let venue = VenueId::intern(exchange_name);
+    let symbol = SymbolId::intern(asset);
+    let subscribe_message = exchange.subscription_message(asset);
+    write
+        .send(Message::Text(subscribe_message.clone()))
+        .await
+        .expect("Failed to send subscribe message");
+
+    // Failed subscriptions are sent again with a doubling delay, reads carry on meanwhile
+    let mut retry_secs = SUBSCRIBE_RETRY_SECS;
+    let mut resubscribe: Option<std::pin::Pin<Box<tokio::time::Sleep>>> = None;
+
+    loop {
+        let message = tokio::select! {
+            message = read.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = async { resubscribe.as_mut().unwrap().await }, if resubscribe.is_some() => {
+                resubscribe = None;
+                println!("Resubscribing to {} {}", exchange_name, asset);
+                if let Err(e) = write.send(Message::Text(subscribe_message.clone())).await {
+                    eprintln!("Failed to resubscribe to {}: {}", exchange_name, e);
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if let Ok(Message::Text(text)) = message {
+            let timestamp2 = feed_time_millis();
+            let event = exchange.parse_message(&text, venue, symbol, timestamp2);
+
+            if event.is_failed_subscription() && resubscribe.is_none() {
+                resubscribe = Some(Box::pin(tokio::time::sleep(
+                    tokio::time::Duration::from_secs(retry_secs),
+                )));
+                retry_secs = (retry_secs * 2).min(SUBSCRIBE_RETRY_MAX_SECS);
+            } else if matches!(event, ExchangeEvent::SubscriptionStatus { error: None, .. }) {
+                retry_secs = SUBSCRIBE_RETRY_SECS;
+            }
+
+            let exchange_msg = ExchangeMessage {
+                venue,
+                symbol,
+                event,
+            };
+            if tx.send(exchange_msg).await.is_err() {
+                eprintln!("Failed to send message from {}", exchange_name);
+                break;
+            }
+        }
+    }
+}
+
+//private feeds reconnect on their own since order state must not go stale silently
+pub async fn connect_and_run_private<T: PrivateWebsocketClient>(
+    exchange_name: &str,
+    exchange: T,
+    tx: mpsc::Sender<AccountEvent>,
+) {
+    loop {
+        if let Err(e) = run_private(exchange_name, &exchange, &tx).await {
+            eprintln!("Private feed error from {}: {}", exchange_name, e);
+        }
+        if tx.is_closed() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    }
+}
+
+async fn run_private<T: PrivateWebsocketClient>(
+    exchange_name: &str,
+    exchange: &T,
+    tx: &mpsc::Sender<AccountEvent>,
+) -> anyhow::Result<()> {
+    let subscribe_messages = exchange.private_subscription_messages().await?;
+    let (ws_stream, _) = connect_async(exchange.private_ws_url().await?).await?;
+    println!("Private WebSocket connected to {}", exchange_name);
+
+    let (mut write, mut read) = ws_stream.split();
+    for subscribe_message in subscribe_messages {
+        write.send(Message::Text(subscribe_message)).await?;
+    }
+
+    //anything that changed while disconnected only shows in the venue's open orders,
+    //fetched once subscribed so no update falls between the list and the stream
+    let orders = exchange.open_orders().await?;
+    let open_orders = AccountEvent::OpenOrders {
+        exchange: exchange_name.to_string(),
+        orders,
+    };
+    if tx.send(open_orders).await.is_err() {
+        return Ok(());
+    }
+
+    // First tick fires immediately, skip it so keepalive starts one interval after connecting
+    let mut keepalive = exchange.keepalive_interval().map(tokio::time::interval);
+    if let Some(keepalive) = keepalive.as_mut() {
+        keepalive.tick().await;
+    }
+
+    loop {
+        tokio::select! {
+            _ = async { keepalive.as_mut().unwrap().tick().await }, if keepalive.is_some() => {
+                exchange.keepalive().await?;
+            }
+            message = read.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Err(anyhow::anyhow!("connection closed")),
+                };
+                if exchange.is_session_expired(&text) {
+                    return Err(anyhow::anyhow!("session expired"));
+                }
+                match exchange.parse_account_events(&text) {
+                    Ok(events) => {
+                        for event in events {
+                            if tx.send(event).await.is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to deserialize {} private message: {}\n The message content is: {}",
+                            exchange_name, e, text
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::*;
 
+use base64::Engine;
 use hmac::{Hmac, Mac};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
@@ -9,22 +10,34 @@ use sha2::{Digest, Sha256, Sha512};
 use std::time::{SystemTime, UNIX_EPOCH};
 type HmacSha512 = Hmac<Sha512>;
 
+use crate::api_key_man::Credentials;
+use crate::order::{AccountEvent, OrderRequest, OrderUpdate};
+use anyhow::anyhow;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use zeroize::Zeroizing;
+
+//clones share the credentials, they are wiped when the last one is dropped
+#[derive(Clone)]
 pub struct Kraken {
-    api_key: String,
-    api_sec: String,
+    credentials: Arc<Credentials>,
 }
 
 impl Client for Kraken {
-    fn new(api_key: String, api_sec: String) -> Kraken {
-        KrakenConfig::set_kraken_api_credentials(api_key.clone(), api_sec.clone());
-
-        Kraken { api_key, api_sec }
+    fn new(credentials: Credentials) -> Kraken {
+        Kraken {
+            credentials: Arc::new(credentials),
+        }
     }
 }
 
 impl WebsocketClient for Kraken {
     fn ws_url(&self) -> String {
-        "wss://ws.kraken.com".to_string()
+        KrakenConfig::get_kraken_ws_url()
     }
 
     fn subscription_message(&self, asset: &str) -> String {
@@ -37,41 +50,351 @@ impl WebsocketClient for Kraken {
         })
         .to_string()
     }
+
+    //spreads are the hot path and tried first, anything else is a control message or a book
+    fn parse_message(
+        &self,
+        text: &str,
+        venue: VenueId,
+        symbol: SymbolId,
+        timestamp2: u64,
+    ) -> ExchangeEvent {
+        match Tick::deserialize_tick_kraken(text, venue, symbol, timestamp2) {
+            Ok(tick) => ExchangeEvent::Ticker(tick),
+            Err(_) => KrakenUtils::parse_control_message(text)
+                .unwrap_or_else(|e| ExchangeEvent::unparsed(e, text)),
+        }
+    }
+}
+
+impl PrivateWebsocketClient for Kraken {
+    async fn private_ws_url(&self) -> anyhow::Result<String> {
+        Ok(KrakenConfig::get_kraken_ws_auth_url())
+    }
+
+    async fn private_subscription_messages(&self) -> anyhow::Result<Vec<String>> {
+        let token = self.get_ws_token().await?;
+        //no ownTrades snapshot, past trades would be booked again as new fills
+        Ok(vec![
+            serde_json::json!({
+                "event": "subscribe",
+                "subscription": {"name": "ownTrades", "token": token, "snapshot": false}
+            })
+            .to_string(),
+            serde_json::json!({
+                "event": "subscribe",
+                "subscription": {"name": "openOrders", "token": token}
+            })
+            .to_string(),
+        ])
+    }
+
+    fn parse_account_events(&self, text: &str) -> anyhow::Result<Vec<AccountEvent>> {
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        AccountEvent::deserialize_kraken(text, timestamp)
+    }
+
+    //the token only has to be valid when connecting
+    fn keepalive_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    async fn keepalive(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn is_session_expired(&self, _text: &str) -> bool {
+        false
+    }
+
+    async fn open_orders(&self) -> anyhow::Result<Vec<OrderUpdate>> {
+        let response = self.query("OpenOrders", "").await;
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        OrderUpdate::deserialize_orders_kraken_rest(&response, timestamp)
+    }
+}
+
+impl Kraken {
+    //token is valid for 15 minutes until a connection using it is made
+    pub async fn get_ws_token(&self) -> anyhow::Result<String> {
+        let response = self.query("GetWebSocketsToken", "").await;
+        KrakenUtils::parse_rest_result(&response)?["token"]
+            .as_str()
+            .map(|token| token.to_string())
+            .ok_or_else(|| anyhow!("Missing token in response: {}", response))
+    }
+
+    //kraken has no endpoint listing a key's permissions, each one is probed with a call
+    //that cannot change anything: AddOrder with validate, WithdrawInfo for a made up key
+    pub async fn key_permissions(&self) -> anyhow::Result<KeyPermissions> {
+        Ok(KeyPermissions {
+            venue: "Kraken",
+            query_funds: self.probe("Balance", "").await?,
+            query_orders: self.probe("OpenOrders", "").await?,
+            trade: self
+                .probe(
+                    "AddOrder",
+                    "pair=XBTUSD&type=buy&ordertype=limit&price=1&volume=0&validate=true",
+                )
+                .await?,
+            withdraw: self
+                .probe("WithdrawInfo", "asset=XBT&key=sdla-probe&amount=0")
+                .await?,
+            ip_restricted: None,
+        })
+    }
+
+    //only EGeneral:Permission denied means the permission is missing, any other venue
+    //error (bad arguments, unknown withdraw key) came after the permission check passed
+    async fn probe(&self, method: &str, url_encoded_body: &str) -> anyhow::Result<bool> {
+        let response = self.query(method, url_encoded_body).await;
+        let value: serde_json::Value = serde_json::from_str(&response)
+            .map_err(|e| anyhow!("{} probe failed: {}, response: {}", method, e, response))?;
+        let errors: Vec<&str> = value["error"]
+            .as_array()
+            .map(|errors| errors.iter().filter_map(|e| e.as_str()).collect())
+            .unwrap_or_default();
+        if errors.iter().any(|e| e.starts_with("EAPI:")) {
+            return Err(anyhow!("{} probe rejected: {:?}", method, errors));
+        }
+        Ok(!errors.contains(&"EGeneral:Permission denied"))
+    }
 }
 
 impl RestClient for Kraken {
-    async fn query(method: &str, url_encoded_body: &str) -> String {
-        KrakenClient::get_kraken_api_response(method.to_string(), url_encoded_body.to_string())
+    async fn query(&self, method: &str, url_encoded_body: &str) -> String {
+        KrakenClient::get_kraken_api_response(method, url_encoded_body, Some(&self.credentials))
             .await
     }
+
+    async fn public_query(method: &str, url_encoded_body: &str) -> String {
+        KrakenClient::get_kraken_api_response(method, url_encoded_body, None).await
+    }
+}
+
+//order entry over ws-auth, requests are matched to their status messages by reqid
+//and go through REST instead while the socket is down
+pub struct KrakenOrderClient {
+    kraken: Kraken,
+    outbound: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<anyhow::Result<serde_json::Value>>>>,
+    next_reqid: AtomicU64,
+    token: Mutex<String>,
+}
+
+impl KrakenOrderClient {
+    pub fn new(kraken: Kraken) -> KrakenOrderClient {
+        KrakenOrderClient {
+            kraken,
+            outbound: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            next_reqid: AtomicU64::new(1),
+            token: Mutex::new(String::new()),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.outbound.lock().unwrap().is_some()
+    }
+
+    //keeps the socket up for the lifetime of the bot
+    pub async fn run(&self) {
+        loop {
+            if let Err(e) = self.run_connection().await {
+                eprintln!("Kraken order socket error: {}", e);
+            }
+            self.disconnect();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn run_connection(&self) -> anyhow::Result<()> {
+        let token = self.kraken.get_ws_token().await?;
+        let (ws_stream, _) = connect_async(KrakenConfig::get_kraken_ws_auth_url()).await?;
+        let (mut write, mut read) = ws_stream.split();
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<String>();
+
+        *self.token.lock().unwrap() = token;
+        *self.outbound.lock().unwrap() = Some(outbound_tx);
+        println!("Order WebSocket connected to Kraken");
+
+        loop {
+            tokio::select! {
+                Some(text) = outbound_rx.recv() => {
+                    write.send(Message::Text(text)).await?;
+                }
+                message = read.next() => match message {
+                    Some(message) => {
+                        if let Message::Text(text) = message? {
+                            self.handle_message(&text);
+                        }
+                    }
+                    None => return Err(anyhow!("connection closed")),
+                },
+            }
+        }
+    }
+
+    fn handle_message(&self, text: &str) {
+        let value: serde_json::Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("Failed to parse Kraken order socket message: {}", e);
+                return;
+            }
+        };
+        match value["event"].as_str() {
+            Some("addOrderStatus") | Some("cancelOrderStatus") => {}
+            _ => return,
+        }
+        let reqid = match value["reqid"].as_u64() {
+            Some(reqid) => reqid,
+            None => return,
+        };
+        if let Some(responder) = self.pending.lock().unwrap().remove(&reqid) {
+            let result = if value["status"] == "ok" {
+                Ok(value)
+            } else {
+                Err(anyhow!(
+                    "Kraken rejected request: {}",
+                    value["errorMessage"]
+                ))
+            };
+            let _ = responder.send(result);
+        }
+    }
+
+    fn disconnect(&self) {
+        *self.outbound.lock().unwrap() = None;
+        for (_, responder) in self.pending.lock().unwrap().drain() {
+            let _ = responder.send(Err(anyhow!("Kraken order socket closed")));
+        }
+    }
+
+    //None means the request never left, so it is safe to retry it over REST
+    async fn ws_request(
+        &self,
+        mut request: serde_json::Value,
+    ) -> Option<anyhow::Result<serde_json::Value>> {
+        let (responder, response) = oneshot::channel();
+        {
+            let outbound = self.outbound.lock().unwrap();
+            let outbound = outbound.as_ref()?;
+            let reqid = self.next_reqid.fetch_add(1, Ordering::Relaxed);
+            request["reqid"] = reqid.into();
+            request["token"] = self.token.lock().unwrap().clone().into();
+
+            self.pending.lock().unwrap().insert(reqid, responder);
+            if outbound.send(request.to_string()).is_err() {
+                self.pending.lock().unwrap().remove(&reqid);
+                return None;
+            }
+        }
+
+        Some(
+            match tokio::time::timeout(KrakenConfig::get_kraken_api_timeout(), response).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(_)) => Err(anyhow!("Kraken order socket dropped the request")),
+                Err(_) => Err(anyhow!("Timed out waiting for Kraken order response")),
+            },
+        )
+    }
+
+    //returns the txid of the new order
+    pub async fn add_order(&self, order: &OrderRequest) -> anyhow::Result<String> {
+        let mut request = serde_json::json!({
+            "event": "addOrder",
+            "pair": order.asset,
+        });
+        for (key, value) in KrakenUtils::order_fields(order) {
+            request[key] = value.into();
+        }
+
+        match self.ws_request(request).await {
+            Some(response) => response?["txid"]
+                .as_str()
+                .map(|txid| txid.to_string())
+                .ok_or_else(|| anyhow!("Missing txid in addOrderStatus")),
+            None => {
+                let mut fields = KrakenUtils::order_fields(order);
+                fields.push(("pair", order.asset.replace("/", "")));
+                let response = self.kraken.query("AddOrder", &url_encode(&fields)).await;
+                let value = KrakenUtils::parse_rest_result(&response)?;
+                value["txid"][0]
+                    .as_str()
+                    .map(|txid| txid.to_string())
+                    .ok_or_else(|| anyhow!("Missing txid in AddOrder response: {}", response))
+            }
+        }
+    }
+
+    pub async fn cancel_order(&self, txid: &str) -> anyhow::Result<()> {
+        let request = serde_json::json!({
+            "event": "cancelOrder",
+            "txid": [txid],
+        });
+
+        match self.ws_request(request).await {
+            Some(response) => response.map(|_| ()),
+            None => {
+                let body = url_encode(&[("txid", txid.to_string())]);
+                let response = self.kraken.query("CancelOrder", &body).await;
+                KrakenUtils::parse_rest_result(&response).map(|_| ())
+            }
+        }
+    }
+
+    //the venue's own record of the order over REST, independent of the private stream
+    pub async fn query_order(&self, txid: &str) -> anyhow::Result<OrderUpdate> {
+        let body = url_encode(&[("txid", txid.to_string())]);
+        let response = self.kraken.query("QueryOrders", &body).await;
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        OrderUpdate::deserialize_orders_kraken_rest(&response, timestamp)?
+            .into_iter()
+            .find(|update| update.order_id == txid)
+            .ok_or_else(|| anyhow!("Kraken has no order {}", txid))
+    }
 }
 
-struct KrakenClient;
+pub struct KrakenClient;
 
 impl KrakenClient {
-    fn get_signature(
-        api_path: String,
-        nonce: String,
-        url_encoded_body: String,
-        api_secret: String,
-    ) -> String {
-        // API-Sign = Message signature using HMAC-SHA512 of (URI path + SHA256(nonce + POST data)) and base64 decoded secret API key
-        let hash_digest = Sha256::digest(format!("{}{}", nonce, url_encoded_body).as_bytes());
-        let private_key = base64::decode(&api_secret).unwrap();
+    // API-Sign = Message signature using HMAC-SHA512 of (URI path + SHA256(nonce + POST data)) and base64 decoded secret API key
+    pub fn get_signature(api_path: &str, nonce: &str, post_data: &str, api_secret: &str) -> String {
+        let hash_digest = Sha256::digest(format!("{}{}", nonce, post_data).as_bytes());
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let private_key = Zeroizing::new(
+            b64.decode(api_secret)
+                .expect("Kraken API secret is not base64"),
+        );
         let mut mac = HmacSha512::new_from_slice(&private_key).unwrap();
 
-        let mut hmac_data = api_path.into_bytes();
-        hmac_data.append(&mut hash_digest.to_vec());
+        let mut hmac_data = api_path.as_bytes().to_vec();
+        hmac_data.extend_from_slice(&hash_digest);
         mac.update(&hmac_data);
-        base64::encode(mac.finalize().into_bytes())
+        b64.encode(mac.finalize().into_bytes())
+    }
+
+    //the nonce leads the POST body, the body is sent as built here
+    pub fn build_private_request(
+        api_path: &str,
+        nonce: u64,
+        url_encoded_body: &str,
+        api_secret: &str,
+    ) -> SignedPayload {
+        let payload = if url_encoded_body.is_empty() {
+            format!("nonce={}", nonce)
+        } else {
+            format!("nonce={}&{}", nonce, url_encoded_body)
+        };
+        let signature =
+            KrakenClient::get_signature(api_path, &nonce.to_string(), &payload, api_secret);
+        SignedPayload { payload, signature }
     }
 
-    fn get_headers(signature: String) -> HeaderMap {
+    fn get_headers(api_key: &str, signature: String) -> HeaderMap {
         let mut headers = HeaderMap::new();
-        headers.insert(
-            "API-Key",
-            HeaderValue::from_str(&KrakenConfig::get_kraken_api_key()).unwrap(),
-        );
+        headers.insert("API-Key", HeaderValue::from_str(api_key).unwrap());
         headers.insert(
             "API-Sign",
             HeaderValue::from_str(&signature.to_string()).unwrap(),
@@ -79,7 +402,12 @@ impl KrakenClient {
         headers
     }
 
-    pub async fn api_request(method: &str, url_encoded_body: &str) -> Result<String, Error> {
+    //private methods need credentials, public ones ignore them
+    pub async fn api_request(
+        method: &str,
+        url_encoded_body: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<String, Error> {
         let method_type: &str = KrakenUtils::get_method_type(method);
         let api_path = format!(
             "/{}/{}/{}",
@@ -101,32 +429,28 @@ impl KrakenClient {
                     .await
             }
             "private" => {
-                if KrakenConfig::get_kraken_api_key().is_empty()
-                    || KrakenConfig::get_kraken_api_secret().is_empty()
-                {
-                    panic!("void credentials");
-                }
+                let credentials = match credentials {
+                    Some(credentials) => credentials,
+                    None => panic!("void credentials"),
+                };
                 let nonce = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
                     .as_millis() as u64;
-                let payload_nonce = format!("nonce={}", &nonce.to_string());
-                let payload_body = if !url_encoded_body.is_empty() {
-                    format!("{}&{}", payload_nonce, url_encoded_body)
-                } else {
-                    payload_nonce
-                };
-                let signature = KrakenClient::get_signature(
-                    api_path,
-                    nonce.to_string(),
-                    payload_body.to_owned(),
-                    KrakenConfig::get_kraken_api_secret(),
+                let request = KrakenClient::build_private_request(
+                    &api_path,
+                    nonce,
+                    url_encoded_body,
+                    &credentials.api_sec,
                 );
                 reqwest::Client::new()
                     .post(&api_endpoint)
-                    .headers(KrakenClient::get_headers(signature))
+                    .headers(KrakenClient::get_headers(
+                        &credentials.api_key,
+                        request.signature,
+                    ))
                     .timeout(api_timeout)
-                    .body(payload_body)
+                    .body(request.payload)
                     .send()
                     .await
             }
@@ -138,8 +462,12 @@ impl KrakenClient {
         }
     }
 
-    pub async fn get_kraken_api_response(api_method: String, url_encoded_body: String) -> String {
-        match KrakenClient::api_request(&api_method, &url_encoded_body).await {
+    pub async fn get_kraken_api_response(
+        api_method: &str,
+        url_encoded_body: &str,
+        credentials: Option<&Credentials>,
+    ) -> String {
+        match KrakenClient::api_request(api_method, url_encoded_body, credentials).await {
             Ok(result) => result,
             Err(error) => error.to_string(),
         }
@@ -148,44 +476,51 @@ impl KrakenClient {
 
 //kraken conf
 
-static mut KRAKEN_API_KEY: String = String::new();
-static mut KRAKEN_API_SECRET: String = String::new();
 const KRAKEN_API_URL: &str = "https://api.kraken.com";
+const KRAKEN_WS_URL: &str = "wss://ws.kraken.com";
+const KRAKEN_WS_AUTH_URL: &str = "wss://ws-auth.kraken.com";
+//empty unless the clients are pointed at another server, e.g. the mock exchange in tests
+static KRAKEN_API_URL_OVERRIDE: RwLock<String> = RwLock::new(String::new());
+static KRAKEN_WS_URL_OVERRIDE: RwLock<String> = RwLock::new(String::new());
+static KRAKEN_WS_AUTH_URL_OVERRIDE: RwLock<String> = RwLock::new(String::new());
 const KRAKEN_API_VERSION: &str = "0";
 const KRAKEN_API_TIMEOUT: u64 = 5000;
 
+use std::sync::RwLock;
 use std::time::Duration;
 
-struct KrakenConfig;
+pub struct KrakenConfig;
 
 impl KrakenConfig {
-    pub fn set_kraken_api_credentials(api_key: String, api_secret: String) {
-        KrakenConfig::set_kraken_api_key(api_key);
-        KrakenConfig::set_kraken_api_secret(api_secret);
+    fn set(value: &RwLock<String>, new: String) {
+        *value.write().unwrap() = new;
     }
 
-    pub fn set_kraken_api_key(api_key: String) {
-        unsafe {
-            KRAKEN_API_KEY = api_key;
-        }
+    pub fn set_kraken_urls(api_url: String, ws_url: String, ws_auth_url: String) {
+        KrakenConfig::set(&KRAKEN_API_URL_OVERRIDE, api_url);
+        KrakenConfig::set(&KRAKEN_WS_URL_OVERRIDE, ws_url);
+        KrakenConfig::set(&KRAKEN_WS_AUTH_URL_OVERRIDE, ws_auth_url);
     }
 
-    pub fn get_kraken_api_key() -> String {
-        unsafe { KRAKEN_API_KEY.to_string() }
+    fn url_or_default(url: &RwLock<String>, default: &str) -> String {
+        let url = url.read().unwrap();
+        if url.is_empty() {
+            default.to_string()
+        } else {
+            url.to_string()
+        }
     }
 
-    pub fn set_kraken_api_secret(api_secret: String) {
-        unsafe {
-            KRAKEN_API_SECRET = api_secret;
-        }
+    pub fn get_kraken_api_url() -> String {
+        KrakenConfig::url_or_default(&KRAKEN_API_URL_OVERRIDE, KRAKEN_API_URL)
     }
 
-    pub fn get_kraken_api_secret() -> String {
-        unsafe { KRAKEN_API_SECRET.to_owned() }
+    pub fn get_kraken_ws_url() -> String {
+        KrakenConfig::url_or_default(&KRAKEN_WS_URL_OVERRIDE, KRAKEN_WS_URL)
     }
 
-    pub fn get_kraken_api_url() -> String {
-        KRAKEN_API_URL.to_string()
+    pub fn get_kraken_ws_auth_url() -> String {
+        KrakenConfig::url_or_default(&KRAKEN_WS_AUTH_URL_OVERRIDE, KRAKEN_WS_AUTH_URL)
     }
 
     pub fn get_kraken_api_version() -> String {
@@ -197,9 +532,58 @@ impl KrakenConfig {
     }
 }
 
-struct KrakenUtils;
+pub struct KrakenUtils;
 
 impl KrakenUtils {
+    //{"event": "heartbeat"}, {"event": "systemStatus", "status": "online"},
+    //{"event": "subscriptionStatus", "status": "error", "errorMessage": "..."},
+    //[channelID, {"b": [["price", "volume", "timestamp"]]}, {"a": [...]}, "book-10", "XBT/USD"]
+    pub fn parse_control_message(text: &str) -> anyhow::Result<ExchangeEvent> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+
+        if let Some(array) = value.as_array() {
+            let channel = array
+                .len()
+                .checked_sub(2)
+                .and_then(|i| array[i].as_str())
+                .unwrap_or_default();
+            if !channel.starts_with("book") {
+                return Err(anyhow!("Unknown Kraken channel {}", channel));
+            }
+            let mut bids = Vec::new();
+            let mut asks = Vec::new();
+            //snapshots use "bs"/"as", updates "b"/"a", "c" is the checksum
+            for side in &array[1..array.len() - 2] {
+                for (key, levels) in side.as_object().into_iter().flatten() {
+                    match key.as_str() {
+                        "b" | "bs" => bids.extend(ExchangeEvent::parse_price_levels(levels)?),
+                        "a" | "as" => asks.extend(ExchangeEvent::parse_price_levels(levels)?),
+                        _ => {}
+                    }
+                }
+            }
+            return Ok(ExchangeEvent::BookUpdate { bids, asks });
+        }
+
+        let field = |name: &str| value[name].as_str().unwrap_or_default().to_string();
+        match value["event"].as_str() {
+            Some("heartbeat") => Ok(ExchangeEvent::Heartbeat),
+            Some("systemStatus") => Ok(ExchangeEvent::SystemStatus {
+                status: VenueStatus::from_str_kraken(&field("status"))
+                    .ok_or_else(|| anyhow!("Unknown Kraken system status {}", value["status"]))?,
+            }),
+            Some("subscriptionStatus") => Ok(ExchangeEvent::SubscriptionStatus {
+                status: field("status"),
+                error: value["errorMessage"].as_str().map(str::to_string),
+            }),
+            Some("error") => Ok(ExchangeEvent::Error {
+                message: field("errorMessage"),
+                raw: text.to_string(),
+            }),
+            _ => Err(anyhow!("Unknown Kraken message")),
+        }
+    }
+
     pub fn is_method_public(method: &str) -> bool {
         [
             "Time",
@@ -234,6 +618,7 @@ impl KrakenUtils {
             "DepositAddresses",
             "DepositStatus",
             "WithdrawInfo",
+            "WithdrawAddresses",
             "Withdraw",
             "WithdrawStatus",
             "WithdrawCancel",
@@ -252,4 +637,35 @@ impl KrakenUtils {
         };
         method_type
     }
+
+    //fields shared by the websocket and REST order bodies, minus the pair
+    pub fn order_fields(order: &OrderRequest) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("ordertype", order.order_type.as_str_kraken().to_string()),
+            ("type", order.side.as_str_kraken().to_string()),
+            ("volume", order.qty.normalize().to_string()),
+        ];
+        if let Some(price) = order.price {
+            fields.push(("price", price.normalize().to_string()));
+        }
+        if let Some(client_order_id) = &order.client_order_id {
+            fields.push(("userref", client_order_id.clone()));
+        }
+        fields
+    }
+
+    //returns the "result" object or the venue's error list
+    pub fn parse_rest_result(response: &str) -> anyhow::Result<serde_json::Value> {
+        let value: serde_json::Value = serde_json::from_str(response).map_err(|e| {
+            anyhow!(
+                "Failed to parse JSON: {}\n The response is: {}",
+                e,
+                response
+            )
+        })?;
+        match value["error"].as_array() {
+            Some(errors) if !errors.is_empty() => Err(anyhow!("Kraken error: {:?}", errors)),
+            _ => Ok(value["result"].clone()),
+        }
+    }
 }
@@ -1,23 +1,208 @@
 // Exchange trait and Kraken implementation
 pub mod binance;
+pub mod feed;
 pub mod kraken;
 
+use crate::api_key_man::Credentials;
+use crate::ids::{SymbolId, VenueId};
+use crate::order::{AccountEvent, OrderUpdate};
+use crate::tick::Tick;
+use rust_decimal::Decimal;
+use std::time::Duration;
+
+//clients own their credentials, nothing else keeps a copy of the secret
 pub trait Client {
-    fn new(api_key: String, api_sec: String) -> Self;
+    fn new(credentials: Credentials) -> Self;
 }
 
 pub trait WebsocketClient {
     fn ws_url(&self) -> String;
     fn subscription_message(&self, asset: &str) -> String;
+    //runs in the socket task, frames it does not recognise come back as ExchangeEvent::Error
+    fn parse_message(
+        &self,
+        text: &str,
+        venue: VenueId,
+        symbol: SymbolId,
+        timestamp2: u64,
+    ) -> ExchangeEvent;
+}
+
+#[allow(async_fn_in_trait)]
+pub trait PrivateWebsocketClient {
+    async fn private_ws_url(&self) -> anyhow::Result<String>;
+    async fn private_subscription_messages(&self) -> anyhow::Result<Vec<String>>;
+    fn parse_account_events(&self, text: &str) -> anyhow::Result<Vec<AccountEvent>>;
+    //sessions that expire unless refreshed return how often to call keepalive
+    fn keepalive_interval(&self) -> Option<Duration>;
+    async fn keepalive(&self) -> anyhow::Result<()>;
+    fn is_session_expired(&self, text: &str) -> bool;
+    //REST open orders, the streams do not replay what changed while disconnected
+    async fn open_orders(&self) -> anyhow::Result<Vec<OrderUpdate>>;
 }
 
+//a private request's payload and its signature, built together so the bytes sent
+//are exactly the bytes signed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedPayload {
+    pub payload: String,
+    pub signature: String,
+}
+
+//form encoded request parameters, for the body of a POST or the query of a GET
+pub fn url_encode(fields: &[(&str, String)]) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(fields)
+        .finish()
+}
+
+#[allow(async_fn_in_trait)]
 pub trait RestClient {
-    async fn query(method: &str, url_encoded_body: &str) -> String;
+    async fn query(&self, method: &str, url_encoded_body: &str) -> String;
+    //public endpoints need no credentials and no client
+    async fn public_query(method: &str, url_encoded_body: &str) -> String;
+}
+
+//what a configured api key is allowed to do, checked once at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyPermissions {
+    pub venue: &'static str,
+    pub query_funds: bool,
+    pub query_orders: bool,
+    pub trade: bool,
+    pub withdraw: bool,
+    //None when the venue does not report it
+    pub ip_restricted: Option<bool>,
+}
+
+impl KeyPermissions {
+    //a trading key that can also withdraw is refused unless allow_withdraw is set,
+    //everything else only warns
+    pub fn check(&self, allow_withdraw: bool) -> anyhow::Result<()> {
+        println!("{} key permissions: {:?}", self.venue, self);
+        if self.withdraw {
+            if self.trade && !allow_withdraw {
+                return Err(anyhow::anyhow!(
+                    "{} trading key has withdrawal permission, remove it or allow withdrawal keys explicitly",
+                    self.venue
+                ));
+            }
+            eprintln!("Warning: {} key has withdrawal permission", self.venue);
+        }
+        if !self.trade {
+            eprintln!("Warning: {} key cannot trade", self.venue);
+        }
+        if !self.query_funds || !self.query_orders {
+            eprintln!(
+                "Warning: {} key cannot query funds or orders, reconciliation will fail",
+                self.venue
+            );
+        }
+        match self.ip_restricted {
+            Some(true) => {}
+            Some(false) => eprintln!("Warning: {} key has no IP whitelist", self.venue),
+            None => eprintln!(
+                "Warning: {} does not report IP whitelisting, check the key settings",
+                self.venue
+            ),
+        }
+        Ok(())
+    }
 }
 
-// Message struct for channel communication
+// Message struct for channel communication, one socket task serves one venue and symbol
+#[derive(Debug, Clone)]
 pub struct ExchangeMessage {
-    pub sender: String,
-    pub asset: String,
-    pub content: String,
+    pub venue: VenueId,
+    pub symbol: SymbolId,
+    pub event: ExchangeEvent,
+}
+
+//price levels are (price, qty), a qty of zero removes the level
+#[derive(Debug, Clone)]
+pub enum ExchangeEvent {
+    Ticker(Tick),
+    Trade(Tick),
+    BookUpdate {
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+    },
+    Heartbeat,
+    SubscriptionStatus {
+        status: String,
+        error: Option<String>,
+    },
+    SystemStatus {
+        status: VenueStatus,
+    },
+    //errors sent by the venue and frames we could not parse, raw is the frame as received
+    Error {
+        message: String,
+        raw: String,
+    },
+}
+
+//trading state the venue announces for itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VenueStatus {
+    Online,
+    Maintenance,
+    CancelOnly,
+    LimitOnly,
+    PostOnly,
+}
+
+impl VenueStatus {
+    pub fn from_str_kraken(status: &str) -> Option<VenueStatus> {
+        match status {
+            "online" => Some(VenueStatus::Online),
+            "maintenance" => Some(VenueStatus::Maintenance),
+            "cancel_only" => Some(VenueStatus::CancelOnly),
+            "limit_only" => Some(VenueStatus::LimitOnly),
+            "post_only" => Some(VenueStatus::PostOnly),
+            _ => None,
+        }
+    }
+
+    //strategies send market orders, which limit_only and post_only reject as well
+    pub fn allows_trading(&self) -> bool {
+        *self == VenueStatus::Online
+    }
+}
+
+impl ExchangeEvent {
+    //the subscription has to be sent again, the venue will not retry it for us
+    pub fn is_failed_subscription(&self) -> bool {
+        matches!(
+            self,
+            ExchangeEvent::SubscriptionStatus { error: Some(_), .. }
+        )
+    }
+
+    //levels arrive as arrays of strings, [price, qty, ...] with venue specific extras after
+    pub fn parse_price_levels(
+        levels: &serde_json::Value,
+    ) -> anyhow::Result<Vec<(Decimal, Decimal)>> {
+        levels
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Invalid format for price levels"))?
+            .iter()
+            .map(|level| {
+                let field = |i: usize| {
+                    level[i]
+                        .as_str()
+                        .and_then(|s| s.parse::<Decimal>().ok())
+                        .ok_or_else(|| anyhow::anyhow!("Invalid format for price level"))
+                };
+                Ok((field(0)?, field(1)?))
+            })
+            .collect()
+    }
+
+    pub fn unparsed(error: anyhow::Error, raw: &str) -> ExchangeEvent {
+        ExchangeEvent::Error {
+            message: error.to_string(),
+            raw: raw.to_string(),
+        }
+    }
 }
@@ -0,0 +1,8 @@
+pub mod exchanges;
+pub mod strats;
+pub mod utils;
+
+use utils::*;
+
+//buffer const
+pub const BUFF_SIZE: usize = 100;
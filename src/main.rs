@@ -1,66 +1,226 @@
-#![allow(warnings)]
-pub mod exchanges;
-pub mod strats;
-pub mod utils;
+use sdla::{exchanges, strats, utils};
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use exchanges::binance::BinanceUtils;
-use exchanges::kraken::Kraken;
-use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use serde_json::to_string;
+use exchanges::kraken::{Kraken, KrakenOrderClient};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use strats::oneleg;
 use tokio::sync::mpsc;
 use tokio::task;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::protocol::Message;
 
-use utils::balance::BalanceBuffer;
+use utils::balance::{Balance, BalanceManager};
+use utils::bars::{BarKind, BarManager, KrakenOhlc};
+use utils::fees::FeeSchedule;
+use utils::ids::VenueId;
+use utils::instrument::InstrumentRegistry;
+use utils::oms::Oms;
+use utils::portfolio::Portfolio;
+use utils::rebalance::{InventoryTarget, RebalanceMode, Rebalancer};
+use utils::reconcile::{Reconciler, TradingGate};
+use utils::stats::{MarketStats, StatsConfig};
+use utils::store::{BookRow, Record, TickStore};
 use utils::*;
 
 use crate::exchanges::binance::Binance;
-use crate::exchanges::{Client, ExchangeMessage, RestClient, WebsocketClient};
-use crate::tick::{Tick, TickBuffer};
+use crate::exchanges::feed::{self, connect_and_run, connect_and_run_private};
+use crate::exchanges::{Client, ExchangeEvent, ExchangeMessage, RestClient};
+use crate::order::AccountEvent;
+use crate::tick::TickHistory;
 use tokio::sync::Notify;
 
-//buffer const
-const BUFF_SIZE: usize = 100;
+//reconciliation against the venues' REST state
+const RECONCILE_SECS: u64 = 300;
+const RECONCILE_LOOKBACK_SECS: u64 = 24 * 60 * 60;
+const RECONCILE_REPAIR: bool = true;
+//fee schedule refresh, tiers move with 30 day volume
+const FEE_REFRESH_SECS: u64 = 60 * 60;
+const PAY_FEES_IN_BNB: bool = false;
+//equity snapshots
+const EQUITY_SNAPSHOT_SECS: u64 = 60;
+const EQUITY_SNAPSHOT_FILE: &str = "data/equity.jsonl";
+//ticks kept in memory per venue, by age rather than count
+const TICK_HISTORY_MS: u64 = 60 * 1000;
+//oneleg stands down when the fast leg has been silent this long
+const FAST_LEG_MAX_AGE_MS: u64 = 2000;
+//every tick and book update is recorded for offline analysis, arrow ipc by venue, pair and day
+const TICK_RECORD_DIR: &str = "data/store";
+const TICK_RECORD_FLUSH_SECS: u64 = 1;
+//bars built from the same ticks, kept in memory for strategies and recorded in the store
+const BAR_BUFFER_SIZE: usize = 1000;
+const BAR_VOLUME_SIZE: Decimal = dec!(100000000);
+const BAR_TICK_COUNT: usize = 100;
+//rolling ratio and volatility stats, oneleg's gap widens to ADAPTIVE_GAP_STDS standard
+//deviations of the ratio when that is above NORM_GAP. ADAPTIVE_GAP_STDS = 0.0 leaves the
+//adaptive gap compiled in but disabled, the gap stays at NORM_GAP
+const STATS_WINDOW_MS: u64 = 5 * 60 * 1000;
+const STATS_EWMA_HALF_LIFE_MS: u64 = 30 * 1000;
+//the ratio is sampled like oneleg compares it, legs at most this far apart
+const STATS_MAX_TIME_DIFF_MS: u64 = 100;
+const NORM_GAP: Decimal = dec!(0.00001);
+const ADAPTIVE_GAP_STDS: f64 = 0.0;
+//our Kraken 1m closes against Kraken's own OHLC
+const BAR_CHECK_SECS: u64 = 15 * 60;
+const BAR_CHECK_TOLERANCE: Decimal = dec!(0.005);
+//keys able to withdraw are refused at startup unless this is set
+const ALLOW_WITHDRAWAL_KEYS: bool = false;
+//inventory rebalancing, executing Kraken withdrawals needs ALLOW_WITHDRAWAL_KEYS
+const REBALANCE_SECS: u64 = 10 * 60;
+const REBALANCE_MODE: RebalanceMode = RebalanceMode::DryRun;
+const REBALANCE_WHITELIST_FILE: &str = "config/rebalance_whitelist.json";
+const REBALANCE_APPROVAL_FILE: &str = "config/rebalance_approved";
+const REBALANCE_STATE_FILE: &str = "data/rebalance.json";
 
 #[tokio::main]
 async fn main() {
     let (tx, mut rx) = mpsc::channel::<ExchangeMessage>(100);
+    let (account_tx, mut account_rx) = mpsc::channel::<AccountEvent>(100);
 
-    let tick_buffer_kraken = Arc::new(RwLock::new(TickBuffer::<BUFF_SIZE>::new(
-        "Kraken".to_string(),
+    let tick_buffer_kraken = Arc::new(RwLock::new(TickHistory::new(
+        VenueId::intern("Kraken"),
+        TICK_HISTORY_MS,
     )));
-    let tick_buffer_binance = Arc::new(RwLock::new(TickBuffer::<BUFF_SIZE>::new(
-        "Binance".to_string(),
+    let tick_buffer_binance = Arc::new(RwLock::new(TickHistory::new(
+        VenueId::intern("Binance"),
+        TICK_HISTORY_MS,
     )));
-    let balance_buffer_kraken = Arc::new(RwLock::new(BalanceBuffer::<BUFF_SIZE>::new(
-        "Kraken".to_string(),
+    let balances = Arc::new(RwLock::new(BalanceManager::new()));
+    let portfolio = Arc::new(RwLock::new(Portfolio::new()));
+    let oms = Arc::new(RwLock::new(Oms::new()));
+    let fees = Arc::new(RwLock::new(FeeSchedule::new(PAY_FEES_IN_BNB)));
+    let market_stats = Arc::new(RwLock::new(MarketStats::new(
+        StatsConfig {
+            window_ms: STATS_WINDOW_MS,
+            ewma_half_life_ms: STATS_EWMA_HALF_LIFE_MS,
+            max_time_diff_ms: STATS_MAX_TIME_DIFF_MS,
+        },
+        "Binance",
+        "Kraken",
+    )));
+    let market_stats_clone_read = market_stats.clone();
+    let bars = Arc::new(RwLock::new(BarManager::new(
+        vec![
+            BarKind::Time(1000),
+            BarKind::Time(60 * 1000),
+            BarKind::Time(5 * 60 * 1000),
+            BarKind::Volume(BAR_VOLUME_SIZE),
+            BarKind::Ticks(BAR_TICK_COUNT),
+        ],
+        BAR_BUFFER_SIZE,
     )));
 
-    //load api keys from file
-
-    let (api_key_kraken, api_sec_kraken) =
-        utils::api_key_man::read_api_credentials_from_file("src/config/kraken_api_key").unwrap();
-
-    let (api_key_binance, api_sec_binance) =
-        utils::api_key_man::read_api_credentials_from_file("src/config/binance_api_key").unwrap();
-
-    //setup clients with credentials
-    let kraken = Kraken::new(api_key_kraken, api_sec_kraken);
-
-    let binance = Binance::new(api_key_binance, api_sec_binance);
+    //load api keys, environment first, then the encrypted keystore, then the plaintext files
+    let keystore_path =
+        std::env::var("SDLA_KEYSTORE").unwrap_or_else(|_| "config/keystore.json".to_string());
+    let keystore = if std::path::Path::new(&keystore_path).exists() {
+        let passphrase = utils::api_key_man::read_passphrase("Keystore passphrase: ").unwrap();
+        Some(utils::api_key_man::Keystore::load(&keystore_path, &passphrase).unwrap())
+    } else {
+        None
+    };
+
+    let creds_kraken = utils::api_key_man::load_credentials(
+        "Kraken",
+        keystore.as_ref(),
+        "src/config/kraken_api_key",
+    )
+    .unwrap();
+
+    let creds_binance = utils::api_key_man::load_credentials(
+        "Binance",
+        keystore.as_ref(),
+        "src/config/binance_api_key",
+    )
+    .unwrap();
+    drop(keystore);
+
+    //setup clients with credentials, the clients keep the only copy
+    let kraken = Kraken::new(creds_kraken);
+    let binance = Binance::new(creds_binance);
+
+    //check what the keys can do before anything trades with them
+    let kraken_permissions = kraken.key_permissions().await.unwrap();
+    kraken_permissions.check(ALLOW_WITHDRAWAL_KEYS).unwrap();
+    binance
+        .key_permissions()
+        .await
+        .and_then(|permissions| permissions.check(ALLOW_WITHDRAWAL_KEYS))
+        .unwrap();
 
     let asset_kraken = "PEPE/USD";
     let asset_binance = "PEPE/USDT";
+    let (base_kraken, quote_kraken) = asset_kraken.split_once('/').unwrap();
 
     let notify_kraken = Arc::new(Notify::new());
     let notify_binance = Arc::new(Notify::new());
-    let balance_notify = Arc::new(Notify::new());
-    let balance_notify_clone = balance_notify.clone();
+
+    let kraken_private = kraken.clone();
+    let kraken_account_tx = account_tx.clone();
+    task::spawn(async move {
+        connect_and_run_private("Kraken", kraken_private, kraken_account_tx).await;
+    });
+
+    let binance_private = binance.clone();
+    let binance_account_tx = account_tx.clone();
+    task::spawn(async move {
+        connect_and_run_private("Binance", binance_private, binance_account_tx).await;
+    });
+
+    // Trading stays blocked until reconciliation finds local state matching the venues,
+    // an early round is asked for when a private feed reconnects with orders unaccounted for
+    let trading_gate = Arc::new(TradingGate::new("reconcile"));
+    let reconcile_now = Arc::new(Notify::new());
+
+    // Private feed consumer, order state, fills and balances arrive here
+    let trading_gate_clone_feed = trading_gate.clone();
+    let reconcile_now_clone_feed = reconcile_now.clone();
+    let balances_clone_write = balances.clone();
+    let portfolio_clone_write = portfolio.clone();
+    let oms_clone_write = oms.clone();
+    task::spawn(async move {
+        while let Some(event) = account_rx.recv().await {
+            match event {
+                AccountEvent::Fill(fill) => {
+                    println!("fill: {:?}", fill);
+                    oms_clone_write.write().await.apply_fill(&fill);
+                    if portfolio_clone_write.write().await.book_fill(&fill) {
+                        balances_clone_write.write().await.apply_fill(&fill);
+                    }
+                }
+                AccountEvent::Order(order) => {
+                    println!("order: {:?}", order);
+                    oms_clone_write.write().await.apply_update(&order);
+                }
+                AccountEvent::Balance(balance) => {
+                    balances_clone_write.write().await.set(balance);
+                }
+                AccountEvent::BalanceDelta(delta) => {
+                    balances_clone_write.write().await.apply_delta(&delta);
+                }
+                AccountEvent::OpenOrders { exchange, orders } => {
+                    let missing = oms_clone_write
+                        .write()
+                        .await
+                        .apply_open_orders_snapshot(&exchange, &orders);
+                    //closed while we were disconnected, only the order history knows how
+                    if !missing.is_empty() {
+                        eprintln!(
+                            "Orders no longer open on {} after reconnect: {:?}",
+                            exchange, missing
+                        );
+                        trading_gate_clone_feed.close();
+                        reconcile_now_clone_feed.notify_one();
+                    }
+                }
+            }
+        }
+    });
+
+    //the public feeds take their clients, everything else works on clones
+    let kraken_rest = kraken.clone();
+    let binance_rest = binance.clone();
 
     let kraken_tx = tx.clone();
     task::spawn({
@@ -94,34 +254,189 @@ async fn main() {
     notify_kraken.notified().await;
     notify_binance.notified().await;
 
-    // Clone for the first async block (e.g., periodic REST API query for Kraken)
-    let balance_buffer_kraken_clone_write = balance_buffer_kraken.clone();
-
     //clone fore writes for binance and kraken buffers
     let tick_buffer_kraken_clone_write = tick_buffer_kraken.clone();
     let tick_buffer_binance_clone_write = tick_buffer_binance.clone();
 
-    // Setup periodic REST API query for Kraken
+    // Seed balances, then reconcile against the venues at startup and on a timer
+    // Kraken announces maintenance and cancel_only in systemStatus, closed until it says online
+    let kraken_status_gate = Arc::new(TradingGate::new("Kraken system status"));
+    let kraken_status_gate_clone_read = kraken_status_gate.clone();
+    // Closed for good once oneleg leaves a position it could not exit, needs a manual close
+    let exit_gate = Arc::new(TradingGate::new("oneleg exit"));
+    exit_gate.open();
+    let reconciler = Reconciler {
+        repair: RECONCILE_REPAIR,
+        lookback_ms: RECONCILE_LOOKBACK_SECS * 1000,
+        assets_kraken: vec![asset_kraken.to_string()],
+        assets_binance: vec![asset_binance.to_string()],
+        kraken: kraken_rest.clone(),
+        binance: binance_rest.clone(),
+    };
+    let balances_clone_reconcile = balances.clone();
+    let portfolio_clone_reconcile = portfolio.clone();
+    let oms_clone_reconcile = oms.clone();
+    let trading_gate_clone_reconcile = trading_gate.clone();
+    task::spawn(async move {
+        while let Err(e) = seed_balances(
+            &reconciler.kraken,
+            &reconciler.binance,
+            &balances_clone_reconcile,
+        )
+        .await
+        {
+            eprintln!("Failed to seed balances: {}", e);
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        }
+        loop {
+            match reconciler.seed_fills(&portfolio_clone_reconcile).await {
+                Ok(count) => {
+                    println!("{} fills of the reconcile lookback already booked", count);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Failed to seed fills: {}", e);
+                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                }
+            }
+        }
+        loop {
+            match reconciler
+                .run(
+                    &oms_clone_reconcile,
+                    &portfolio_clone_reconcile,
+                    &balances_clone_reconcile,
+                )
+                .await
+            {
+                Ok(report) => {
+                    report.print();
+                    report.update_gate(&trading_gate_clone_reconcile);
+                }
+                Err(e) => {
+                    eprintln!("Failed to reconcile: {}", e);
+                    trading_gate_clone_reconcile.close();
+                }
+            }
+            // Retry soon while blocked, otherwise wait for the next round
+            let wait_secs = if trading_gate_clone_reconcile.is_open() {
+                RECONCILE_SECS
+            } else {
+                5
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(wait_secs)) => {}
+                _ = reconcile_now.notified() => {}
+            }
+        }
+    });
+
+    // Inventory rebalancing, the one-directional arb drains base on one venue
+    let rebalance_mode = if REBALANCE_MODE != RebalanceMode::DryRun && !kraken_permissions.withdraw
+    {
+        eprintln!("Kraken key cannot withdraw, rebalancing in dry run");
+        RebalanceMode::DryRun
+    } else {
+        REBALANCE_MODE
+    };
+    let mut rebalancer = Rebalancer::new(
+        rebalance_mode,
+        vec![InventoryTarget {
+            asset: base_kraken.to_string(),
+            kraken_share: dec!(0.5),
+            band: dec!(0.2),
+            min_transfer: dec!(1000000),
+        }],
+        Rebalancer::load_whitelist(REBALANCE_WHITELIST_FILE).unwrap(),
+        REBALANCE_APPROVAL_FILE,
+        REBALANCE_STATE_FILE,
+        kraken_rest.clone(),
+        binance_rest.clone(),
+    )
+    .unwrap();
+    let balances_clone_rebalance = balances.clone();
+    task::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(REBALANCE_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = rebalancer.run(&balances_clone_rebalance).await {
+                eprintln!("Failed to rebalance: {}", e);
+            }
+        }
+    });
+
+    // Cross-check of our Kraken bars against Kraken's OHLC
+    let bars_clone_check = bars.clone();
+    task::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(BAR_CHECK_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = check_bars(&bars_clone_check, asset_kraken).await {
+                eprintln!("Failed to check bars: {}", e);
+            }
+        }
+    });
+
+    // Fee tiers per venue and pair
+    let fees_clone_write = fees.clone();
+    let kraken_fees = kraken_rest.clone();
+    let binance_fees = binance_rest.clone();
     task::spawn(async move {
-        //wait 1 second interval
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(FEE_REFRESH_SECS));
         loop {
             interval.tick().await;
-            let response = Kraken::query("Balance", "").await;
-            //extract balance
-            let balance_kraken_struct = balance::Balance::extract_balance_kraken(&response, "USDT")
-                .expect("Failed to extract balance");
+            if let Err(e) = refresh_fees(
+                &kraken_fees,
+                &binance_fees,
+                &fees_clone_write,
+                asset_kraken,
+                asset_binance,
+            )
+            .await
+            {
+                eprintln!("Failed to refresh fees: {}", e);
+            }
+        }
+    });
 
-            //push to buffer
+    // Periodic equity snapshots to disk
+    let portfolio_clone_snapshot = portfolio.clone();
+    task::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(EQUITY_SNAPSHOT_SECS));
+        loop {
+            interval.tick().await;
+            let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+            if let Err(e) = portfolio_clone_snapshot
+                .read()
+                .await
+                .append_snapshot(EQUITY_SNAPSHOT_FILE, timestamp)
             {
-                let mut balance_buffer = balance_buffer_kraken_clone_write.write().await;
-                balance_buffer.add_balance(balance_kraken_struct.clone());
+                eprintln!("Failed to write equity snapshot: {}", e);
             }
+        }
+    });
 
-            // Notify that the first balance item is available
-            balance_notify.notify_one();
+    // Instrument rules are needed before any order can be sized
+    let instruments = loop {
+        match InstrumentRegistry::load(&[asset_kraken.to_string()], &[asset_binance.to_string()])
+            .await
+        {
+            Ok(instruments) => break Arc::new(instruments),
+            Err(e) => {
+                eprintln!("Failed to load instruments: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            }
+        }
+    };
 
-            // println!("Kraken balance: {:?}", balance_kraken_struct);
+    // Order entry socket for the slow leg
+    let order_client_kraken = Arc::new(KrakenOrderClient::new(kraken_rest));
+    task::spawn({
+        let order_client_kraken = order_client_kraken.clone();
+        async move {
+            order_client_kraken.run().await;
         }
     });
 
@@ -129,133 +444,243 @@ async fn main() {
     task::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(10));
 
-        balance_notify_clone.notified().await;
         loop {
             interval.tick().await;
 
-            let tick_buffer_binance_read = tick_buffer_binance.read().await;
-            let tick_buffer_kraken_read = tick_buffer_kraken.read().await;
-            let balance_buffer_kraken_read = balance_buffer_kraken.read().await;
-
-            // Using match
-            let fast_buff_back = match tick_buffer_binance_read.buffer.back() {
-                Some(back) => back,
-                None => {
-                    eprintln!(
-                        "Error: Could not get back element from tick_buffer_binance_read.buffer"
-                    );
-                    continue;
-                }
-            };
+            //oneleg trades on Kraken, so it also waits for Kraken to be online
+            if !trading_gate.is_open()
+                || !kraken_status_gate_clone_read.is_open()
+                || !exit_gate.is_open()
+            {
+                continue;
+            }
 
-            let slow_buff_back = match tick_buffer_kraken_read.buffer.back() {
-                Some(back) => back,
-                None => {
-                    eprintln!(
-                        "Error: Could not get back element from tick_buffer_kraken_read.buffer"
-                    );
+            // Copy out the legs so no lock is held while orders are in flight
+            let (fast_buff_back, slow_buff_back) = {
+                let fast = tick_buffer_binance.read().await;
+                let slow = tick_buffer_kraken.read().await;
+                let fast_back = match fast.back() {
+                    Some(back) => back.clone(),
+                    None => {
+                        eprintln!("Error: No ticks in tick_buffer_binance");
+                        continue;
+                    }
+                };
+                //a frozen fast feed would otherwise be matched against an equally old slow tick
+                if !fast.is_fresh(feed::feed_time_millis(), FAST_LEG_MAX_AGE_MS) {
                     continue;
                 }
+                //the slow leg as of the fast tick, a slow tick from after it would be lookahead
+                match slow.tick_at(fast_back.timestamp2) {
+                    Some(slow_back) => (fast_back, slow_back.clone()),
+                    None => {
+                        eprintln!("Error: No ticks in tick_buffer_kraken");
+                        continue;
+                    }
+                }
             };
 
-            let balance_buffer_back = match balance_buffer_kraken_read.buffer.back() {
-                Some(back) => back,
-                None => {
-                    eprintln!(
-                        "Error: Could not get back element from balance_buffer_kraken_read.buffer"
-                    );
-                    continue;
-                }
+            let (quote_balance, base_balance) = {
+                let balances_read = balances.read().await;
+                (
+                    balances_read.get("Kraken", quote_kraken),
+                    balances_read.get("Kraken", base_kraken),
+                )
             };
 
-            oneleg::oneleg(
-                0.01,
-                0.00001,
+            let round_trip_fee = fees.read().await.round_trip_taker("Kraken", asset_kraken);
+
+            //quiet markets keep the fixed gap, volatile ones widen it
+            let norm_gap = market_stats_clone_read
+                .read()
+                .await
+                .ratio
+                .adaptive_gap(ADAPTIVE_GAP_STDS)
+                .map_or(NORM_GAP, |gap| gap.max(NORM_GAP));
+
+            if let Err(e) = oneleg::oneleg(
+                dec!(0.01),
+                norm_gap,
                 1000,
                 100,
-                0.0026,
+                round_trip_fee,
                 &fast_buff_back,
                 &slow_buff_back,
-                &balance_buffer_back,
+                &quote_balance,
+                &base_balance,
+                &order_client_kraken,
+                &oms,
+                &instruments,
             )
-            .await;
+            .await
+            {
+                eprintln!("Unexited oneleg position: {}", e);
+                exit_gate.close();
+            }
         }
     });
 
-    // Process incoming messages
-    while let Some(message) = rx.recv().await {
-        // println!("Received message from {}: {}", message.sender, message.content);
-        //get timestamp2 in ms since epoch using chrono as u64
-        let timestamp2 =
-            chrono::Utc::now().timestamp_millis() as u64 - BinanceUtils::get_time_offset_millis();
-
-        match message.sender.as_str() {
-            "Kraken" => {
-                match Tick::deserialize_tick_kraken(&message.content, message.asset, timestamp2) {
-                    Ok(tick) => {
-                        // println!("tick: {:?}", tick.clone());
-
-                        let mut tick_buffer = tick_buffer_kraken_clone_write.write().await;
-                        tick_buffer.add_tick(tick);
+    // Tick recording and bar building, off the message loop so disk writes never delay it
+    let (record_tx, mut record_rx) = mpsc::unbounded_channel::<Record>();
+    let bars_clone_write = bars.clone();
+    task::spawn(async move {
+        let mut recorder = TickStore::new(TICK_RECORD_DIR);
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(TICK_RECORD_FLUSH_SECS));
+        loop {
+            tokio::select! {
+                record = record_rx.recv() => match record {
+                    Some(record) => {
+                        if let Record::Tick(tick) = &record {
+                            for bar in bars_clone_write.write().await.update(tick) {
+                                recorder.record(Record::Bar(bar));
+                            }
+                        }
+                        recorder.record(record);
                     }
-                    Err(e) => {
-                        // eprintln!("Failed to deserialize Kraken tick: {}\n The message content is: {}", e, &message.content);
+                    None => break,
+                },
+                _ = interval.tick() => {
+                    if let Err(e) = recorder.flush() {
+                        eprintln!("Failed to flush tick recording: {}", e);
                     }
                 }
             }
-            "Binance" => {
-                match Tick::deserialize_tick_binance(&message.content, message.asset, timestamp2) {
-                    Ok(tick) => {
-                        // println!("tick: {:?}", tick.clone());
-                        let mut tick_buffer = tick_buffer_binance_clone_write.write().await;
-                        tick_buffer.add_tick(tick);
-                    }
-                    Err(e) => {
-                        // eprintln!("Failed to deserialize Binance tick: {}\n The message content is: {}", e, &message.content);
+        }
+    });
+
+    // Process incoming messages, already parsed by the socket tasks
+    let tick_buffers = HashMap::from([
+        (VenueId::intern("Kraken"), tick_buffer_kraken_clone_write),
+        (VenueId::intern("Binance"), tick_buffer_binance_clone_write),
+    ]);
+    let status_gates = HashMap::from([(VenueId::intern("Kraken"), kraken_status_gate)]);
+    while let Some(message) = rx.recv().await {
+        match message.event {
+            ExchangeEvent::Ticker(tick) | ExchangeEvent::Trade(tick) => {
+                // println!("tick: {:?}", tick.clone());
+                portfolio.write().await.mark(&tick);
+                market_stats.write().await.update(&tick);
+                let _ = record_tx.send(Record::Tick(tick.clone()));
+                match tick_buffers.get(&message.venue) {
+                    Some(tick_buffer) => tick_buffer.write().await.add_tick(tick),
+                    None => println!("No tick buffer for {}", message.venue),
+                }
+            }
+            ExchangeEvent::BookUpdate { bids, asks } => {
+                //same clock as the ticks' timestamp2, so book rows and ticks line up
+                let timestamp2 = feed::feed_time_millis();
+                let levels = bids
+                    .iter()
+                    .map(|level| (true, level))
+                    .chain(asks.iter().map(|level| (false, level)));
+                for (bid, (price, qty)) in levels {
+                    let _ = record_tx.send(Record::Book(BookRow {
+                        timestamp2,
+                        exchange: message.venue.to_string(),
+                        asset: message.symbol.to_string(),
+                        bid,
+                        price: *price,
+                        qty: *qty,
+                    }));
+                }
+            }
+            ExchangeEvent::Heartbeat => {}
+            ExchangeEvent::SubscriptionStatus { status, error } => match error {
+                Some(error) => eprintln!(
+                    "{} {} subscription {}: {}",
+                    message.venue, message.symbol, status, error
+                ),
+                None => println!(
+                    "{} {} subscription {}",
+                    message.venue, message.symbol, status
+                ),
+            },
+            ExchangeEvent::SystemStatus { status } => {
+                println!("{} system status: {:?}", message.venue, status);
+                if let Some(gate) = status_gates.get(&message.venue) {
+                    if status.allows_trading() {
+                        gate.open();
+                    } else {
+                        gate.close();
                     }
                 }
             }
-            _ => {
-                println!("Unknown exchange: {}", message.sender);
+            ExchangeEvent::Error {
+                message: error,
+                raw,
+            } => {
+                eprintln!(
+                    "{} {} error: {}\n The message content is: {}",
+                    message.venue, message.symbol, error, raw
+                );
             }
         }
     }
 }
 
-async fn connect_and_run<T: WebsocketClient>(
-    exchange_name: &str,
-    exchange: T,
-    tx: mpsc::Sender<ExchangeMessage>,
-    asset: &str,
-    notify: Arc<Notify>,
-) {
-    let (ws_stream, _) = connect_async(exchange.ws_url())
-        .await
-        .expect("Failed to connect");
-    println!("WebSocket connected to {}", exchange_name);
-
-    // Notify that the exchange is connected
-    notify.notify_one();
+async fn check_bars(bars: &RwLock<BarManager>, asset: &str) -> anyhow::Result<()> {
+    let kind = BarKind::Time(60 * 1000);
+    let (local, late): (Vec<_>, u64) = {
+        let bars = bars.read().await;
+        match bars.bars("Kraken", asset, &kind) {
+            Some(local) => (
+                local.iter().cloned().collect(),
+                bars.late("Kraken", asset, &kind),
+            ),
+            None => return Ok(()),
+        }
+    };
+    if late > 0 {
+        eprintln!(
+            "Kraken {} 1m bars dropped {} late ticks so far",
+            asset, late
+        );
+    }
+    let since = local.first().map_or(0, |bar| bar.start / 1000);
+    let ohlc = KrakenOhlc::fetch(asset, 1, since.saturating_sub(60)).await?;
+    for mismatch in KrakenOhlc::cross_check(&local, &ohlc, BAR_CHECK_TOLERANCE) {
+        eprintln!(
+            "Kraken {} 1m bar at {} closed at {}, Kraken OHLC says {} ({} off)",
+            asset, mismatch.start, mismatch.local, mismatch.kraken, mismatch.deviation
+        );
+    }
+    Ok(())
+}
 
-    let (mut write, mut read) = ws_stream.split();
+async fn seed_balances(
+    kraken: &Kraken,
+    binance: &Binance,
+    balances: &RwLock<BalanceManager>,
+) -> anyhow::Result<()> {
+    let response = kraken.query("BalanceEx", "").await;
+    let balances_kraken = Balance::extract_balances_kraken_ex(&response)
+        .map_err(|e| anyhow::anyhow!("Kraken: {}", e))?;
+
+    let response = binance.query("account", "").await;
+    let balances_binance = Balance::extract_balances_binance(&response)
+        .map_err(|e| anyhow::anyhow!("Binance: {}", e))?;
+
+    let mut balances = balances.write().await;
+    balances.seed("Kraken", balances_kraken);
+    balances.seed("Binance", balances_binance);
+    Ok(())
+}
 
-    let subscribe_message = exchange.subscription_message(asset);
-    write
-        .send(Message::Text(subscribe_message))
-        .await
-        .expect("Failed to send subscribe message");
-
-    while let Some(message) = read.next().await {
-        if let Ok(Message::Text(text)) = message {
-            let exchange_msg = ExchangeMessage {
-                sender: exchange_name.to_string(),
-                asset: asset.to_string(),
-                content: text,
-            };
-            if tx.send(exchange_msg).await.is_err() {
-                eprintln!("Failed to send message from {}", exchange_name);
-                break;
-            }
-        }
+async fn refresh_fees(
+    kraken: &Kraken,
+    binance: &Binance,
+    fees: &RwLock<FeeSchedule>,
+    asset_kraken: &str,
+    asset_binance: &str,
+) -> anyhow::Result<()> {
+    let tiers_kraken = FeeSchedule::fetch_kraken(kraken, &[asset_kraken.to_string()]).await?;
+    let tiers_binance = FeeSchedule::fetch_binance(binance, &[asset_binance.to_string()]).await?;
+
+    let mut fees = fees.write().await;
+    for tier in tiers_kraken.into_iter().chain(tiers_binance) {
+        fees.set(tier);
     }
+    fees.updated = chrono::Utc::now().timestamp_millis() as u64;
+    Ok(())
 }
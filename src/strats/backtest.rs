@@ -0,0 +1,268 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Serialize;
+
+use crate::{order::Side, tick::Tick};
+
+const YEAR_MS: f64 = 365.0 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+//the four knobs main.rs passes to oneleg
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OnelegParams {
+    pub norm_trade_size: Decimal,
+    pub norm_gap: Decimal,
+    pub time_gap_ms: u64,
+    pub max_time_diff_ms: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestConfig {
+    pub round_trip_fee: Decimal,
+    pub initial_quote: Decimal,
+    pub initial_base: Decimal,
+    //pnl is bucketed at this interval for the sharpe ratio
+    pub sharpe_bucket_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Leg {
+    Fast,
+    Slow,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    timestamp: u64,
+    leg: Leg,
+    price: Decimal,
+}
+
+//both legs merged on timestamp2 once, so many backtests can replay the same data
+#[derive(Debug, Clone, Default)]
+pub struct MarketData {
+    events: Vec<Event>,
+    //slow leg alone, for exit prices
+    slow: Vec<(u64, Decimal)>,
+}
+
+impl MarketData {
+    pub fn new(fast: &[Tick], slow: &[Tick]) -> MarketData {
+        let mut events: Vec<Event> = fast
+            .iter()
+            .map(|tick| Event {
+                timestamp: tick.timestamp2,
+                leg: Leg::Fast,
+                price: tick.avg,
+            })
+            .chain(slow.iter().map(|tick| Event {
+                timestamp: tick.timestamp2,
+                leg: Leg::Slow,
+                price: tick.avg,
+            }))
+            .collect();
+        events.sort_by_key(|event| event.timestamp);
+        MarketData::from_events(events)
+    }
+
+    fn from_events(events: Vec<Event>) -> MarketData {
+        let slow = events
+            .iter()
+            .filter(|event| event.leg == Leg::Slow)
+            .map(|event| (event.timestamp, event.price))
+            .collect();
+        MarketData { events, slow }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn start(&self) -> u64 {
+        self.events.first().map_or(0, |event| event.timestamp)
+    }
+
+    pub fn end(&self) -> u64 {
+        self.events.last().map_or(0, |event| event.timestamp)
+    }
+
+    //events in [from, to)
+    pub fn window(&self, from: u64, to: u64) -> MarketData {
+        let first = self.events.partition_point(|event| event.timestamp < from);
+        let last = self.events.partition_point(|event| event.timestamp < to);
+        MarketData::from_events(self.events[first..last].to_vec())
+    }
+
+    fn slow_price_as_of(&self, timestamp: u64) -> Option<Decimal> {
+        let i = self.slow.partition_point(|(t, _)| *t <= timestamp);
+        if i == 0 {
+            None
+        } else {
+            Some(self.slow[i - 1].1)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestTrade {
+    pub entry_time: u64,
+    pub exit_time: u64,
+    pub side: Side,
+    pub qty: Decimal,
+    pub entry_price: Decimal,
+    pub exit_price: Decimal,
+    pub fee: Decimal,
+    pub pnl: Decimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BacktestResult {
+    pub pnl: Decimal,
+    pub fees: Decimal,
+    pub trades: usize,
+    pub wins: usize,
+    pub sharpe: f64,
+    //largest peak to trough fall of cumulative pnl, as a fraction of starting capital
+    pub max_drawdown: f64,
+    #[serde(skip)]
+    pub trade_log: Vec<BacktestTrade>,
+}
+
+impl BacktestResult {
+    //several windows of the same parameters: pnl and trades add up, sharpe is averaged,
+    //drawdown is the worst one
+    pub fn combine(results: &[BacktestResult]) -> BacktestResult {
+        if results.is_empty() {
+            return BacktestResult::default();
+        }
+        BacktestResult {
+            pnl: results.iter().map(|r| r.pnl).sum(),
+            fees: results.iter().map(|r| r.fees).sum(),
+            trades: results.iter().map(|r| r.trades).sum(),
+            wins: results.iter().map(|r| r.wins).sum(),
+            sharpe: results.iter().map(|r| r.sharpe).sum::<f64>() / results.len() as f64,
+            max_drawdown: results.iter().map(|r| r.max_drawdown).fold(0.0, f64::max),
+            trade_log: Vec::new(),
+        }
+    }
+}
+
+//replays oneleg: one round trip at a time, market entry on the slow leg when the
+//fast/slow ratio is past gap + fee, market exit time_gap_ms later, fills at the
+//slow leg's last price
+pub fn run(data: &MarketData, params: &OnelegParams, config: &BacktestConfig) -> BacktestResult {
+    let mut quote = config.initial_quote;
+    let base = config.initial_base;
+    let mut fast: Option<(u64, Decimal)> = None;
+    let mut slow: Option<(u64, Decimal)> = None;
+    let mut busy_until = 0u64;
+    let mut trades = Vec::new();
+    let threshold = params.norm_gap + config.round_trip_fee;
+    let (upper, lower) = (Decimal::ONE + threshold, Decimal::ONE - threshold);
+
+    for event in &data.events {
+        match event.leg {
+            Leg::Fast => fast = Some((event.timestamp, event.price)),
+            Leg::Slow => slow = Some((event.timestamp, event.price)),
+        }
+        if event.timestamp < busy_until {
+            continue;
+        }
+        let ((fast_time, fast_price), (slow_time, slow_price)) = match (fast, slow) {
+            (Some(fast), Some(slow)) => (fast, slow),
+            _ => continue,
+        };
+        if fast_time.abs_diff(slow_time) >= params.max_time_diff_ms || slow_price <= Decimal::ZERO {
+            continue;
+        }
+
+        //fast / slow against 1 +- threshold, multiplied out to keep division off the hot path
+        let (side, qty) = if fast_price > slow_price * upper {
+            (Side::Buy, params.norm_trade_size * quote / slow_price)
+        } else if fast_price < slow_price * lower {
+            (Side::Sell, params.norm_trade_size * base)
+        } else {
+            continue;
+        };
+        if qty <= Decimal::ZERO {
+            continue;
+        }
+
+        let exit_time = event.timestamp + params.time_gap_ms;
+        let exit_price = data.slow_price_as_of(exit_time).unwrap_or(slow_price);
+        let fee = (qty * slow_price + qty * exit_price) * config.round_trip_fee / Decimal::TWO;
+        let pnl = match side {
+            Side::Buy => (exit_price - slow_price) * qty,
+            Side::Sell => (slow_price - exit_price) * qty,
+        } - fee;
+        //base ends where it started, the round trip only moves quote
+        quote += pnl;
+        busy_until = exit_time;
+        trades.push(BacktestTrade {
+            entry_time: event.timestamp,
+            exit_time,
+            side,
+            qty,
+            entry_price: slow_price,
+            exit_price,
+            fee,
+            pnl,
+        });
+    }
+
+    let capital = config.initial_quote
+        + config.initial_base * data.slow.first().map_or(Decimal::ZERO, |(_, p)| *p);
+    let capital = capital.to_f64().unwrap_or(0.0);
+    let (sharpe, max_drawdown) = pnl_stats(
+        &trades,
+        capital,
+        data.start(),
+        data.end(),
+        config.sharpe_bucket_ms,
+    );
+
+    BacktestResult {
+        pnl: trades.iter().map(|t| t.pnl).sum(),
+        fees: trades.iter().map(|t| t.fee).sum(),
+        trades: trades.len(),
+        wins: trades.iter().filter(|t| t.pnl > Decimal::ZERO).count(),
+        sharpe,
+        max_drawdown,
+        trade_log: trades,
+    }
+}
+
+//annualized sharpe of bucketed pnl returns on capital, and max drawdown of cumulative pnl
+fn pnl_stats(
+    trades: &[BacktestTrade],
+    capital: f64,
+    start: u64,
+    end: u64,
+    bucket_ms: u64,
+) -> (f64, f64) {
+    if capital <= 0.0 || bucket_ms == 0 || end <= start {
+        return (0.0, 0.0);
+    }
+
+    let buckets = ((end - start) / bucket_ms + 1) as usize;
+    let mut returns = vec![0.0; buckets];
+    let mut cumulative = 0.0;
+    let mut peak = 0.0;
+    let mut max_drawdown = 0.0f64;
+    for trade in trades {
+        let pnl = trade.pnl.to_f64().unwrap_or(0.0);
+        let bucket = ((trade.exit_time.saturating_sub(start)) / bucket_ms) as usize;
+        returns[bucket.min(buckets - 1)] += pnl / capital;
+        cumulative += pnl;
+        peak = f64::max(peak, cumulative);
+        max_drawdown = max_drawdown.max((peak - cumulative) / capital);
+    }
+
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let std = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let sharpe = if std > 0.0 {
+        mean / std * (YEAR_MS / bucket_ms as f64).sqrt()
+    } else {
+        0.0
+    };
+    (sharpe, max_drawdown)
+}
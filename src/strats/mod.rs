@@ -1 +1,3 @@
+pub mod backtest;
 pub mod oneleg;
+pub mod optimize;
@@ -1,73 +1,208 @@
-use core::time;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
 
 use crate::{
-    balance::{Balance, BalanceBuffer},
-    tick::{Tick, TickBuffer},
-    BUFF_SIZE,
+    balance::Balance,
+    exchanges::kraken::KrakenOrderClient,
+    instrument::InstrumentRegistry,
+    oms::Oms,
+    order::{OrderRequest, OrderType, Side},
+    tick::Tick,
 };
 
+//name orders are tracked under in the oms
+pub const STRATEGY: &str = "oneleg";
+//an exit the venue refuses is retried this often before oneleg gives up on it
+pub const EXIT_ATTEMPTS: u32 = 3;
+pub const EXIT_RETRY_MS: u64 = 500;
+
 //gap arg from 0 to 1
-//fee_slow_buff arg from 0 to 1
+//round_trip_fee arg from 0 to 1, entry plus exit fees on the slow venue
 //trade_size arg from 0 to 1
-use std::sync::Arc;
+//an error means an entry was left without its exit, trading has to stop until it is closed
+use anyhow::anyhow;
 use tokio::sync::RwLock;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn oneleg(
-    norm_trade_size: f64,
-    norm_gap: f64,
+    norm_trade_size: Decimal,
+    norm_gap: Decimal,
     time_gap_ms: usize,
     max_time_diff_ms: usize,
-    fee_slow_buff: f64,
+    round_trip_fee: Decimal,
     fast_buff_back: &Tick,
     slow_buff_back: &Tick,
-    balance_buff_back: &Balance,
-) {
+    quote_balance: &Balance,
+    base_balance: &Balance,
+    order_client: &KrakenOrderClient,
+    oms: &RwLock<Oms>,
+    instruments: &InstrumentRegistry,
+) -> anyhow::Result<()> {
+    //one round trip at a time
+    if !oms.read().await.open_orders(STRATEGY).is_empty() {
+        return Ok(());
+    }
+
+    //the ratio and the order size both divide by the slow price
+    if slow_buff_back.avg <= Decimal::ZERO {
+        return Ok(());
+    }
+
     //get time difference between the two exchanges
 
     let time_diff_ms = fast_buff_back.timestamp2 as i64 - slow_buff_back.timestamp2 as i64;
     let time_diff_ms = time_diff_ms.abs();
     let time_diff_ms = time_diff_ms as usize;
 
-    if (time_diff_ms < max_time_diff_ms) {
+    if time_diff_ms < max_time_diff_ms {
         // println!("time diff good: {}", time_diff_ms);
 
         let price_diff = fast_buff_back.avg - slow_buff_back.avg;
 
         //get ratio of the exchanges' price
-        let ratio: f64 = fast_buff_back.avg / slow_buff_back.avg;
-
-        //test
-        if (ratio > 1.0) {
-            println!("ratio: {}", ratio - 1.0);
-        } else {
-            println!("ratio: {}", 1.0 - ratio);
-        }
+        let ratio: Decimal = fast_buff_back.avg / slow_buff_back.avg;
 
         // if difference is greater than gap + fee, then trade
-        if (ratio > 1. + norm_gap + fee_slow_buff || ratio < 1. - norm_gap - fee_slow_buff) {
+        if ratio > Decimal::ONE + norm_gap + round_trip_fee
+            || ratio < Decimal::ONE - norm_gap - round_trip_fee
+        {
             println!("ratio pass");
 
-            let denorm_trade_size = norm_trade_size * balance_buff_back.amount;
-
-            //if difference is positive, then bull slow
-            if price_diff > 0.0 {
-                //buy
+            //if difference is positive, then bull slow, buying is sized off the quote we hold
+            let (entry_side, exit_side, qty) = if price_diff > Decimal::ZERO {
+                let denorm_trade_size = norm_trade_size * quote_balance.free;
                 println!("buying for {}", denorm_trade_size);
-
-                //wait time_gap_ms
-
-                //sell max amount of the bought asset
+                (
+                    Side::Buy,
+                    Side::Sell,
+                    denorm_trade_size / slow_buff_back.avg,
+                )
             }
-            //if difference is negative, then bear slow
+            //if difference is negative, then bear slow, selling is sized off the base we hold
             else {
-                //sell
-
-                println!("selling for {}", denorm_trade_size);
+                let qty = norm_trade_size * base_balance.free;
+                println!("selling for {}", qty * slow_buff_back.avg);
+                (Side::Sell, Side::Buy, qty)
+            };
+
+            if qty <= Decimal::ZERO {
+                eprintln!(
+                    "No balance to trade {:?} on {}",
+                    entry_side, slow_buff_back.exchange
+                );
+                return Ok(());
+            }
 
-                //wait time_gap_ms
+            let entry = market_order(slow_buff_back.asset.name(), entry_side, qty);
+            let entry_id =
+                match place_order(order_client, oms, instruments, slow_buff_back, entry).await {
+                    Ok(entry_id) => entry_id,
+                    Err(e) => {
+                        eprintln!("Entry not placed: {}", e);
+                        return Ok(());
+                    }
+                };
+
+            //wait time_gap_ms
+            tokio::time::sleep(Duration::from_millis(time_gap_ms as u64)).await;
+
+            //whatever part of the entry is still working gets cancelled
+            let (entry_open, entry_order_id) = match oms.read().await.get(&entry_id) {
+                Some(order) => (order.is_open(), order.order_id.clone()),
+                None => return Ok(()),
+            };
+            if let Some(entry_order_id) = &entry_order_id {
+                if entry_open {
+                    if let Err(e) = order_client.cancel_order(entry_order_id).await {
+                        eprintln!("Failed to cancel entry order: {}", e);
+                    }
+                }
+                //the private stream can lag the venue, its fills decide nothing alone
+                match order_client.query_order(entry_order_id).await {
+                    Ok(update) => oms.write().await.apply_update(&update),
+                    Err(e) => eprintln!("Failed to confirm entry fill over REST: {}", e),
+                }
+            }
+            let filled_qty = match oms.read().await.get(&entry_id) {
+                Some(order) => order.filled_qty,
+                None => return Ok(()),
+            };
+            if filled_qty <= Decimal::ZERO {
+                eprintln!("Entry order {} did not fill, no exit needed", entry_id);
+                return Ok(());
+            }
 
-                //buy max amount of the sold asset
+            //close out the amount that actually filled on entry
+            let mut attempt = 1;
+            loop {
+                let exit = market_order(slow_buff_back.asset.name(), exit_side, filled_qty);
+                match place_order(order_client, oms, instruments, slow_buff_back, exit).await {
+                    Ok(_) => break,
+                    Err(e) if attempt < EXIT_ATTEMPTS => {
+                        eprintln!("Exit attempt {} failed: {}", attempt, e);
+                        attempt += 1;
+                        tokio::time::sleep(Duration::from_millis(EXIT_RETRY_MS)).await;
+                    }
+                    Err(e) => {
+                        return Err(anyhow!(
+                            "{:?} {} {} left open after entry {}: {}",
+                            entry_side,
+                            filled_qty,
+                            slow_buff_back.asset,
+                            entry_id,
+                            e
+                        ))
+                    }
+                }
             }
         }
     }
+    Ok(())
+}
+
+//returns the client order id if the venue accepted the order,
+//orders that break the venue's size rules never leave
+async fn place_order(
+    order_client: &KrakenOrderClient,
+    oms: &RwLock<Oms>,
+    instruments: &InstrumentRegistry,
+    tick: &Tick,
+    order: OrderRequest,
+) -> anyhow::Result<String> {
+    let order = instruments
+        .normalize(tick.exchange.name(), &order, tick.avg)
+        .map_err(|e| anyhow!("Invalid {:?} order: {}", order.side, e))?;
+    let order = oms
+        .write()
+        .await
+        .create(STRATEGY, tick.exchange.name(), order);
+    let client_order_id = order
+        .client_order_id
+        .clone()
+        .ok_or_else(|| anyhow!("Order created without a client order id"))?;
+    match order_client.add_order(&order).await {
+        Ok(txid) => {
+            println!("{:?} order placed: {}", order.side, txid);
+            oms.write().await.on_ack(&client_order_id, &txid);
+            Ok(client_order_id)
+        }
+        Err(e) => {
+            oms.write()
+                .await
+                .on_reject(&client_order_id, &e.to_string());
+            Err(anyhow!("Failed to place {:?} order: {}", order.side, e))
+        }
+    }
+}
+
+fn market_order(asset: &str, side: Side, qty: Decimal) -> OrderRequest {
+    OrderRequest {
+        asset: asset.to_string(),
+        side,
+        order_type: OrderType::Market,
+        qty,
+        price: None,
+        client_order_id: None,
+    }
 }
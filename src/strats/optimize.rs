@@ -0,0 +1,272 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::backtest::{self, BacktestConfig, BacktestResult, MarketData, OnelegParams};
+
+//(train, test) results of one candidate, one entry per split
+type SplitResults = Vec<(BacktestResult, BacktestResult)>;
+
+//every combination of these is tried
+#[derive(Debug, Clone)]
+pub struct ParamGrid {
+    pub norm_trade_size: Vec<Decimal>,
+    pub norm_gap: Vec<Decimal>,
+    pub time_gap_ms: Vec<u64>,
+    pub max_time_diff_ms: Vec<u64>,
+}
+
+impl ParamGrid {
+    pub fn combinations(&self) -> Vec<OnelegParams> {
+        let mut params = Vec::new();
+        for &norm_trade_size in &self.norm_trade_size {
+            for &norm_gap in &self.norm_gap {
+                for &time_gap_ms in &self.time_gap_ms {
+                    for &max_time_diff_ms in &self.max_time_diff_ms {
+                        params.push(OnelegParams {
+                            norm_trade_size,
+                            norm_gap,
+                            time_gap_ms,
+                            max_time_diff_ms,
+                        });
+                    }
+                }
+            }
+        }
+        params
+    }
+}
+
+//inclusive bounds for random search
+#[derive(Debug, Clone)]
+pub struct ParamRanges {
+    pub norm_trade_size: (Decimal, Decimal),
+    pub norm_gap: (Decimal, Decimal),
+    pub time_gap_ms: (u64, u64),
+    pub max_time_diff_ms: (u64, u64),
+}
+
+impl ParamRanges {
+    //same seed, same candidates
+    pub fn sample(&self, count: usize, seed: u64) -> Vec<OnelegParams> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| OnelegParams {
+                norm_trade_size: random_decimal(&mut rng, self.norm_trade_size),
+                norm_gap: random_decimal(&mut rng, self.norm_gap),
+                time_gap_ms: rng.gen_range(self.time_gap_ms.0..=self.time_gap_ms.1),
+                max_time_diff_ms: rng.gen_range(self.max_time_diff_ms.0..=self.max_time_diff_ms.1),
+            })
+            .collect()
+    }
+}
+
+fn random_decimal(rng: &mut StdRng, (min, max): (Decimal, Decimal)) -> Decimal {
+    let t = Decimal::try_from(rng.gen::<f64>()).unwrap_or_default();
+    (min + (max - min) * t).round_dp(8)
+}
+
+//train on one slice of time, test on the next one
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Split {
+    pub train_from: u64,
+    pub train_to: u64,
+    pub test_from: u64,
+    pub test_to: u64,
+}
+
+//rolling walk-forward: the range is cut into splits + 1 equal slices, split i trains on
+//slice i and tests on slice i + 1. zero splits tests everything in sample
+pub fn walk_forward_splits(data: &MarketData, splits: usize) -> Vec<Split> {
+    let (start, end) = (data.start(), data.end() + 1);
+    if splits == 0 {
+        return vec![Split {
+            train_from: start,
+            train_to: end,
+            test_from: start,
+            test_to: end,
+        }];
+    }
+    let slice = (end - start) / (splits as u64 + 1);
+    (0..splits as u64)
+        .map(|i| Split {
+            train_from: start + i * slice,
+            train_to: start + (i + 1) * slice,
+            test_from: start + (i + 1) * slice,
+            test_to: if i + 1 == splits as u64 {
+                end
+            } else {
+                start + (i + 2) * slice
+            },
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    pub params: OnelegParams,
+    pub train: BacktestResult,
+    pub test: BacktestResult,
+}
+
+//per split, the parameters that did best in training and how they did out of sample
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForwardStep {
+    pub split: Split,
+    pub params: OnelegParams,
+    pub train: BacktestResult,
+    pub test: BacktestResult,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepReport {
+    pub rows: Vec<SweepRow>,
+    pub walk_forward: Vec<WalkForwardStep>,
+}
+
+//best first: higher sharpe, then higher pnl
+fn by_score(a: &BacktestResult, b: &BacktestResult) -> std::cmp::Ordering {
+    b.sharpe.total_cmp(&a.sharpe).then(b.pnl.cmp(&a.pnl))
+}
+
+//every candidate is backtested on every split in parallel, rows are ranked by
+//out of sample sharpe, then pnl
+pub fn sweep(
+    data: &MarketData,
+    candidates: &[OnelegParams],
+    config: &BacktestConfig,
+    splits: usize,
+    threads: usize,
+) -> Result<SweepReport> {
+    if data.is_empty() {
+        return Err(anyhow!("No market data to sweep over"));
+    }
+    if candidates.is_empty() {
+        return Err(anyhow!("No parameter sets to try"));
+    }
+    let splits = walk_forward_splits(data, splits);
+    let windows: Vec<(MarketData, MarketData)> = splits
+        .iter()
+        .map(|s| {
+            (
+                data.window(s.train_from, s.train_to),
+                data.window(s.test_from, s.test_to),
+            )
+        })
+        .collect();
+
+    //results[candidate][split] = (train, test)
+    let results: Mutex<Vec<Option<SplitResults>>> = Mutex::new(vec![None; candidates.len()]);
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= candidates.len() {
+                    break;
+                }
+                let per_split = windows
+                    .iter()
+                    .map(|(train, test)| {
+                        let mut train = backtest::run(train, &candidates[i], config);
+                        let mut test = backtest::run(test, &candidates[i], config);
+                        train.trade_log.clear();
+                        test.trade_log.clear();
+                        (train, test)
+                    })
+                    .collect();
+                results.lock().unwrap()[i] = Some(per_split);
+            });
+        }
+    });
+    let results: Vec<SplitResults> = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.unwrap_or_default())
+        .collect();
+
+    let walk_forward = splits
+        .iter()
+        .enumerate()
+        .filter_map(|(s, split)| {
+            let best = (0..candidates.len())
+                .min_by(|&a, &b| by_score(&results[a][s].0, &results[b][s].0))?;
+            Some(WalkForwardStep {
+                split: *split,
+                params: candidates[best],
+                train: results[best][s].0.clone(),
+                test: results[best][s].1.clone(),
+            })
+        })
+        .collect();
+
+    let mut rows: Vec<SweepRow> = candidates
+        .iter()
+        .zip(results)
+        .map(|(params, per_split)| {
+            let (train, test): (Vec<_>, Vec<_>) = per_split.into_iter().unzip();
+            SweepRow {
+                params: *params,
+                train: BacktestResult::combine(&train),
+                test: BacktestResult::combine(&test),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| by_score(&a.test, &b.test));
+
+    Ok(SweepReport { rows, walk_forward })
+}
+
+impl SweepReport {
+    //ranked table, one parameter set per line
+    pub fn save_csv(&self, file_path: &str) -> Result<()> {
+        if let Some(dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::create(file_path)?;
+        writeln!(
+            file,
+            "rank,norm_trade_size,norm_gap,time_gap_ms,max_time_diff_ms,\
+test_pnl,test_sharpe,test_max_drawdown,test_trades,\
+train_pnl,train_sharpe,train_max_drawdown,train_trades"
+        )?;
+        for (rank, row) in self.rows.iter().enumerate() {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                rank + 1,
+                row.params.norm_trade_size,
+                row.params.norm_gap,
+                row.params.time_gap_ms,
+                row.params.max_time_diff_ms,
+                row.test.pnl.round_dp(8),
+                row.test.sharpe,
+                row.test.max_drawdown,
+                row.test.trades,
+                row.train.pnl.round_dp(8),
+                row.train.sharpe,
+                row.train.max_drawdown,
+                row.train.trades
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn save_json(&self, file_path: &str) -> Result<()> {
+        if let Some(dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(file_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+
+use crate::tick::Tick;
+
+//everything is aligned on timestamp2, the local receive time, kraken spread frames
+//carry no exchange timestamp and the strategy itself compares receive times
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisParams {
+    //spacing of the resampled price grid
+    pub grid_ms: u64,
+    pub max_lag_ms: u64,
+    //norm_gap + round trip fee, as a ratio like in oneleg
+    pub gap_threshold: f64,
+    pub time_gap_ms: u64,
+    //smallest relative Binance tick to tick change counted as a move
+    pub min_move: f64,
+}
+
+//spread is binance / kraken - 1, positive when Binance is above
+#[derive(Debug, Clone, Serialize)]
+pub struct SpreadPoint {
+    pub timestamp: u64,
+    pub kraken: f64,
+    pub binance: f64,
+    pub spread: f64,
+}
+
+//positive lag: Binance returns against Kraken returns lag_ms later, i.e. Binance leading
+#[derive(Debug, Clone, Serialize)]
+pub struct LagCorrelation {
+    pub lag_ms: i64,
+    pub correlation: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct GapStats {
+    pub threshold: f64,
+    pub count: usize,
+    pub total_ms: u64,
+    pub mean_ms: f64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+    #[serde(skip)]
+    pub durations_ms: Vec<u64>,
+}
+
+//a Binance move is followed when Kraken trades away from its price at the time of the
+//move in the same direction within time_gap_ms
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct FollowStats {
+    pub binance_moves: usize,
+    pub followed: usize,
+    pub opposite: usize,
+    pub no_kraken_move: usize,
+    pub follow_rate: f64,
+    pub mean_delay_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeadLagReport {
+    pub params: AnalysisParams,
+    pub kraken_ticks: usize,
+    pub binance_ticks: usize,
+    pub start: u64,
+    pub end: u64,
+    pub spread_mean: f64,
+    pub spread_std: f64,
+    pub best_lag_ms: i64,
+    pub correlations: Vec<LagCorrelation>,
+    pub gaps: GapStats,
+    pub follow: FollowStats,
+    #[serde(skip)]
+    pub spread: Vec<SpreadPoint>,
+}
+
+fn price(tick: &Tick) -> f64 {
+    tick.avg.to_f64().unwrap_or(f64::NAN)
+}
+
+fn sorted(ticks: &[Tick]) -> Vec<Tick> {
+    let mut ticks = ticks.to_vec();
+    ticks.sort_by_key(|tick| tick.timestamp2);
+    ticks
+}
+
+//last price at or before timestamp, ticks sorted by timestamp2
+fn price_as_of(ticks: &[Tick], timestamp: u64) -> Option<f64> {
+    let i = ticks.partition_point(|tick| tick.timestamp2 <= timestamp);
+    if i == 0 {
+        None
+    } else {
+        Some(price(&ticks[i - 1]))
+    }
+}
+
+fn mean_std(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, var.sqrt())
+}
+
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let (mean_a, std_a) = mean_std(a);
+    let (mean_b, std_b) = mean_std(b);
+    if std_a == 0.0 || std_b == 0.0 || a.is_empty() {
+        return 0.0;
+    }
+    let cov = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (x - mean_a) * (y - mean_b))
+        .sum::<f64>()
+        / a.len() as f64;
+    cov / (std_a * std_b)
+}
+
+//nearest rank on sorted values
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+//both venues resampled on the grid where both have a price
+pub fn spread_series(kraken: &[Tick], binance: &[Tick], grid_ms: u64) -> Vec<SpreadPoint> {
+    let kraken = sorted(kraken);
+    let binance = sorted(binance);
+    let (start, end) = match (
+        kraken.first().zip(binance.first()),
+        kraken.last().zip(binance.last()),
+    ) {
+        (Some((k, b)), Some((k_last, b_last))) => (
+            k.timestamp2.max(b.timestamp2),
+            k_last.timestamp2.min(b_last.timestamp2),
+        ),
+        _ => return Vec::new(),
+    };
+
+    let mut series = Vec::new();
+    let mut timestamp = start;
+    while timestamp <= end && grid_ms > 0 {
+        if let (Some(k), Some(b)) = (
+            price_as_of(&kraken, timestamp),
+            price_as_of(&binance, timestamp),
+        ) {
+            if k > 0.0 {
+                series.push(SpreadPoint {
+                    timestamp,
+                    kraken: k,
+                    binance: b,
+                    spread: b / k - 1.0,
+                });
+            }
+        }
+        timestamp += grid_ms;
+    }
+    series
+}
+
+//correlation of per step log returns, from -max_lag_ms to +max_lag_ms in grid steps
+pub fn cross_correlation(
+    series: &[SpreadPoint],
+    grid_ms: u64,
+    max_lag_ms: u64,
+) -> Vec<LagCorrelation> {
+    let returns = |f: fn(&SpreadPoint) -> f64| -> Vec<f64> {
+        series
+            .windows(2)
+            .map(|w| (f(&w[1]) / f(&w[0])).ln())
+            .collect()
+    };
+    let kraken = returns(|p| p.kraken);
+    let binance = returns(|p| p.binance);
+    let max_steps = (max_lag_ms / grid_ms.max(1)) as i64;
+
+    (-max_steps..=max_steps)
+        .map(|steps| {
+            let shift = steps.unsigned_abs() as usize;
+            let correlation = if shift >= kraken.len() {
+                0.0
+            } else if steps >= 0 {
+                correlation(&binance[..binance.len() - shift], &kraken[shift..])
+            } else {
+                correlation(&binance[shift..], &kraken[..kraken.len() - shift])
+            };
+            LagCorrelation {
+                lag_ms: steps * grid_ms as i64,
+                correlation,
+            }
+        })
+        .collect()
+}
+
+//how long |spread| stays above the threshold each time it gets there
+pub fn gap_stats(series: &[SpreadPoint], threshold: f64, grid_ms: u64) -> GapStats {
+    let mut durations = Vec::new();
+    let mut run = 0u64;
+    for point in series {
+        if point.spread.abs() > threshold {
+            run += 1;
+        } else if run > 0 {
+            durations.push(run * grid_ms);
+            run = 0;
+        }
+    }
+    if run > 0 {
+        durations.push(run * grid_ms);
+    }
+
+    let mut sorted = durations.clone();
+    sorted.sort_unstable();
+    let total_ms = sorted.iter().sum::<u64>();
+    GapStats {
+        threshold,
+        count: sorted.len(),
+        total_ms,
+        mean_ms: if sorted.is_empty() {
+            0.0
+        } else {
+            total_ms as f64 / sorted.len() as f64
+        },
+        p50_ms: percentile(&sorted, 0.5),
+        p90_ms: percentile(&sorted, 0.9),
+        p99_ms: percentile(&sorted, 0.99),
+        max_ms: sorted.last().copied().unwrap_or(0),
+        durations_ms: durations,
+    }
+}
+
+pub fn follow_stats(
+    kraken: &[Tick],
+    binance: &[Tick],
+    min_move: f64,
+    time_gap_ms: u64,
+) -> FollowStats {
+    let kraken = sorted(kraken);
+    let binance = sorted(binance);
+    let mut stats = FollowStats::default();
+    let mut total_delay = 0u64;
+
+    for w in binance.windows(2) {
+        let (prev, tick) = (price(&w[0]), price(&w[1]));
+        if prev <= 0.0 || ((tick - prev) / prev).abs() < min_move {
+            continue;
+        }
+        let timestamp = w[1].timestamp2;
+        let base = match price_as_of(&kraken, timestamp) {
+            Some(base) => base,
+            None => continue,
+        };
+        stats.binance_moves += 1;
+
+        //the first Kraken tick that moved away from base in the same direction, otherwise
+        //whether Kraken ended the window moved against the Binance move
+        let direction = (tick - prev).signum();
+        let first = kraken.partition_point(|k| k.timestamp2 <= timestamp);
+        let window = kraken[first..]
+            .iter()
+            .take_while(|k| k.timestamp2 <= timestamp + time_gap_ms);
+        let mut moved_against = false;
+        let mut follow = None;
+        for k in window {
+            let change = price(k) - base;
+            if change != 0.0 && change.signum() == direction {
+                follow = Some(k.timestamp2 - timestamp);
+                break;
+            }
+            moved_against = change != 0.0;
+        }
+        match follow {
+            Some(delay) => {
+                stats.followed += 1;
+                total_delay += delay;
+            }
+            None if moved_against => stats.opposite += 1,
+            None => stats.no_kraken_move += 1,
+        }
+    }
+
+    if stats.binance_moves > 0 {
+        stats.follow_rate = stats.followed as f64 / stats.binance_moves as f64;
+    }
+    if stats.followed > 0 {
+        stats.mean_delay_ms = total_delay as f64 / stats.followed as f64;
+    }
+    stats
+}
+
+impl LeadLagReport {
+    pub fn build(
+        kraken: &[Tick],
+        binance: &[Tick],
+        params: AnalysisParams,
+    ) -> Result<LeadLagReport> {
+        if params.grid_ms == 0 {
+            return Err(anyhow!("grid_ms must be positive"));
+        }
+        let spread = spread_series(kraken, binance, params.grid_ms);
+        if spread.len() < 2 {
+            return Err(anyhow!("Not enough overlapping ticks to analyse"));
+        }
+        let spreads: Vec<f64> = spread.iter().map(|p| p.spread).collect();
+        let (spread_mean, spread_std) = mean_std(&spreads);
+        let correlations = cross_correlation(&spread, params.grid_ms, params.max_lag_ms);
+        let best_lag_ms = correlations
+            .iter()
+            .max_by(|a, b| a.correlation.total_cmp(&b.correlation))
+            .map_or(0, |c| c.lag_ms);
+
+        Ok(LeadLagReport {
+            kraken_ticks: kraken.len(),
+            binance_ticks: binance.len(),
+            start: spread[0].timestamp,
+            end: spread[spread.len() - 1].timestamp,
+            spread_mean,
+            spread_std,
+            best_lag_ms,
+            correlations,
+            gaps: gap_stats(&spread, params.gap_threshold, params.grid_ms),
+            follow: follow_stats(kraken, binance, params.min_move, params.time_gap_ms),
+            spread,
+            params,
+        })
+    }
+
+    //report.json plus spread.csv, correlation.csv and gaps.csv in dir
+    pub fn save(&self, dir: &str) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(
+            Path::new(dir).join("report.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+
+        let mut file = fs::File::create(Path::new(dir).join("spread.csv"))?;
+        writeln!(file, "timestamp,kraken,binance,spread")?;
+        for point in &self.spread {
+            writeln!(
+                file,
+                "{},{},{},{}",
+                point.timestamp, point.kraken, point.binance, point.spread
+            )?;
+        }
+
+        let mut file = fs::File::create(Path::new(dir).join("correlation.csv"))?;
+        writeln!(file, "lag_ms,correlation")?;
+        for lag in &self.correlations {
+            writeln!(file, "{},{}", lag.lag_ms, lag.correlation)?;
+        }
+
+        let mut file = fs::File::create(Path::new(dir).join("gaps.csv"))?;
+        writeln!(file, "duration_ms")?;
+        for duration in &self.gaps.durations_ms {
+            writeln!(file, "{}", duration)?;
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} Kraken / {} Binance ticks, {} grid points",
+            self.kraken_ticks,
+            self.binance_ticks,
+            self.spread.len()
+        );
+        println!(
+            "spread mean {:.6}, std {:.6}",
+            self.spread_mean, self.spread_std
+        );
+        println!("best lag {} ms (positive: Binance leads)", self.best_lag_ms);
+        println!(
+            "gaps above {}: {}, mean {:.0} ms, p50 {} ms, p90 {} ms, max {} ms",
+            self.gaps.threshold,
+            self.gaps.count,
+            self.gaps.mean_ms,
+            self.gaps.p50_ms,
+            self.gaps.p90_ms,
+            self.gaps.max_ms
+        );
+        println!(
+            "Binance moves {}, followed by Kraken within {} ms: {} ({:.1}%), mean delay {:.0} ms",
+            self.follow.binance_moves,
+            self.params.time_gap_ms,
+            self.follow.followed,
+            self.follow.follow_rate * 100.0,
+            self.follow.mean_delay_ms
+        );
+    }
+}
@@ -1,20 +1,281 @@
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
 
-pub(crate) fn read_api_credentials_from_file(file_path: &str) -> io::Result<(String, String)> {
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+//scrypt cost, 2^15 rounds takes about 100ms and 32MB
+const KEYSTORE_SCRYPT_LOG_N: u8 = 15;
+const KEYSTORE_SCRYPT_R: u32 = 8;
+const KEYSTORE_SCRYPT_P: u32 = 1;
+const KEYSTORE_VERSION: u32 = 1;
+
+//secrets are wiped from memory when dropped
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Credentials {
+    pub api_key: Zeroizing<String>,
+    pub api_sec: Zeroizing<String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("api_key", &"<redacted>")
+            .field("api_sec", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Credentials {
+    pub fn new(api_key: String, api_sec: String) -> Credentials {
+        Credentials {
+            api_key: Zeroizing::new(api_key),
+            api_sec: Zeroizing::new(api_sec),
+        }
+    }
+
+    //catches truncated or mixed up keys before they turn into signature errors
+    pub fn validate(&self, venue: &str) -> Result<()> {
+        if self.api_key.trim().is_empty() {
+            return Err(anyhow!("{} api_key is missing", venue));
+        }
+        if self.api_sec.trim().is_empty() {
+            return Err(anyhow!("{} api_sec is missing", venue));
+        }
+        if self.api_key.chars().any(char::is_whitespace)
+            || self.api_sec.chars().any(char::is_whitespace)
+        {
+            return Err(anyhow!("{} credentials contain whitespace", venue));
+        }
+        if venue == "Kraken" {
+            use base64::Engine;
+            base64::engine::general_purpose::STANDARD
+                .decode(self.api_sec.as_bytes())
+                .map_err(|e| anyhow!("Kraken api_sec is not valid base64: {}", e))?;
+        }
+        Ok(())
+    }
+
+    //KRAKEN_API_KEY / KRAKEN_API_SECRET, None unless both are set
+    pub fn from_env(venue: &str) -> Option<Credentials> {
+        let prefix = venue.to_uppercase();
+        let api_key = std::env::var(format!("{}_API_KEY", prefix)).ok()?;
+        let api_sec = std::env::var(format!("{}_API_SECRET", prefix)).ok()?;
+        Some(Credentials::new(api_key, api_sec))
+    }
+}
+
+//files holding secrets must not be readable by other users
+pub fn check_permissions(file_path: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(file_path)?.permissions().mode();
+        if mode & 0o007 != 0 {
+            return Err(anyhow!(
+                "{} is accessible by other users (mode {:o}), run chmod 600 on it",
+                file_path,
+                mode & 0o777
+            ));
+        }
+    }
+    Ok(())
+}
+
+//legacy plaintext format, one api_key:... and one api_sec:... line
+pub fn read_api_credentials_from_file(file_path: &str) -> Result<Credentials> {
+    check_permissions(file_path)?;
     let file = File::open(file_path)?;
     let reader = BufReader::new(file);
-    let mut api_key = String::new();
-    let mut api_sec = String::new();
+    let mut api_key = None;
+    let mut api_sec = None;
 
     for line in reader.lines() {
-        let line = line?;
-        if line.starts_with("api_key:") {
-            api_key = line.trim_start_matches("api_key:").trim().to_string();
-        } else if line.starts_with("api_sec:") {
-            api_sec = line.trim_start_matches("api_sec:").trim().to_string();
+        let line = Zeroizing::new(line?);
+        if let Some(value) = line.strip_prefix("api_key:") {
+            api_key = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("api_sec:") {
+            api_sec = Some(value.trim().to_string());
         }
     }
 
-    Ok((api_key, api_sec))
+    match (api_key, api_sec) {
+        (Some(api_key), Some(api_sec)) => Ok(Credentials::new(api_key, api_sec)),
+        (None, _) => Err(anyhow!("{} has no api_key line", file_path)),
+        (_, None) => Err(anyhow!("{} has no api_sec line", file_path)),
+    }
+}
+
+//environment first, then the keystore, then the legacy plaintext file
+pub fn load_credentials(
+    venue: &str,
+    keystore: Option<&Keystore>,
+    legacy_file: &str,
+) -> Result<Credentials> {
+    let credentials = if let Some(credentials) = Credentials::from_env(venue) {
+        println!("{} credentials loaded from environment", venue);
+        credentials
+    } else if let Some(credentials) = keystore.and_then(|keystore| keystore.get(venue)) {
+        println!("{} credentials loaded from keystore", venue);
+        credentials.clone()
+    } else if Path::new(legacy_file).exists() {
+        println!("{} credentials loaded from {}", venue, legacy_file);
+        read_api_credentials_from_file(legacy_file)?
+    } else {
+        return Err(anyhow!("No credentials found for {}", venue));
+    };
+    credentials.validate(venue)?;
+    Ok(credentials)
+}
+
+//on disk: scrypt parameters, salt and nonce in the clear, the venue map encrypted
+//with ChaCha20-Poly1305 under the passphrase derived key
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Default)]
+pub struct Keystore {
+    entries: BTreeMap<String, Credentials>,
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Result<Zeroizing<[u8; 32]>> {
+    let params = scrypt::Params::new(log_n, r, p, 32)
+        .map_err(|e| anyhow!("Invalid scrypt params: {}", e))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, key.as_mut())
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+impl Keystore {
+    pub fn new() -> Keystore {
+        Keystore {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(file_path: &str, passphrase: &str) -> Result<Keystore> {
+        use base64::Engine;
+        let b64 = base64::engine::general_purpose::STANDARD;
+
+        check_permissions(file_path)?;
+        let file: KeystoreFile = serde_json::from_str(&fs::read_to_string(file_path)?)
+            .map_err(|e| anyhow!("Invalid keystore {}: {}", file_path, e))?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(anyhow!("Unsupported keystore version {}", file.version));
+        }
+
+        let salt = b64.decode(&file.salt)?;
+        let nonce = b64.decode(&file.nonce)?;
+        if nonce.len() != 12 {
+            return Err(anyhow!("Invalid keystore nonce"));
+        }
+        let key = derive_key(passphrase, &salt, file.log_n, file.r, file.p)?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()));
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    Nonce::from_slice(&nonce),
+                    b64.decode(&file.ciphertext)?.as_ref(),
+                )
+                .map_err(|_| anyhow!("Wrong passphrase or corrupted keystore"))?,
+        );
+
+        Ok(Keystore {
+            entries: serde_json::from_slice(&plaintext)?,
+        })
+    }
+
+    //a fresh salt and nonce on every save, so rotating a key re-encrypts everything
+    pub fn save(&self, file_path: &str, passphrase: &str) -> Result<()> {
+        use base64::Engine;
+        let b64 = base64::engine::general_purpose::STANDARD;
+
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(
+            passphrase,
+            &salt,
+            KEYSTORE_SCRYPT_LOG_N,
+            KEYSTORE_SCRYPT_R,
+            KEYSTORE_SCRYPT_P,
+        )?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = Zeroizing::new(serde_json::to_vec(&self.entries)?);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            log_n: KEYSTORE_SCRYPT_LOG_N,
+            r: KEYSTORE_SCRYPT_R,
+            p: KEYSTORE_SCRYPT_P,
+            salt: b64.encode(salt),
+            nonce: b64.encode(nonce),
+            ciphertext: b64.encode(ciphertext),
+        };
+
+        if let Some(dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        //written next to the target and renamed, an interrupted save keeps the old file
+        let tmp_path = format!("{}.tmp", file_path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut tmp = options.open(&tmp_path)?;
+        std::io::Write::write_all(&mut tmp, serde_json::to_string_pretty(&file)?.as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, file_path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, venue: &str) -> Option<&Credentials> {
+        self.entries.get(venue)
+    }
+
+    pub fn set(&mut self, venue: &str, credentials: Credentials) {
+        self.entries.insert(venue.to_string(), credentials);
+    }
+
+    pub fn remove(&mut self, venue: &str) -> Option<Credentials> {
+        self.entries.remove(venue)
+    }
+
+    pub fn venues(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+}
+
+//SDLA_KEYSTORE_PASSPHRASE for unattended starts, otherwise asked on the terminal
+pub fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>> {
+    if let Ok(passphrase) = std::env::var("SDLA_KEYSTORE_PASSPHRASE") {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    Ok(Zeroizing::new(rpassword::prompt_password(prompt)?))
 }
@@ -1,110 +1,202 @@
-use std::{error::Error, fs::File, io::Write};
+use std::{collections::HashMap, error::Error};
 
-use circular_buffer::CircularBuffer;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::order::{Fill, Side};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Balance {
     pub currency: String,
-    pub amount: f64,
-    pub exchange: String,
-}
-#[derive(Debug, Clone)]
-pub struct BalanceBuffer<const SIZE: usize> {
-    pub buffer: CircularBuffer<SIZE, Balance>,
+    pub free: Decimal,
+    pub locked: Decimal,
     pub exchange: String,
 }
 
 impl Balance {
-    pub fn extract_balance_binance(
-        json_string: &str,
-        asset: &str,
-    ) -> Result<Balance, Box<dyn Error>> {
-        let json_value: Value = serde_json::from_str(json_string)?;
-        let balance_str = json_value["balances"]
-            .as_array()
-            .ok_or("Failed to get account balances from API")?
-            .iter()
-            .find(|&balance| balance["asset"].as_str() == Some(asset))
-            .ok_or(format!("Asset not found: {}", asset))?
-            .get("free")
-            .and_then(|v| v.as_str())
-            .ok_or(format!("Failed to get balance for asset: {}", asset))?
-            .to_string();
-
-        let balance: f64 = balance_str.parse()?;
-
-        Ok(Balance {
-            currency: asset.to_string(),
-            amount: balance,
-            exchange: "Binance".to_string(),
-        })
+    pub fn new(exchange: &str, currency: &str) -> Balance {
+        Balance {
+            currency: currency.to_string(),
+            free: Decimal::ZERO,
+            locked: Decimal::ZERO,
+            exchange: exchange.to_string(),
+        }
     }
 
-    pub fn extract_balance_kraken(json_str: &str, asset: &str) -> Result<Balance, Box<dyn Error>> {
-        let v: Value = serde_json::from_str(json_str)?;
-        let balance = v["result"][asset].as_str();
-        ////parse balance as f64
-        let balance_str = match balance {
-            Some(b) => match b.parse::<String>() {
-                Ok(balance) => balance,
-                Err(e) => return Err(e.into()),
-            },
-            None => {
-                eprintln!("Error: balance is None");
-                let error_message = format!(
-                    "Balance is None\n The json for the asset {} is: {}",
-                    asset, json_str
-                );
-                return Err(error_message.into());
+    pub fn total(&self) -> Decimal {
+        self.free + self.locked
+    }
+
+    //kraken prefixes its legacy assets with X (crypto) or Z (fiat)
+    pub fn normalize_asset_kraken(asset: &str) -> String {
+        match asset {
+            "XXBT" | "XBT" => "BTC".to_string(),
+            "XXDG" | "XDG" => "DOGE".to_string(),
+            "XETH" | "XLTC" | "XXRP" | "XXLM" | "XETC" | "XZEC" | "XXMR" | "XMLN" | "XREP"
+            | "ZUSD" | "ZEUR" | "ZGBP" | "ZCAD" | "ZJPY" | "ZAUD" | "ZCHF" => {
+                asset[1..].to_string()
             }
-        };
+            _ => asset.to_string(),
+        }
+    }
 
-        let balance: f64 = match balance_str.parse::<f64>() {
-            Ok(balance) => balance,
-            Err(e) => return Err(e.into()),
-        };
-        if let Some(balance) = Some(balance) {
-            Ok(Balance {
-                currency: asset.to_string(),
-                amount: balance,
+    //BalanceEx: {"result": {"ZUSD": {"balance": "1.0", "hold_trade": "0.5"}, ...}}
+    pub fn extract_balances_kraken_ex(json_str: &str) -> Result<Vec<Balance>, Box<dyn Error>> {
+        let v: Value = serde_json::from_str(json_str)?;
+        let result = v["result"]
+            .as_object()
+            .ok_or(format!("Failed to get balances from API: {}", json_str))?;
+
+        let mut balances = Vec::new();
+        for (asset, balance) in result {
+            let total: Decimal = balance["balance"]
+                .as_str()
+                .ok_or(format!("Failed to get balance for asset: {}", asset))?
+                .parse()?;
+            let hold: Decimal = balance["hold_trade"].as_str().unwrap_or("0").parse()?;
+            balances.push(Balance {
+                currency: Balance::normalize_asset_kraken(asset),
+                free: total - hold,
+                locked: hold,
                 exchange: "Kraken".to_string(),
-            })
-        } else {
-            Err("Failed to parse balance".into())
+            });
         }
+        Ok(balances)
     }
-}
 
-impl<const SIZE: usize> BalanceBuffer<SIZE> {
-    pub fn new(exchange: String) -> BalanceBuffer<SIZE> {
-        BalanceBuffer {
-            buffer: CircularBuffer::<SIZE, Balance>::new(),
-            exchange: exchange,
+    //account: {"balances": [{"asset": "BTC", "free": "1.0", "locked": "0.5"}, ...]}
+    pub fn extract_balances_binance(json_string: &str) -> Result<Vec<Balance>, Box<dyn Error>> {
+        let json_value: Value = serde_json::from_str(json_string)?;
+        let balances = json_value["balances"].as_array().ok_or(format!(
+            "Failed to get account balances from API: {}",
+            json_string
+        ))?;
+
+        let mut result = Vec::new();
+        for balance in balances {
+            let currency = balance["asset"]
+                .as_str()
+                .ok_or("Failed to get asset name")?
+                .to_string();
+            let free: Decimal = balance["free"]
+                .as_str()
+                .ok_or(format!("Failed to get balance for asset: {}", currency))?
+                .parse()?;
+            let locked: Decimal = balance["locked"].as_str().unwrap_or("0").parse()?;
+            result.push(Balance {
+                currency,
+                free,
+                locked,
+                exchange: "Binance".to_string(),
+            });
         }
+        Ok(result)
     }
+}
 
-    pub fn add_balance(&mut self, balance: Balance) {
-        if self.exchange == balance.exchange {
-            self.buffer.push_back(balance);
-        }
+//latest balance of every asset on every venue, keyed by (exchange, currency)
+#[derive(Debug, Clone, Default)]
+pub struct BalanceManager {
+    balances: HashMap<(String, String), Balance>,
+    //local time of the last change, a REST snapshot taken before it is older than us
+    updated: HashMap<(String, String), u64>,
+}
+
+//these venues stream the absolute balance after every change, in no guaranteed order
+//with the fill or transfer behind it, so only the absolute balances are applied
+const ABSOLUTE_BALANCE_VENUES: [&str; 1] = ["Binance"];
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+impl BalanceManager {
+    pub fn new() -> BalanceManager {
+        BalanceManager::default()
     }
 
-    pub fn serialize_to_json(&self) -> Result<String, serde_json::Error> {
-        let balances: Vec<&Balance> = self.buffer.iter().collect();
-        serde_json::to_string(&balances)
+    //0 for assets never set
+    pub fn updated(&self, exchange: &str, currency: &str) -> u64 {
+        self.updated
+            .get(&(exchange.to_string(), currency.to_string()))
+            .copied()
+            .unwrap_or_default()
     }
 
-    pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
-        let json_string = self
-            .serialize_to_json()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    pub fn get(&self, exchange: &str, currency: &str) -> Balance {
+        self.balances
+            .get(&(exchange.to_string(), currency.to_string()))
+            .cloned()
+            .unwrap_or_else(|| Balance::new(exchange, currency))
+    }
 
-        //handle case if directory does not exist
+    pub fn all(&self) -> impl Iterator<Item = &Balance> {
+        self.balances.values()
+    }
 
-        let mut file = File::create(file_path)?;
-        file.write_all(json_string.as_bytes())?;
-        Ok(())
+    fn entry(&mut self, exchange: &str, currency: &str) -> &mut Balance {
+        self.updated
+            .insert((exchange.to_string(), currency.to_string()), now_millis());
+        self.balances
+            .entry((exchange.to_string(), currency.to_string()))
+            .or_insert_with(|| Balance::new(exchange, currency))
+    }
+
+    //REST snapshots and absolute stream updates replace what we had
+    pub fn set(&mut self, balance: Balance) {
+        self.updated.insert(
+            (balance.exchange.clone(), balance.currency.clone()),
+            now_millis(),
+        );
+        self.balances.insert(
+            (balance.exchange.clone(), balance.currency.clone()),
+            balance,
+        );
+    }
+
+    //replaces every asset of the snapshot's venue, assets missing from it are dropped
+    pub fn seed(&mut self, exchange: &str, balances: Vec<Balance>) {
+        self.balances.retain(|(venue, _), _| venue != exchange);
+        self.updated.retain(|(venue, _), _| venue != exchange);
+        for balance in balances {
+            self.set(balance);
+        }
+    }
+
+    pub fn apply_delta(&mut self, delta: &Balance) {
+        if ABSOLUTE_BALANCE_VENUES.contains(&delta.exchange.as_str()) {
+            return;
+        }
+        let balance = self.entry(&delta.exchange, &delta.currency);
+        balance.free += delta.free;
+        balance.locked += delta.locked;
+    }
+
+    //keeps free balances current between REST snapshots on venues without an absolute
+    //balance stream
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        if ABSOLUTE_BALANCE_VENUES.contains(&fill.exchange.as_str()) {
+            return;
+        }
+        let mut currencies = fill.asset.split('/');
+        let (base, quote) = match (currencies.next(), currencies.next()) {
+            (Some(base), Some(quote)) => (base.to_string(), quote.to_string()),
+            _ => {
+                eprintln!("Cannot apply fill for asset without quote: {}", fill.asset);
+                return;
+            }
+        };
+
+        let notional = fill.price * fill.qty;
+        let (base_change, quote_change) = match fill.side {
+            Side::Buy => (fill.qty, -notional),
+            Side::Sell => (-fill.qty, notional),
+        };
+        self.entry(&fill.exchange, &base).free += base_change;
+        self.entry(&fill.exchange, &quote).free += quote_change;
+        if !fill.fee_asset.is_empty() {
+            self.entry(&fill.exchange, &fill.fee_asset).free -= fill.fee;
+        }
     }
 }
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    exchanges::{kraken::Kraken, RestClient},
+    ids::{SymbolId, VenueId},
+    tick::Tick,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BarKind {
+    //fixed wall clock buckets, aligned to multiples of ms since the epoch
+    Time(u64),
+    //closes once traded volume reaches the size, quote only streams never close one
+    Volume(Decimal),
+    Ticks(usize),
+}
+
+impl BarKind {
+    pub fn label(&self) -> String {
+        match self {
+            BarKind::Time(ms) if ms % 60_000 == 0 => format!("{}m", ms / 60_000),
+            BarKind::Time(ms) if ms % 1000 == 0 => format!("{}s", ms / 1000),
+            BarKind::Time(ms) => format!("{}ms", ms),
+            BarKind::Volume(size) => format!("v{}", size.normalize()),
+            BarKind::Ticks(count) => format!("t{}", count),
+        }
+    }
+}
+
+//bars are stamped with timestamp2 like everything else built from ticks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bar {
+    pub exchange: String,
+    pub asset: String,
+    pub kind: String,
+    pub start: u64,
+    pub end: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub ticks: usize,
+}
+
+impl Bar {
+    fn open_at(tick: &Tick, kind: &BarKind, start: u64) -> Bar {
+        Bar {
+            exchange: tick.exchange.name().to_string(),
+            asset: tick.asset.name().to_string(),
+            kind: kind.label(),
+            start,
+            end: tick.timestamp2,
+            open: tick.avg,
+            high: tick.avg,
+            low: tick.avg,
+            close: tick.avg,
+            volume: tick.volume,
+            ticks: 1,
+        }
+    }
+
+    fn add(&mut self, tick: &Tick) {
+        self.high = self.high.max(tick.avg);
+        self.low = self.low.min(tick.avg);
+        self.close = tick.avg;
+        self.volume += tick.volume;
+        self.ticks += 1;
+        self.end = self.end.max(tick.timestamp2);
+    }
+}
+
+//one venue, symbol and bar kind. time buckets without ticks produce no bar
+#[derive(Debug, Clone)]
+pub struct BarBuilder {
+    pub kind: BarKind,
+    current: Option<Bar>,
+    late: u64,
+}
+
+impl BarBuilder {
+    pub fn new(kind: BarKind) -> BarBuilder {
+        BarBuilder {
+            kind,
+            current: None,
+            late: 0,
+        }
+    }
+
+    //ticks dropped for belonging to a time bar that was already finished
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    //the bar still being built
+    pub fn current(&self) -> Option<&Bar> {
+        self.current.as_ref()
+    }
+
+    //returns the bar the tick completed, if any
+    pub fn update(&mut self, tick: &Tick) -> Option<Bar> {
+        match self.kind {
+            BarKind::Time(ms) => {
+                let start = tick.timestamp2 - tick.timestamp2 % ms.max(1);
+                match &mut self.current {
+                    Some(bar) if bar.start == start => {
+                        bar.add(tick);
+                        None
+                    }
+                    //the bar they belong to is already out, folding them into this one
+                    //would move its open, high and low
+                    Some(bar) if start < bar.start => {
+                        self.late += 1;
+                        None
+                    }
+                    _ => {
+                        let mut done = self.current.replace(Bar::open_at(tick, &self.kind, start));
+                        if let Some(bar) = done.as_mut() {
+                            bar.end = bar.start + ms;
+                        }
+                        done
+                    }
+                }
+            }
+            BarKind::Volume(size) => {
+                match &mut self.current {
+                    Some(bar) => bar.add(tick),
+                    None => self.current = Some(Bar::open_at(tick, &self.kind, tick.timestamp2)),
+                }
+                if self.current.as_ref().is_some_and(|bar| bar.volume >= size) {
+                    self.current.take()
+                } else {
+                    None
+                }
+            }
+            BarKind::Ticks(count) => {
+                match &mut self.current {
+                    Some(bar) => bar.add(tick),
+                    None => self.current = Some(Bar::open_at(tick, &self.kind, tick.timestamp2)),
+                }
+                if self.current.as_ref().is_some_and(|bar| bar.ticks >= count) {
+                    self.current.take()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+//rolling buffer of finished bars per (exchange, asset, kind label), newest at the back.
+//finished bars are returned to the caller to be recorded in the store
+pub struct BarManager {
+    kinds: Vec<BarKind>,
+    capacity: usize,
+    builders: HashMap<(VenueId, SymbolId), Vec<BarBuilder>>,
+    bars: HashMap<(String, String, String), VecDeque<Bar>>,
+}
+
+impl BarManager {
+    pub fn new(kinds: Vec<BarKind>, capacity: usize) -> BarManager {
+        BarManager {
+            kinds,
+            capacity,
+            builders: HashMap::new(),
+            bars: HashMap::new(),
+        }
+    }
+
+    //returns the bars the tick completed
+    pub fn update(&mut self, tick: &Tick) -> Vec<Bar> {
+        let kinds = &self.kinds;
+        let builders = self
+            .builders
+            .entry((tick.exchange, tick.asset))
+            .or_insert_with(|| kinds.iter().map(|kind| BarBuilder::new(*kind)).collect());
+        let done: Vec<Bar> = builders
+            .iter_mut()
+            .filter_map(|builder| builder.update(tick))
+            .collect();
+
+        for bar in &done {
+            let bars = self
+                .bars
+                .entry((bar.exchange.clone(), bar.asset.clone(), bar.kind.clone()))
+                .or_default();
+            bars.push_back(bar.clone());
+            while bars.len() > self.capacity {
+                bars.pop_front();
+            }
+        }
+        done
+    }
+
+    //finished bars, oldest first
+    pub fn bars(&self, exchange: &str, asset: &str, kind: &BarKind) -> Option<&VecDeque<Bar>> {
+        self.bars
+            .get(&(exchange.to_string(), asset.to_string(), kind.label()))
+    }
+
+    pub fn last(&self, exchange: &str, asset: &str, kind: &BarKind) -> Option<&Bar> {
+        self.bars(exchange, asset, kind)
+            .and_then(|bars| bars.back())
+    }
+
+    fn builder(&self, exchange: &str, asset: &str, kind: &BarKind) -> Option<&BarBuilder> {
+        self.builders
+            .get(&(VenueId::intern(exchange), SymbolId::intern(asset)))?
+            .iter()
+            .find(|builder| builder.kind == *kind)
+    }
+
+    //the bar still being built
+    pub fn current(&self, exchange: &str, asset: &str, kind: &BarKind) -> Option<&Bar> {
+        self.builder(exchange, asset, kind)?.current()
+    }
+
+    //late ticks dropped so far
+    pub fn late(&self, exchange: &str, asset: &str, kind: &BarKind) -> u64 {
+        self.builder(exchange, asset, kind)
+            .map_or(0, |builder| builder.late())
+    }
+}
+
+//one row of Kraken's public OHLC
+#[derive(Debug, Clone, PartialEq)]
+pub struct KrakenOhlc {
+    pub start: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub count: u64,
+}
+
+//a local bar whose close is further than the tolerance from Kraken's
+#[derive(Debug, Clone)]
+pub struct BarMismatch {
+    pub start: u64,
+    pub local: Decimal,
+    pub kraken: Decimal,
+    pub deviation: Decimal,
+}
+
+impl KrakenOhlc {
+    //OHLC: {"result": {"PEPEUSD": [[1688671200, "open", "high", "low", "close", "vwap", "volume", 12]],
+    //  "last": 1688672160}}
+    pub fn parse(json_string: &str) -> Result<Vec<KrakenOhlc>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if let Some(errors) = value["error"].as_array() {
+            if !errors.is_empty() {
+                return Err(anyhow!("Kraken error: {:?}", errors));
+            }
+        }
+        let rows = value["result"]
+            .as_object()
+            .and_then(|result| result.iter().find(|(key, _)| *key != "last"))
+            .and_then(|(_, rows)| rows.as_array())
+            .ok_or_else(|| anyhow!("No OHLC rows in response: {}", json_string))?;
+
+        let decimal = |row: &Value, i: usize| -> Result<Decimal> {
+            row[i]
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid OHLC row: {}", row))?
+                .parse()
+                .map_err(|e| anyhow!("Invalid OHLC value: {}", e))
+        };
+        rows.iter()
+            .map(|row| {
+                Ok(KrakenOhlc {
+                    start: row[0].as_u64().unwrap_or(0) * 1000,
+                    open: decimal(row, 1)?,
+                    high: decimal(row, 2)?,
+                    low: decimal(row, 3)?,
+                    close: decimal(row, 4)?,
+                    volume: decimal(row, 6)?,
+                    count: row[7].as_u64().unwrap_or(0),
+                })
+            })
+            .collect()
+    }
+
+    //interval in minutes, since in seconds
+    pub async fn fetch(asset: &str, interval_minutes: u64, since: u64) -> Result<Vec<KrakenOhlc>> {
+        let body = format!(
+            "pair={}&interval={}&since={}",
+            asset.replace('/', ""),
+            interval_minutes,
+            since
+        );
+        let response = Kraken::public_query("OHLC", &body).await;
+        KrakenOhlc::parse(&response)
+    }
+
+    //our Kraken bars are built from spread mids, Kraken's from trades, so closes are compared
+    //with a tolerance. bars missing on either side are skipped
+    pub fn cross_check(bars: &[Bar], ohlc: &[KrakenOhlc], tolerance: Decimal) -> Vec<BarMismatch> {
+        let by_start: HashMap<u64, &KrakenOhlc> = ohlc.iter().map(|row| (row.start, row)).collect();
+        bars.iter()
+            .filter_map(|bar| {
+                let row = by_start.get(&bar.start)?;
+                if row.close.is_zero() {
+                    return None;
+                }
+                let deviation = ((bar.close - row.close) / row.close).abs();
+                (deviation > tolerance).then_some(BarMismatch {
+                    start: bar.start,
+                    local: bar.close,
+                    kraken: row.close,
+                    deviation,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::exchanges::{binance::Binance, kraken::Kraken, RestClient};
+
+//used until the venue has told us our actual tier
+const KRAKEN_DEFAULT_MAKER: Decimal = dec!(0.0016);
+const KRAKEN_DEFAULT_TAKER: Decimal = dec!(0.0026);
+const BINANCE_DEFAULT_MAKER: Decimal = dec!(0.001);
+const BINANCE_DEFAULT_TAKER: Decimal = dec!(0.001);
+//binance takes 25% off when fees are paid in BNB
+const BINANCE_BNB_DISCOUNT: Decimal = dec!(0.75);
+
+//fractions of notional, 0.0026 is 0.26%
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FeeRate {
+    pub maker: Decimal,
+    pub taker: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeTier {
+    pub exchange: String,
+    pub asset: String,
+    pub rate: FeeRate,
+    //30 day volume in the venue's fee currency and where the next tier starts
+    pub volume_30d: Option<Decimal>,
+    pub next_tier_volume: Option<Decimal>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    tiers: HashMap<(String, String), FeeTier>,
+    pub pay_fees_in_bnb: bool,
+    pub updated: u64,
+}
+
+impl FeeRate {
+    pub fn default_for(exchange: &str) -> FeeRate {
+        match exchange {
+            "Binance" => FeeRate {
+                maker: BINANCE_DEFAULT_MAKER,
+                taker: BINANCE_DEFAULT_TAKER,
+            },
+            _ => FeeRate {
+                maker: KRAKEN_DEFAULT_MAKER,
+                taker: KRAKEN_DEFAULT_TAKER,
+            },
+        }
+    }
+}
+
+fn parse_percent(value: &Value) -> Option<Decimal> {
+    value
+        .as_str()
+        .and_then(|s| s.parse::<Decimal>().ok())
+        .map(|p| p / Decimal::ONE_HUNDRED)
+}
+
+impl FeeSchedule {
+    pub fn new(pay_fees_in_bnb: bool) -> FeeSchedule {
+        FeeSchedule {
+            tiers: HashMap::new(),
+            pay_fees_in_bnb,
+            updated: 0,
+        }
+    }
+
+    pub fn set(&mut self, tier: FeeTier) {
+        self.tiers
+            .insert((tier.exchange.clone(), tier.asset.clone()), tier);
+    }
+
+    pub fn tier(&self, exchange: &str, asset: &str) -> Option<&FeeTier> {
+        self.tiers.get(&(exchange.to_string(), asset.to_string()))
+    }
+
+    //effective rate for the pair, including the BNB discount where it applies
+    pub fn rate(&self, exchange: &str, asset: &str) -> FeeRate {
+        let rate = self
+            .tier(exchange, asset)
+            .map(|tier| tier.rate)
+            .unwrap_or_else(|| FeeRate::default_for(exchange));
+        if exchange == "Binance" && self.pay_fees_in_bnb {
+            FeeRate {
+                maker: rate.maker * BINANCE_BNB_DISCOUNT,
+                taker: rate.taker * BINANCE_BNB_DISCOUNT,
+            }
+        } else {
+            rate
+        }
+    }
+
+    //cost of crossing the spread twice on one venue, as a fraction of notional
+    pub fn round_trip_taker(&self, exchange: &str, asset: &str) -> Decimal {
+        Decimal::TWO * self.rate(exchange, asset).taker
+    }
+
+    //TradeVolume: {"result": {"currency": "ZUSD", "volume": "...",
+    //  "fees": {"PEPEUSD": {"fee": "0.2600", "nextvolume": "..."}}, "fees_maker": {...}}}
+    //fees are in percent and keyed by REST pair names, mapped back onto the ws names given
+    pub fn parse_kraken_trade_volume(json_string: &str, assets: &[String]) -> Result<Vec<FeeTier>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if let Some(errors) = value["error"].as_array() {
+            if !errors.is_empty() {
+                return Err(anyhow!("Kraken error: {:?}", errors));
+            }
+        }
+
+        let result = &value["result"];
+        let volume_30d = result["volume"].as_str().and_then(|s| s.parse().ok());
+        assets
+            .iter()
+            .map(|asset| {
+                let pair = asset.replace("/", "");
+                let taker = &result["fees"][&pair];
+                let maker = &result["fees_maker"][&pair];
+                let taker_rate = parse_percent(&taker["fee"])
+                    .ok_or_else(|| anyhow!("Missing taker fee for {}", pair))?;
+                Ok(FeeTier {
+                    exchange: "Kraken".to_string(),
+                    asset: asset.clone(),
+                    rate: FeeRate {
+                        //pairs without a separate maker schedule charge taker for both
+                        maker: parse_percent(&maker["fee"]).unwrap_or(taker_rate),
+                        taker: taker_rate,
+                    },
+                    volume_30d,
+                    next_tier_volume: taker["nextvolume"].as_str().and_then(|s| s.parse().ok()),
+                })
+            })
+            .collect()
+    }
+
+    //account: {"commissionRates": {"maker": "0.00100000", "taker": "0.00100000", ...}}
+    //older responses only carry makerCommission/takerCommission in basis points
+    pub fn parse_binance_account(json_string: &str, assets: &[String]) -> Result<Vec<FeeTier>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if value.get("code").is_some() {
+            return Err(anyhow!("Binance error: {}", value["msg"]));
+        }
+
+        let rates = &value["commissionRates"];
+        let parse_rate = |field: &str, legacy: &str| {
+            rates[field]
+                .as_str()
+                .and_then(|s| s.parse::<Decimal>().ok())
+                .or_else(|| value[legacy].as_i64().map(|bps| Decimal::new(bps, 4)))
+                .ok_or_else(|| anyhow!("Missing {} commission", field))
+        };
+        let rate = FeeRate {
+            maker: parse_rate("maker", "makerCommission")?,
+            taker: parse_rate("taker", "takerCommission")?,
+        };
+
+        Ok(assets
+            .iter()
+            .map(|asset| FeeTier {
+                exchange: "Binance".to_string(),
+                asset: asset.clone(),
+                rate,
+                volume_30d: None,
+                next_tier_volume: None,
+            })
+            .collect())
+    }
+
+    pub async fn fetch_kraken(kraken: &Kraken, assets: &[String]) -> Result<Vec<FeeTier>> {
+        let pairs: Vec<String> = assets.iter().map(|a| a.replace("/", "")).collect();
+        let body = format!("pair={}", pairs.join(","));
+        let response = kraken.query("TradeVolume", &body).await;
+        FeeSchedule::parse_kraken_trade_volume(&response, assets)
+    }
+
+    pub async fn fetch_binance(binance: &Binance, assets: &[String]) -> Result<Vec<FeeTier>> {
+        let response = binance.query("account", "").await;
+        FeeSchedule::parse_binance_account(&response, assets)
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::{OnceLock, RwLock},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+//small copyable handles for venue and symbol names, so hot paths compare integers
+//names are interned once per process and never freed, there are only a handful
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VenueId(u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = self.names.len() as u32;
+        self.names.push(name);
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+fn venues() -> &'static RwLock<Interner> {
+    static VENUES: OnceLock<RwLock<Interner>> = OnceLock::new();
+    VENUES.get_or_init(Default::default)
+}
+
+fn symbols() -> &'static RwLock<Interner> {
+    static SYMBOLS: OnceLock<RwLock<Interner>> = OnceLock::new();
+    SYMBOLS.get_or_init(Default::default)
+}
+
+impl VenueId {
+    pub fn intern(name: &str) -> VenueId {
+        VenueId(venues().write().unwrap().intern(name) as u16)
+    }
+
+    pub fn name(self) -> &'static str {
+        venues().read().unwrap().names[self.0 as usize]
+    }
+}
+
+impl SymbolId {
+    pub fn intern(name: &str) -> SymbolId {
+        SymbolId(symbols().write().unwrap().intern(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        symbols().read().unwrap().names[self.0 as usize]
+    }
+}
+
+impl fmt::Display for VenueId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+//stored and sent by name, ids are only stable within one process
+impl Serialize for VenueId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for VenueId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<VenueId, D::Error> {
+        let name = Cow::<str>::deserialize(deserializer)?;
+        Ok(VenueId::intern(&name))
+    }
+}
+
+impl Serialize for SymbolId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for SymbolId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SymbolId, D::Error> {
+        let name = Cow::<str>::deserialize(deserializer)?;
+        Ok(SymbolId::intern(&name))
+    }
+}
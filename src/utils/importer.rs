@@ -0,0 +1,507 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    bars::KrakenOhlc,
+    exchanges::{
+        binance::Binance,
+        kraken::{Kraken, KrakenUtils},
+        RestClient,
+    },
+    ids::{SymbolId, VenueId},
+    store::{Record, TickStore},
+    tick::Tick,
+};
+
+//lines read from a kraken csv per page
+const CSV_PAGE_LINES: usize = 100_000;
+//rows asked for per binance request, the maximum
+const BINANCE_PAGE_LIMIT: usize = 1000;
+
+//history is imported as ticks. there is no local receive time, timestamp2 is the exchange's.
+//bars become one tick at their close time with the close price and the bar's volume
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    //kraken's downloadable trade history: timestamp,price,volume per line, seconds
+    KrakenCsv,
+    KrakenTrades,
+    //spread mids, like the live kraken ticks. kraken only keeps a few minutes of these
+    KrakenSpread,
+    KrakenOhlc,
+    BinanceAggTrades,
+    BinanceKlines,
+}
+
+impl Source {
+    pub fn parse(name: &str) -> Option<Source> {
+        match name {
+            "kraken-csv" => Some(Source::KrakenCsv),
+            "kraken-trades" => Some(Source::KrakenTrades),
+            "kraken-spread" => Some(Source::KrakenSpread),
+            "kraken-ohlc" => Some(Source::KrakenOhlc),
+            "binance-aggtrades" => Some(Source::BinanceAggTrades),
+            "binance-klines" => Some(Source::BinanceKlines),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::KrakenCsv => "kraken-csv",
+            Source::KrakenTrades => "kraken-trades",
+            Source::KrakenSpread => "kraken-spread",
+            Source::KrakenOhlc => "kraken-ohlc",
+            Source::BinanceAggTrades => "binance-aggtrades",
+            Source::BinanceKlines => "binance-klines",
+        }
+    }
+
+    pub fn exchange(&self) -> &'static str {
+        match self {
+            Source::BinanceAggTrades | Source::BinanceKlines => "Binance",
+            _ => "Kraken",
+        }
+    }
+}
+
+//where the last run stopped, saved after every page so an interrupted import resumes there
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportState {
+    //byte offset for csv files, the next since or fromId for rest history
+    pub cursor: String,
+    //newest imported row, rows at or before it are skipped by time based sources
+    pub last_timestamp: u64,
+    //newest imported trade id, for sources that have them
+    pub last_id: Option<u64>,
+    pub rows: u64,
+    //canonical path of the csv the cursor points into
+    #[serde(default)]
+    pub file: Option<String>,
+    //range of the run that saved this, 0 to 0 in state files from before it was kept
+    #[serde(default)]
+    pub from: u64,
+    #[serde(default)]
+    pub to: u64,
+}
+
+pub struct Importer {
+    pub source: Source,
+    pub asset: String,
+    //csv file, only for Source::KrakenCsv
+    pub file: Option<String>,
+    //rows outside [from, to) ms are not imported
+    pub from: u64,
+    pub to: u64,
+    //bar length for the ohlc and kline sources
+    pub interval_minutes: u64,
+    //pause between requests, kraken's public limit is about one per second
+    pub page_delay_ms: u64,
+    pub state: ImportState,
+    state_path: String,
+    store: TickStore,
+}
+
+impl Importer {
+    //state lives in {store_root}/import/{source}_{base}-{quote}.json. without `to` a resumed
+    //import keeps the range it was started with, a new one runs until now. asking for another
+    //range than the saved one is refused, the cursor only means something within its range
+    pub fn new(
+        source: Source,
+        asset: &str,
+        file: Option<&str>,
+        store_root: &str,
+        from: u64,
+        to: Option<u64>,
+    ) -> Result<Importer> {
+        if source == Source::KrakenCsv && file.is_none() {
+            return Err(anyhow!("kraken-csv needs a file to import"));
+        }
+        let file = match file {
+            Some(file) => Some(
+                fs::canonicalize(file)
+                    .map_err(|e| anyhow!("{}: {}", file, e))?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            None => None,
+        };
+        let state_path = format!(
+            "{}/import/{}_{}.json",
+            store_root,
+            source.name(),
+            asset.replace('/', "-")
+        );
+        let mut state: ImportState = if Path::new(&state_path).exists() {
+            serde_json::from_str(&fs::read_to_string(&state_path)?)
+                .map_err(|e| anyhow!("{}: {}", state_path, e))?
+        } else {
+            ImportState::default()
+        };
+
+        let saved_range = state.to > 0;
+        let to = match to {
+            Some(to) => to,
+            None if saved_range => state.to,
+            None => chrono::Utc::now().timestamp_millis() as u64,
+        };
+        if saved_range && (state.from, state.to) != (from, to) {
+            return Err(anyhow!(
+                "{} was imported for [{}, {}), rerun with that range or delete {} to start over",
+                source.name(),
+                state.from,
+                state.to,
+                state_path
+            ));
+        }
+        state.from = from;
+        state.to = to;
+
+        //another file, or the same one rewritten shorter than the cursor, is read from the top
+        if let Some(file) = &file {
+            let offset: u64 = state.cursor.parse().unwrap_or(0);
+            let replaced = state.file.as_deref().is_some_and(|saved| saved != file)
+                || fs::metadata(file)?.len() < offset;
+            if replaced && !state.cursor.is_empty() {
+                println!(
+                    "{} is not the file the saved cursor points into, importing it from the start",
+                    file
+                );
+                state.cursor.clear();
+            }
+            state.file = Some(file.clone());
+        }
+
+        Ok(Importer {
+            source,
+            asset: asset.to_string(),
+            file,
+            from,
+            to,
+            interval_minutes: 1,
+            page_delay_ms: 1000,
+            state,
+            state_path,
+            store: TickStore::new(store_root),
+        })
+    }
+
+    //pages until the source is exhausted or past `to`, returns the rows imported so far
+    pub async fn run(&mut self) -> Result<u64> {
+        loop {
+            let ticks = match self.next_page().await? {
+                Some(ticks) => ticks,
+                None => break,
+            };
+            let past_end = ticks.iter().any(|tick| tick.timestamp2 >= self.to);
+            let mut imported = 0;
+            for tick in ticks {
+                if tick.timestamp2 < self.from || tick.timestamp2 >= self.to {
+                    continue;
+                }
+                self.state.last_timestamp = self.state.last_timestamp.max(tick.timestamp2);
+                self.store.record(Record::Tick(tick));
+                imported += 1;
+            }
+            //rows are on disk before the cursor moves past them
+            self.store.flush()?;
+            self.state.rows += imported;
+            self.save_state()?;
+            if past_end {
+                break;
+            }
+            if self.source != Source::KrakenCsv {
+                tokio::time::sleep(tokio::time::Duration::from_millis(self.page_delay_ms)).await;
+            }
+        }
+        self.store.close()?;
+        Ok(self.state.rows)
+    }
+
+    fn save_state(&self) -> Result<()> {
+        if let Some(dir) = Path::new(&self.state_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = format!("{}.tmp", self.state_path);
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp_path, &self.state_path)?;
+        Ok(())
+    }
+
+    //one page of new ticks and the cursor moved past it, none once there is nothing left
+    async fn next_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        match self.source {
+            Source::KrakenCsv => self.kraken_csv_page(),
+            Source::KrakenTrades => self.kraken_trades_page().await,
+            Source::KrakenSpread => self.kraken_spread_page().await,
+            Source::KrakenOhlc => self.kraken_ohlc_page().await,
+            Source::BinanceAggTrades => self.binance_agg_trades_page().await,
+            Source::BinanceKlines => self.binance_klines_page().await,
+        }
+    }
+
+    fn tick(&self, timestamp: u64, price: Decimal, volume: Decimal) -> Tick {
+        Tick {
+            timestamp,
+            timestamp2: timestamp,
+            avg: price,
+            exchange: VenueId::intern(self.source.exchange()),
+            asset: SymbolId::intern(&self.asset),
+            volume,
+        }
+    }
+
+    fn cursor_or(&self, default: u64) -> String {
+        if self.state.cursor.is_empty() {
+            default.to_string()
+        } else {
+            self.state.cursor.clone()
+        }
+    }
+
+    fn kraken_csv_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        let path = self.file.clone().unwrap_or_default();
+        let offset: u64 = self.cursor_or(0).parse()?;
+        let mut reader = BufReader::new(File::open(&path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut ticks = Vec::new();
+        let mut read = 0u64;
+        let mut line = String::new();
+        for _ in 0..CSV_PAGE_LINES {
+            line.clear();
+            let n = reader.read_line(&mut line)?;
+            //a last line without a newline may still be being written
+            if n == 0 || !line.ends_with('\n') {
+                break;
+            }
+            read += n as u64;
+            let fields: Vec<&str> = line.trim().split(',').collect();
+            let (timestamp, price, volume) = match fields.as_slice() {
+                [timestamp, price, volume] => (timestamp, price, volume),
+                [] | [""] => continue,
+                _ => return Err(anyhow!("{}: invalid line {:?}", path, line.trim())),
+            };
+            let timestamp: f64 = timestamp
+                .parse()
+                .map_err(|e| anyhow!("{}: invalid timestamp {}: {}", path, timestamp, e))?;
+            ticks.push(self.tick(
+                (timestamp * 1000.0).round() as u64,
+                price.parse()?,
+                volume.parse()?,
+            ));
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        self.state.cursor = (offset + read).to_string();
+        Ok(Some(ticks))
+    }
+
+    //rows under the pair's key, kraken names it differently from the request, and `last`
+    fn kraken_rows(response: &str) -> Result<(Vec<Value>, Value)> {
+        let result = KrakenUtils::parse_rest_result(response)?;
+        let rows = result
+            .as_object()
+            .and_then(|result| result.iter().find(|(key, _)| *key != "last"))
+            .and_then(|(_, rows)| rows.as_array())
+            .ok_or_else(|| anyhow!("No rows in response: {}", response))?;
+        Ok((rows.clone(), result["last"].clone()))
+    }
+
+    fn decimal(value: &Value) -> Result<Decimal> {
+        value
+            .as_str()
+            .ok_or_else(|| anyhow!("Expected a decimal string, got {}", value))?
+            .parse()
+            .map_err(|e| anyhow!("Invalid decimal {}: {}", value, e))
+    }
+
+    async fn kraken_trades_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        //Trades: {"PEPEUSD": [["price", "volume", 1688671200.1234, "b", "m", "", 123]], "last": "1688671200123400000"}
+        //since and last are nanoseconds
+        let since = self.cursor_or(self.from * 1_000_000);
+        let body = format!("pair={}&since={}", self.asset.replace('/', ""), since);
+        let response = Kraken::public_query("Trades", &body).await;
+        let (rows, last) = Importer::kraken_rows(&response)?;
+        let last = match last.as_str() {
+            Some(last) => last.to_string(),
+            None => last.to_string(),
+        };
+
+        let mut ticks = Vec::new();
+        for row in &rows {
+            let timestamp = (row[2].as_f64().unwrap_or(0.0) * 1000.0).round() as u64;
+            match row[6].as_u64() {
+                //pages overlap at the boundary, trade ids say what was already imported
+                Some(id) if self.state.last_id.is_some_and(|last_id| id <= last_id) => continue,
+                Some(id) => self.state.last_id = Some(id),
+                None if timestamp < self.state.last_timestamp => continue,
+                None => {}
+            }
+            ticks.push(self.tick(
+                timestamp,
+                Importer::decimal(&row[0])?,
+                Importer::decimal(&row[1])?,
+            ));
+        }
+        if ticks.is_empty() && (rows.is_empty() || last == since) {
+            return Ok(None);
+        }
+        self.state.cursor = last;
+        Ok(Some(ticks))
+    }
+
+    async fn kraken_spread_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        //Spread: {"PEPEUSD": [[1688671200, "bid", "ask"]], "last": 1688671234}, seconds
+        let since = self.cursor_or(self.from / 1000);
+        let body = format!("pair={}&since={}", self.asset.replace('/', ""), since);
+        let response = Kraken::public_query("Spread", &body).await;
+        let (rows, last) = Importer::kraken_rows(&response)?;
+
+        let mut ticks = Vec::new();
+        for row in &rows {
+            let timestamp = row[0].as_u64().unwrap_or(0) * 1000;
+            if timestamp <= self.state.last_timestamp {
+                continue;
+            }
+            let (bid, ask) = (Importer::decimal(&row[1])?, Importer::decimal(&row[2])?);
+            ticks.push(self.tick(timestamp, (bid + ask) / Decimal::TWO, Decimal::ZERO));
+        }
+        let last = last.as_u64().map(|last| last.to_string());
+        if ticks.is_empty() || last.as_deref().is_none_or(|last| last == since) {
+            return Ok(None);
+        }
+        self.state.cursor = last.unwrap_or_default();
+        Ok(Some(ticks))
+    }
+
+    async fn kraken_ohlc_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        let interval_ms = self.interval_minutes * 60 * 1000;
+        let since: u64 = self.cursor_or(self.from / 1000).parse()?;
+        let rows = KrakenOhlc::fetch(&self.asset, self.interval_minutes, since).await?;
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+
+        let mut ticks = Vec::new();
+        let mut newest = None;
+        for row in rows {
+            let close_time = row.start + interval_ms;
+            //the last bar is still forming, it is imported once it has closed
+            if close_time > now || close_time <= self.state.last_timestamp {
+                continue;
+            }
+            newest = Some(row.start / 1000);
+            ticks.push(self.tick(close_time, row.close, row.volume));
+        }
+        match newest {
+            Some(newest) if !ticks.is_empty() => {
+                self.state.cursor = newest.to_string();
+                Ok(Some(ticks))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn binance_rows(response: &str) -> Result<Vec<Value>> {
+        let value: Value =
+            serde_json::from_str(response).map_err(|e| anyhow!("{}, response: {}", e, response))?;
+        match value.as_array() {
+            Some(rows) => Ok(rows.clone()),
+            None => Err(anyhow!("Binance error: {}", response)),
+        }
+    }
+
+    async fn binance_agg_trades_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        //aggTrades: [{"a": 26129, "p": "0.01633102", "q": "4.70443515", "f": 27781, "l": 27781, "T": 1498793709153, "m": true}]
+        let symbol = self.asset.replace('/', "");
+        let body = if self.state.cursor.is_empty() {
+            format!(
+                "symbol={}&startTime={}&limit={}",
+                symbol, self.from, BINANCE_PAGE_LIMIT
+            )
+        } else {
+            format!(
+                "symbol={}&fromId={}&limit={}",
+                symbol, self.state.cursor, BINANCE_PAGE_LIMIT
+            )
+        };
+        let response = Binance::public_query("aggTrades", &body).await;
+        let rows = Importer::binance_rows(&response)?;
+
+        let mut ticks = Vec::new();
+        let mut seen = HashSet::new();
+        for row in &rows {
+            let id = row["a"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("Invalid aggTrade: {}", row))?;
+            if self.state.last_id.is_some_and(|last_id| id <= last_id) || !seen.insert(id) {
+                continue;
+            }
+            self.state.last_id = Some(self.state.last_id.map_or(id, |last_id| last_id.max(id)));
+            ticks.push(self.tick(
+                row["T"].as_u64().unwrap_or(0),
+                Importer::decimal(&row["p"])?,
+                Importer::decimal(&row["q"])?,
+            ));
+        }
+        if ticks.is_empty() {
+            return Ok(None);
+        }
+        self.state.cursor = (self.state.last_id.unwrap_or(0) + 1).to_string();
+        Ok(Some(ticks))
+    }
+
+    fn binance_interval(&self) -> String {
+        match self.interval_minutes {
+            m if m % (24 * 60) == 0 => format!("{}d", m / (24 * 60)),
+            m if m % 60 == 0 => format!("{}h", m / 60),
+            m => format!("{}m", m),
+        }
+    }
+
+    async fn binance_klines_page(&mut self) -> Result<Option<Vec<Tick>>> {
+        //klines: [[open time, "open", "high", "low", "close", "volume", close time, ...]]
+        let start = self.cursor_or(self.from);
+        let body = format!(
+            "symbol={}&interval={}&startTime={}&limit={}",
+            self.asset.replace('/', ""),
+            self.binance_interval(),
+            start,
+            BINANCE_PAGE_LIMIT
+        );
+        let response = Binance::public_query("klines", &body).await;
+        let rows = Importer::binance_rows(&response)?;
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+
+        let mut ticks = Vec::new();
+        let mut next = None;
+        for row in &rows {
+            let close_time = row[6].as_u64().unwrap_or(0);
+            //the last kline is still forming, it is imported once it has closed
+            if close_time > now || close_time <= self.state.last_timestamp {
+                continue;
+            }
+            next = Some(close_time + 1);
+            ticks.push(self.tick(
+                close_time,
+                Importer::decimal(&row[4])?,
+                Importer::decimal(&row[5])?,
+            ));
+        }
+        match next {
+            Some(next) => {
+                self.state.cursor = next.to_string();
+                Ok(Some(ticks))
+            }
+            None => Ok(None),
+        }
+    }
+}
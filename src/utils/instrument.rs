@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    exchanges::{binance::Binance, kraken::Kraken, RestClient},
+    order::{parse_str_decimal, OrderRequest, Side},
+};
+
+//trading rules of one pair on one venue, sizes are in base and notionals in quote
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Instrument {
+    pub exchange: String,
+    pub asset: String,
+    pub symbol: String,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    pub min_qty: Decimal,
+    pub min_notional: Decimal,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments: HashMap<(String, String), Instrument>,
+}
+
+//snaps to the step grid, exact in decimal so 0.3/0.1 is 3 steps
+fn round_to_step(value: Decimal, step: Decimal, round_down: bool) -> Decimal {
+    if step <= Decimal::ZERO {
+        return value;
+    }
+    let steps = if round_down {
+        (value / step).floor()
+    } else {
+        (value / step).ceil()
+    };
+    (steps * step).normalize()
+}
+
+//kraken gives precisions as a number of decimals, 3 -> 0.001
+fn decimals_to_step(decimals: i64) -> Decimal {
+    Decimal::new(1, decimals as u32)
+}
+
+impl Instrument {
+    //buys round down and sells round up so a limit never gets more aggressive
+    pub fn round_price(&self, price: Decimal, side: Side) -> Decimal {
+        match side {
+            Side::Buy => round_to_step(price, self.tick_size, true),
+            Side::Sell => round_to_step(price, self.tick_size, false),
+        }
+    }
+
+    //never round a quantity up, it may not be covered by the balance
+    pub fn round_qty(&self, qty: Decimal) -> Decimal {
+        round_to_step(qty, self.lot_size, true)
+    }
+
+    //returns the order snapped to valid increments, or why the venue would reject it
+    //reference_price is used for the notional check of market orders
+    pub fn normalize(
+        &self,
+        order: &OrderRequest,
+        reference_price: Decimal,
+    ) -> Result<OrderRequest> {
+        let qty = self.round_qty(order.qty);
+        let price = order.price.map(|price| self.round_price(price, order.side));
+
+        if qty <= Decimal::ZERO || qty < self.min_qty {
+            return Err(anyhow!(
+                "{} {} qty {} below minimum {}",
+                self.exchange,
+                self.asset,
+                qty,
+                self.min_qty
+            ));
+        }
+        let notional = qty * price.unwrap_or(reference_price);
+        if notional < self.min_notional {
+            return Err(anyhow!(
+                "{} {} notional {} below minimum {}",
+                self.exchange,
+                self.asset,
+                notional,
+                self.min_notional
+            ));
+        }
+
+        Ok(OrderRequest {
+            qty,
+            price,
+            ..order.clone()
+        })
+    }
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> InstrumentRegistry {
+        InstrumentRegistry {
+            instruments: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, instrument: Instrument) {
+        self.instruments.insert(
+            (instrument.exchange.clone(), instrument.asset.clone()),
+            instrument,
+        );
+    }
+
+    pub fn get(&self, exchange: &str, asset: &str) -> Option<&Instrument> {
+        self.instruments
+            .get(&(exchange.to_string(), asset.to_string()))
+    }
+
+    pub fn normalize(
+        &self,
+        exchange: &str,
+        order: &OrderRequest,
+        reference_price: Decimal,
+    ) -> Result<OrderRequest> {
+        self.get(exchange, &order.asset)
+            .ok_or_else(|| anyhow!("No instrument for {} {}", exchange, order.asset))?
+            .normalize(order, reference_price)
+    }
+
+    //AssetPairs: {"result": {"PEPEUSD": {"wsname": "PEPE/USD", "pair_decimals": 9,
+    //  "lot_decimals": 8, "ordermin": "...", "costmin": "...", "tick_size": "..."}}}
+    pub fn parse_kraken_asset_pairs(json_string: &str) -> Result<Vec<Instrument>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if let Some(errors) = value["error"].as_array() {
+            if !errors.is_empty() {
+                return Err(anyhow!("Kraken error: {:?}", errors));
+            }
+        }
+
+        let pairs = value["result"]
+            .as_object()
+            .ok_or_else(|| anyhow!("Invalid format for Kraken AssetPairs"))?;
+        pairs
+            .iter()
+            .map(|(symbol, pair)| {
+                let pair_decimals = pair["pair_decimals"]
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("Missing pair_decimals for {}", symbol))?;
+                let lot_decimals = pair["lot_decimals"]
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("Missing lot_decimals for {}", symbol))?;
+                Ok(Instrument {
+                    exchange: "Kraken".to_string(),
+                    asset: pair["wsname"].as_str().unwrap_or(symbol).to_string(),
+                    symbol: symbol.clone(),
+                    //older pairs have no tick_size, their tick is one price decimal
+                    tick_size: parse_str_decimal(pair, "tick_size")
+                        .unwrap_or_else(|_| decimals_to_step(pair_decimals)),
+                    lot_size: decimals_to_step(lot_decimals),
+                    min_qty: parse_str_decimal(pair, "ordermin").unwrap_or(Decimal::ZERO),
+                    min_notional: parse_str_decimal(pair, "costmin").unwrap_or(Decimal::ZERO),
+                })
+            })
+            .collect()
+    }
+
+    //exchangeInfo: {"symbols": [{"symbol": "PEPEUSDT", "baseAsset": "PEPE", "quoteAsset": "USDT",
+    //  "filters": [{"filterType": "PRICE_FILTER", "tickSize": "..."}, {"filterType": "LOT_SIZE", ...}]}]}
+    pub fn parse_binance_exchange_info(json_string: &str) -> Result<Vec<Instrument>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if value.get("code").is_some() {
+            return Err(anyhow!("Binance error: {}", value["msg"]));
+        }
+
+        let symbols = value["symbols"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid format for Binance exchangeInfo"))?;
+        symbols
+            .iter()
+            .map(|symbol| {
+                let filters = symbol["filters"]
+                    .as_array()
+                    .ok_or_else(|| anyhow!("Missing filters"))?;
+                let filter = |filter_type: &str| {
+                    filters
+                        .iter()
+                        .find(|f| f["filterType"] == filter_type)
+                        .cloned()
+                        .unwrap_or(Value::Null)
+                };
+                let price_filter = filter("PRICE_FILTER");
+                let lot_size = filter("LOT_SIZE");
+                //MIN_NOTIONAL was replaced by NOTIONAL, older symbols may still carry it
+                let notional = match filter("NOTIONAL") {
+                    Value::Null => filter("MIN_NOTIONAL"),
+                    notional => notional,
+                };
+
+                Ok(Instrument {
+                    exchange: "Binance".to_string(),
+                    asset: format!(
+                        "{}/{}",
+                        symbol["baseAsset"].as_str().unwrap_or_default(),
+                        symbol["quoteAsset"].as_str().unwrap_or_default()
+                    ),
+                    symbol: symbol["symbol"].as_str().unwrap_or_default().to_string(),
+                    tick_size: parse_str_decimal(&price_filter, "tickSize")?,
+                    lot_size: parse_str_decimal(&lot_size, "stepSize")?,
+                    min_qty: parse_str_decimal(&lot_size, "minQty")?,
+                    min_notional: parse_str_decimal(&notional, "minNotional")
+                        .unwrap_or(Decimal::ZERO),
+                })
+            })
+            .collect()
+    }
+
+    //only the pairs we trade are fetched, the full lists are large
+    pub async fn load(
+        assets_kraken: &[String],
+        assets_binance: &[String],
+    ) -> Result<InstrumentRegistry> {
+        let mut registry = InstrumentRegistry::new();
+
+        let pairs: Vec<String> = assets_kraken.iter().map(|a| a.replace("/", "")).collect();
+        let response =
+            Kraken::public_query("AssetPairs", &format!("pair={}", pairs.join(","))).await;
+        for instrument in InstrumentRegistry::parse_kraken_asset_pairs(&response)? {
+            registry.add(instrument);
+        }
+
+        //symbols=["A","B"], url encoded
+        let symbols: Vec<String> = assets_binance
+            .iter()
+            .map(|a| format!("%22{}%22", a.replace("/", "")))
+            .collect();
+        let body = format!("symbols=%5B{}%5D", symbols.join(","));
+        let response = Binance::public_query("exchangeInfo", &body).await;
+        for instrument in InstrumentRegistry::parse_binance_exchange_info(&response)? {
+            registry.add(instrument);
+        }
+
+        for (exchange, assets) in [("Kraken", assets_kraken), ("Binance", assets_binance)] {
+            for asset in assets {
+                if registry.get(exchange, asset).is_none() {
+                    return Err(anyhow!("Missing instrument for {} {}", exchange, asset));
+                }
+            }
+        }
+        Ok(registry)
+    }
+}
@@ -1,3 +1,16 @@
+pub mod analytics;
 pub mod api_key_man;
 pub mod balance;
+pub mod bars;
+pub mod fees;
+pub mod ids;
+pub mod importer;
+pub mod instrument;
+pub mod oms;
+pub mod order;
+pub mod portfolio;
+pub mod rebalance;
+pub mod reconcile;
+pub mod stats;
+pub mod store;
 pub mod tick;
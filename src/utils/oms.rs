@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::order::{Fill, OrderRequest, OrderStatus, OrderUpdate};
+
+//strategy name given to orders we find on a venue but did not place in this run
+pub const EXTERNAL_STRATEGY: &str = "external";
+
+//kraken userrefs are signed 32-bit. client order ids start at the seconds since
+//2024-01-01, so a restart carries on above the last run unless it averaged more than
+//an order a second, and the range lasts until 2092
+const CLIENT_ORDER_ID_EPOCH: i64 = 1_704_067_200;
+const MAX_CLIENT_ORDER_ID: u32 = i32::MAX as u32;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManagedOrder {
+    pub client_order_id: String,
+    pub order_id: Option<String>,
+    pub exchange: String,
+    pub strategy: String,
+    pub request: OrderRequest,
+    pub status: OrderStatus,
+    pub filled_qty: Decimal,
+    pub avg_fill_price: Decimal,
+    pub fills: Vec<Fill>,
+    pub reject_reason: Option<String>,
+    pub created: u64,
+    pub updated: u64,
+}
+
+//keyed by client order id, exchange order ids are looked up through by_order_id
+#[derive(Debug, Default)]
+pub struct Oms {
+    orders: HashMap<String, ManagedOrder>,
+    by_order_id: HashMap<(String, String), String>,
+    next_client_order_id: u32,
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+impl ManagedOrder {
+    pub fn is_open(&self) -> bool {
+        !self.status.is_final()
+    }
+
+    pub fn remaining_qty(&self) -> Decimal {
+        (self.request.qty - self.filled_qty).max(Decimal::ZERO)
+    }
+
+    //venues only ever move forward, so a stale REST answer must not undo a stream update
+    fn set_status(&mut self, status: OrderStatus) {
+        if self.status.is_final() && !status.is_final() {
+            return;
+        }
+        if status == OrderStatus::Acked && self.status == OrderStatus::PartiallyFilled {
+            return;
+        }
+        self.status = status;
+    }
+}
+
+impl Oms {
+    pub fn new() -> Oms {
+        let seed = (chrono::Utc::now().timestamp() - CLIENT_ORDER_ID_EPOCH)
+            .clamp(1, MAX_CLIENT_ORDER_ID as i64) as u32;
+        Oms {
+            orders: HashMap::new(),
+            by_order_id: HashMap::new(),
+            next_client_order_id: seed,
+        }
+    }
+
+    //ids in use on the venue, e.g. by orders of an earlier run, are never handed out again
+    fn reserve(&mut self, client_order_id: &str) {
+        if let Ok(id) = client_order_id.parse::<u32>() {
+            if id >= self.next_client_order_id && id < MAX_CLIENT_ORDER_ID {
+                self.next_client_order_id = id + 1;
+            }
+        }
+    }
+
+    //registers the order and returns it with its client order id filled in
+    pub fn create(
+        &mut self,
+        strategy: &str,
+        exchange: &str,
+        request: OrderRequest,
+    ) -> OrderRequest {
+        let mut client_order_id = self.next_client_order_id.to_string();
+        while self.orders.contains_key(&client_order_id) {
+            self.advance_client_order_id();
+            client_order_id = self.next_client_order_id.to_string();
+        }
+        self.advance_client_order_id();
+
+        let request = OrderRequest {
+            client_order_id: Some(client_order_id.clone()),
+            ..request
+        };
+        let now = now_millis();
+        self.orders.insert(
+            client_order_id.clone(),
+            ManagedOrder {
+                client_order_id,
+                order_id: None,
+                exchange: exchange.to_string(),
+                strategy: strategy.to_string(),
+                request: request.clone(),
+                status: OrderStatus::New,
+                filled_qty: Decimal::ZERO,
+                avg_fill_price: Decimal::ZERO,
+                fills: Vec::new(),
+                reject_reason: None,
+                created: now,
+                updated: now,
+            },
+        );
+        request
+    }
+
+    fn advance_client_order_id(&mut self) {
+        self.next_client_order_id = if self.next_client_order_id >= MAX_CLIENT_ORDER_ID {
+            1
+        } else {
+            self.next_client_order_id + 1
+        };
+    }
+
+    pub fn on_ack(&mut self, client_order_id: &str, order_id: &str) {
+        if let Some(order) = self.orders.get_mut(client_order_id) {
+            order.order_id = Some(order_id.to_string());
+            if order.status == OrderStatus::New {
+                order.set_status(OrderStatus::Acked);
+            }
+            order.updated = now_millis();
+            self.by_order_id.insert(
+                (order.exchange.clone(), order_id.to_string()),
+                client_order_id.to_string(),
+            );
+        }
+    }
+
+    pub fn on_reject(&mut self, client_order_id: &str, reason: &str) {
+        if let Some(order) = self.orders.get_mut(client_order_id) {
+            order.set_status(OrderStatus::Rejected);
+            order.reject_reason = Some(reason.to_string());
+            order.updated = now_millis();
+        }
+    }
+
+    //the venue has no record of the order, it never reached the book
+    pub fn on_missing(&mut self, exchange: &str, order_id: &str) {
+        let client_order_id = match self.find_client_order_id(exchange, order_id, None) {
+            Some(client_order_id) => client_order_id,
+            None => return,
+        };
+        if let Some(order) = self.orders.get_mut(&client_order_id) {
+            order.set_status(OrderStatus::Expired);
+            order.reject_reason = Some("unknown to the venue".to_string());
+            order.updated = now_millis();
+        }
+    }
+
+    pub fn get(&self, client_order_id: &str) -> Option<&ManagedOrder> {
+        self.orders.get(client_order_id)
+    }
+
+    pub fn get_by_order_id(&self, exchange: &str, order_id: &str) -> Option<&ManagedOrder> {
+        self.by_order_id
+            .get(&(exchange.to_string(), order_id.to_string()))
+            .and_then(|client_order_id| self.orders.get(client_order_id))
+    }
+
+    //the order a venue update belongs to, by exchange order id or by our client order id
+    pub fn get_by_update(&self, update: &OrderUpdate) -> Option<&ManagedOrder> {
+        self.find_client_order_id(
+            &update.exchange,
+            &update.order_id,
+            update.client_order_id.as_ref(),
+        )
+        .and_then(|client_order_id| self.orders.get(&client_order_id))
+    }
+
+    fn find_client_order_id(
+        &self,
+        exchange: &str,
+        order_id: &str,
+        client_order_id: Option<&String>,
+    ) -> Option<String> {
+        if let Some(client_order_id) = self
+            .by_order_id
+            .get(&(exchange.to_string(), order_id.to_string()))
+        {
+            return Some(client_order_id.clone());
+        }
+        client_order_id
+            .filter(|client_order_id| self.orders.contains_key(*client_order_id))
+            .cloned()
+    }
+
+    //orders we never created are adopted under EXTERNAL_STRATEGY so they are still visible
+    pub fn apply_update(&mut self, update: &OrderUpdate) {
+        let client_order_id = match self.find_client_order_id(
+            &update.exchange,
+            &update.order_id,
+            update.client_order_id.as_ref(),
+        ) {
+            Some(client_order_id) => client_order_id,
+            None => {
+                let (asset, side, qty) = match (&update.asset, update.side, update.qty) {
+                    (Some(asset), Some(side), Some(qty)) => (asset.clone(), side, qty),
+                    //a partial update for an order we know nothing about
+                    _ => return,
+                };
+                let client_order_id = update
+                    .client_order_id
+                    .clone()
+                    .unwrap_or_else(|| format!("{}-{}", update.exchange, update.order_id));
+                self.reserve(&client_order_id);
+                let now = now_millis();
+                self.orders.insert(
+                    client_order_id.clone(),
+                    ManagedOrder {
+                        client_order_id: client_order_id.clone(),
+                        order_id: Some(update.order_id.clone()),
+                        exchange: update.exchange.clone(),
+                        strategy: EXTERNAL_STRATEGY.to_string(),
+                        request: OrderRequest {
+                            asset,
+                            side,
+                            order_type: crate::order::OrderType::Limit,
+                            qty,
+                            price: update.price,
+                            client_order_id: Some(client_order_id.clone()),
+                        },
+                        status: OrderStatus::Acked,
+                        filled_qty: Decimal::ZERO,
+                        avg_fill_price: Decimal::ZERO,
+                        fills: Vec::new(),
+                        reject_reason: None,
+                        created: now,
+                        updated: now,
+                    },
+                );
+                client_order_id
+            }
+        };
+
+        self.by_order_id.insert(
+            (update.exchange.clone(), update.order_id.clone()),
+            client_order_id.clone(),
+        );
+        let order = self.orders.get_mut(&client_order_id).unwrap();
+        order.order_id = Some(update.order_id.clone());
+        if let Some(filled_qty) = update.filled_qty {
+            order.filled_qty = order.filled_qty.max(filled_qty);
+        }
+        if let Some(status) = update.status {
+            order.set_status(status);
+        } else if order.filled_qty > Decimal::ZERO && order.remaining_qty() > Decimal::ZERO {
+            order.set_status(OrderStatus::PartiallyFilled);
+        }
+        order.updated = now_millis();
+    }
+
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        let client_order_id = match self.find_client_order_id(&fill.exchange, &fill.order_id, None)
+        {
+            Some(client_order_id) => client_order_id,
+            None => return,
+        };
+        let order = self.orders.get_mut(&client_order_id).unwrap();
+        if order.fills.iter().any(|f| f.trade_id == fill.trade_id) {
+            return;
+        }
+
+        let filled_from_fills: Decimal =
+            order.fills.iter().map(|f| f.qty).sum::<Decimal>() + fill.qty;
+        order.avg_fill_price = (order.avg_fill_price * (filled_from_fills - fill.qty)
+            + fill.price * fill.qty)
+            / filled_from_fills;
+        order.fills.push(fill.clone());
+        order.filled_qty = order.filled_qty.max(filled_from_fills);
+        if order.remaining_qty() > Decimal::ZERO {
+            order.set_status(OrderStatus::PartiallyFilled);
+        } else {
+            order.set_status(OrderStatus::Filled);
+        }
+        order.updated = now_millis();
+    }
+
+    pub fn open_orders(&self, strategy: &str) -> Vec<&ManagedOrder> {
+        self.orders
+            .values()
+            .filter(|order| order.strategy == strategy && order.is_open())
+            .collect()
+    }
+
+    pub fn all_open_orders(&self) -> Vec<&ManagedOrder> {
+        self.orders
+            .values()
+            .filter(|order| order.is_open())
+            .collect()
+    }
+
+    pub fn fills(&self, strategy: &str) -> Vec<&Fill> {
+        self.orders
+            .values()
+            .filter(|order| order.strategy == strategy)
+            .flat_map(|order| order.fills.iter())
+            .collect()
+    }
+
+    //applies a venue's open-order list and returns the ids of our open orders
+    //missing from it, whose final state has to be queried separately
+    pub fn apply_open_orders_snapshot(
+        &mut self,
+        exchange: &str,
+        updates: &[OrderUpdate],
+    ) -> Vec<String> {
+        for update in updates {
+            self.apply_update(update);
+        }
+        self.orders
+            .values()
+            .filter(|order| order.exchange == exchange && order.is_open())
+            .filter_map(|order| order.order_id.clone())
+            .filter(|order_id| !updates.iter().any(|update| &update.order_id == order_id))
+            .collect()
+    }
+}
@@ -0,0 +1,556 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::balance::Balance;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+//New: sent but not yet acknowledged, Acked: resting or being matched on the venue
+pub enum OrderStatus {
+    New,
+    Acked,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    Market,
+    Limit,
+}
+
+//an order we want placed, price is only used for limit orders
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderRequest {
+    pub asset: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub qty: Decimal,
+    pub price: Option<Decimal>,
+    pub client_order_id: Option<String>,
+}
+
+//a single execution against one of our orders
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fill {
+    pub exchange: String,
+    pub asset: String,
+    pub order_id: String,
+    pub trade_id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub fee: Decimal,
+    pub fee_asset: String,
+    pub timestamp: u64,
+}
+
+//state change of one of our orders, fields the venue did not send are None
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderUpdate {
+    pub exchange: String,
+    pub order_id: String,
+    pub client_order_id: Option<String>,
+    pub asset: Option<String>,
+    pub side: Option<Side>,
+    pub status: Option<OrderStatus>,
+    pub price: Option<Decimal>,
+    pub qty: Option<Decimal>,
+    pub filled_qty: Option<Decimal>,
+    pub timestamp: u64,
+}
+
+//events coming from the private (authenticated) feeds
+#[derive(Debug, Clone)]
+pub enum AccountEvent {
+    Fill(Fill),
+    Order(OrderUpdate),
+    //absolute balance of an asset
+    Balance(Balance),
+    //deposit, withdrawal or transfer, free is the signed change
+    BalanceDelta(Balance),
+    //every open order of the venue, sent after each connect of the private feed
+    OpenOrders {
+        exchange: String,
+        orders: Vec<OrderUpdate>,
+    },
+}
+
+impl Side {
+    pub fn as_str_kraken(&self) -> &str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+
+    pub fn from_str_kraken(side: &str) -> Result<Side> {
+        match side {
+            "buy" => Ok(Side::Buy),
+            "sell" => Ok(Side::Sell),
+            _ => Err(anyhow!("Unknown Kraken side: {}", side)),
+        }
+    }
+
+    pub fn from_str_binance(side: &str) -> Result<Side> {
+        match side {
+            "BUY" => Ok(Side::Buy),
+            "SELL" => Ok(Side::Sell),
+            _ => Err(anyhow!("Unknown Binance side: {}", side)),
+        }
+    }
+}
+
+impl OrderType {
+    pub fn as_str_kraken(&self) -> &str {
+        match self {
+            OrderType::Market => "market",
+            OrderType::Limit => "limit",
+        }
+    }
+}
+
+impl OrderStatus {
+    pub fn from_str_kraken(status: &str) -> Result<OrderStatus> {
+        match status {
+            "pending" | "open" => Ok(OrderStatus::Acked),
+            "closed" => Ok(OrderStatus::Filled),
+            "canceled" => Ok(OrderStatus::Cancelled),
+            "expired" => Ok(OrderStatus::Expired),
+            _ => Err(anyhow!("Unknown Kraken order status: {}", status)),
+        }
+    }
+
+    pub fn from_str_binance(status: &str) -> Result<OrderStatus> {
+        match status {
+            "NEW" | "PENDING_NEW" | "PENDING_CANCEL" => Ok(OrderStatus::Acked),
+            "PARTIALLY_FILLED" => Ok(OrderStatus::PartiallyFilled),
+            "FILLED" => Ok(OrderStatus::Filled),
+            "CANCELED" => Ok(OrderStatus::Cancelled),
+            "REJECTED" => Ok(OrderStatus::Rejected),
+            "EXPIRED" | "EXPIRED_IN_MATCH" => Ok(OrderStatus::Expired),
+            _ => Err(anyhow!("Unknown Binance order status: {}", status)),
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Filled
+                | OrderStatus::Cancelled
+                | OrderStatus::Rejected
+                | OrderStatus::Expired
+        )
+    }
+}
+
+//kraken sends numbers as strings, timestamps as fractional seconds
+pub fn parse_str_decimal(value: &Value, field: &str) -> Result<Decimal> {
+    value[field]
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid format for {}", field))
+}
+
+pub fn parse_opt_str_decimal(value: &Value, field: &str) -> Option<Decimal> {
+    value[field].as_str().and_then(|s| s.parse().ok())
+}
+
+//kraken sends times as strings on the socket and as numbers over REST
+fn parse_kraken_time(value: &Value, field: &str) -> Result<Decimal> {
+    match &value[field] {
+        Value::Number(number) => number
+            .to_string()
+            .parse()
+            .map_err(|_| anyhow!("Invalid format for {}", field)),
+        _ => parse_str_decimal(value, field),
+    }
+}
+
+fn kraken_time_to_millis(time: Decimal) -> u64 {
+    (time * Decimal::ONE_THOUSAND).to_u64().unwrap_or_default()
+}
+
+//binance symbols have no separator, split off a known quote currency
+pub fn binance_symbol_to_asset(symbol: &str) -> String {
+    const QUOTES: [&str; 9] = [
+        "USDT", "USDC", "FDUSD", "TUSD", "BUSD", "BTC", "ETH", "BNB", "EUR",
+    ];
+    QUOTES
+        .iter()
+        .find(|quote| symbol.ends_with(*quote) && symbol.len() > quote.len())
+        .map(|quote| format!("{}/{}", &symbol[..symbol.len() - quote.len()], quote))
+        .unwrap_or_else(|| symbol.to_string())
+}
+
+impl Fill {
+    //ownTrades payload: [[{txid: {...}}, ...], "ownTrades", {"sequence": n}]
+    pub fn deserialize_fills_kraken(trades: &Value) -> Result<Vec<Fill>> {
+        let trades = trades
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid format for Kraken ownTrades"))?;
+
+        let mut fills = Vec::new();
+        for entry in trades {
+            let entry = entry
+                .as_object()
+                .ok_or_else(|| anyhow!("Invalid format for Kraken trade entry"))?;
+            for (trade_id, trade) in entry {
+                let asset = trade["pair"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Missing pair"))?
+                    .to_string();
+                //fees are charged in the quote currency unless the order asked otherwise
+                let fee_asset = asset.split('/').nth(1).unwrap_or_default().to_string();
+
+                fills.push(Fill {
+                    exchange: "Kraken".to_string(),
+                    order_id: trade["ordertxid"]
+                        .as_str()
+                        .ok_or_else(|| anyhow!("Missing ordertxid"))?
+                        .to_string(),
+                    trade_id: trade_id.clone(),
+                    side: Side::from_str_kraken(trade["type"].as_str().unwrap_or_default())?,
+                    price: parse_str_decimal(trade, "price")?,
+                    qty: parse_str_decimal(trade, "vol")?,
+                    fee: parse_str_decimal(trade, "fee")?,
+                    fee_asset,
+                    timestamp: kraken_time_to_millis(parse_kraken_time(trade, "time")?),
+                    asset,
+                });
+            }
+        }
+        Ok(fills)
+    }
+}
+
+impl Fill {
+    //executionReport with execution type TRADE
+    pub fn deserialize_fill_binance(report: &Value) -> Result<Fill> {
+        Ok(Fill {
+            exchange: "Binance".to_string(),
+            asset: binance_symbol_to_asset(report["s"].as_str().unwrap_or_default()),
+            order_id: report["i"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("Missing order id"))?
+                .to_string(),
+            trade_id: report["t"]
+                .as_i64()
+                .ok_or_else(|| anyhow!("Missing trade id"))?
+                .to_string(),
+            side: Side::from_str_binance(report["S"].as_str().unwrap_or_default())?,
+            price: parse_str_decimal(report, "L")?,
+            qty: parse_str_decimal(report, "l")?,
+            fee: parse_str_decimal(report, "n")?,
+            fee_asset: report["N"].as_str().unwrap_or_default().to_string(),
+            timestamp: report["T"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("Missing transaction time"))?,
+        })
+    }
+
+    //TradesHistory: {"result": {"trades": {txid: {...}}, "count": n}}
+    //REST pairs have no separator, they are mapped back onto the given ws names
+    pub fn deserialize_fills_kraken_rest(
+        json_string: &str,
+        assets: &[String],
+    ) -> Result<Vec<Fill>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if let Some(errors) = value["error"].as_array() {
+            if !errors.is_empty() {
+                return Err(anyhow!("Kraken error: {:?}", errors));
+            }
+        }
+
+        let trades = Value::Array(vec![value["result"]["trades"].clone()]);
+        let mut fills = Fill::deserialize_fills_kraken(&trades)?;
+        for fill in fills.iter_mut() {
+            if let Some(asset) = assets
+                .iter()
+                .find(|asset| asset.replace("/", "") == fill.asset)
+            {
+                fill.asset = asset.clone();
+                fill.fee_asset = asset.split('/').nth(1).unwrap_or_default().to_string();
+            }
+        }
+        Ok(fills)
+    }
+
+    //myTrades: [{"symbol": "...", "id": 1, "orderId": 2, "isBuyer": true, ...}]
+    pub fn deserialize_fills_binance_rest(json_string: &str) -> Result<Vec<Fill>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        let trades = value
+            .as_array()
+            .ok_or_else(|| anyhow!("Binance error: {}", value["msg"]))?;
+
+        trades
+            .iter()
+            .map(|trade| {
+                Ok(Fill {
+                    exchange: "Binance".to_string(),
+                    asset: binance_symbol_to_asset(trade["symbol"].as_str().unwrap_or_default()),
+                    order_id: trade["orderId"]
+                        .as_u64()
+                        .ok_or_else(|| anyhow!("Missing order id"))?
+                        .to_string(),
+                    trade_id: trade["id"]
+                        .as_u64()
+                        .ok_or_else(|| anyhow!("Missing trade id"))?
+                        .to_string(),
+                    side: if trade["isBuyer"].as_bool().unwrap_or_default() {
+                        Side::Buy
+                    } else {
+                        Side::Sell
+                    },
+                    price: parse_str_decimal(trade, "price")?,
+                    qty: parse_str_decimal(trade, "qty")?,
+                    fee: parse_str_decimal(trade, "commission")?,
+                    fee_asset: trade["commissionAsset"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    timestamp: trade["time"].as_u64().unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl OrderUpdate {
+    pub fn deserialize_order_binance(report: &Value) -> Result<OrderUpdate> {
+        let status = OrderStatus::from_str_binance(report["X"].as_str().unwrap_or_default())?;
+        //cancels carry the original client id in "C", "c" is the id of the cancel request
+        let client_order_id = match report["C"].as_str() {
+            Some(original) if !original.is_empty() => original,
+            _ => report["c"].as_str().unwrap_or_default(),
+        };
+
+        Ok(OrderUpdate {
+            exchange: "Binance".to_string(),
+            order_id: report["i"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("Missing order id"))?
+                .to_string(),
+            client_order_id: Some(client_order_id.to_string()),
+            asset: Some(binance_symbol_to_asset(
+                report["s"].as_str().unwrap_or_default(),
+            )),
+            side: Some(Side::from_str_binance(
+                report["S"].as_str().unwrap_or_default(),
+            )?),
+            status: Some(status),
+            price: parse_opt_str_decimal(report, "p"),
+            qty: parse_opt_str_decimal(report, "q"),
+            filled_qty: parse_opt_str_decimal(report, "z"),
+            timestamp: report["E"].as_u64().unwrap_or_default(),
+        })
+    }
+
+    //openOrders payload: [[{txid: {...}}, ...], "openOrders", {"sequence": n}]
+    //the first message is a snapshot, later ones only carry the fields that changed
+    pub fn deserialize_orders_kraken(orders: &Value, timestamp: u64) -> Result<Vec<OrderUpdate>> {
+        let orders = orders
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid format for Kraken openOrders"))?;
+
+        let mut updates = Vec::new();
+        for entry in orders {
+            let entry = entry
+                .as_object()
+                .ok_or_else(|| anyhow!("Invalid format for Kraken order entry"))?;
+            for (order_id, order) in entry {
+                let descr = &order["descr"];
+                let status = match order["status"].as_str() {
+                    Some(status) => Some(OrderStatus::from_str_kraken(status)?),
+                    None => None,
+                };
+                let side = match descr["type"].as_str() {
+                    Some(side) => Some(Side::from_str_kraken(side)?),
+                    None => None,
+                };
+                let qty = parse_opt_str_decimal(order, "vol");
+                let filled_qty = parse_opt_str_decimal(order, "vol_exec");
+
+                //kraken keeps the status "open" while partially executed
+                let status = match (status, qty, filled_qty) {
+                    (Some(OrderStatus::Acked), Some(qty), Some(filled))
+                        if filled > Decimal::ZERO && filled < qty =>
+                    {
+                        Some(OrderStatus::PartiallyFilled)
+                    }
+                    (status, _, _) => status,
+                };
+
+                updates.push(OrderUpdate {
+                    exchange: "Kraken".to_string(),
+                    order_id: order_id.clone(),
+                    client_order_id: order["userref"]
+                        .as_i64()
+                        .or_else(|| order["userref"].as_str().and_then(|s| s.parse().ok()))
+                        .filter(|userref| *userref != 0)
+                        .map(|userref| userref.to_string()),
+                    asset: descr["pair"].as_str().map(|s| s.to_string()),
+                    side,
+                    status,
+                    price: parse_opt_str_decimal(descr, "price"),
+                    qty,
+                    filled_qty,
+                    timestamp,
+                });
+            }
+        }
+        Ok(updates)
+    }
+
+    //OpenOrders nests under "open", ClosedOrders under "closed", QueryOrders is flat
+    pub fn deserialize_orders_kraken_rest(
+        json_string: &str,
+        timestamp: u64,
+    ) -> Result<Vec<OrderUpdate>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if let Some(errors) = value["error"].as_array() {
+            if !errors.is_empty() {
+                return Err(anyhow!("Kraken error: {:?}", errors));
+            }
+        }
+
+        let result = &value["result"];
+        let orders = if result["open"].is_object() {
+            &result["open"]
+        } else if result["closed"].is_object() {
+            &result["closed"]
+        } else {
+            result
+        };
+        OrderUpdate::deserialize_orders_kraken(&Value::Array(vec![orders.clone()]), timestamp)
+    }
+
+    //openOrders returns an array, order a single object
+    pub fn deserialize_orders_binance_rest(json_string: &str) -> Result<Vec<OrderUpdate>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+        if value.get("code").is_some() {
+            return Err(anyhow!("Binance error: {}", value["msg"]));
+        }
+
+        let orders = match value {
+            Value::Array(orders) => orders,
+            order => vec![order],
+        };
+        orders
+            .iter()
+            .map(|order| {
+                Ok(OrderUpdate {
+                    exchange: "Binance".to_string(),
+                    order_id: order["orderId"]
+                        .as_u64()
+                        .ok_or_else(|| anyhow!("Missing order id"))?
+                        .to_string(),
+                    client_order_id: order["clientOrderId"].as_str().map(|s| s.to_string()),
+                    asset: order["symbol"].as_str().map(binance_symbol_to_asset),
+                    side: Some(Side::from_str_binance(
+                        order["side"].as_str().unwrap_or_default(),
+                    )?),
+                    status: Some(OrderStatus::from_str_binance(
+                        order["status"].as_str().unwrap_or_default(),
+                    )?),
+                    price: parse_opt_str_decimal(order, "price"),
+                    qty: parse_opt_str_decimal(order, "origQty"),
+                    filled_qty: parse_opt_str_decimal(order, "executedQty"),
+                    timestamp: order["updateTime"].as_u64().unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl AccountEvent {
+    pub fn deserialize_kraken(json_string: &str, timestamp: u64) -> Result<Vec<AccountEvent>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+
+        //heartbeats, systemStatus and subscriptionStatus are objects, private data are arrays
+        if value.is_object() {
+            return Ok(Vec::new());
+        }
+
+        let array = value
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid format for Kraken private message"))?;
+        if array.len() < 2 {
+            return Err(anyhow!("Invalid format for Kraken private message"));
+        }
+
+        match array[1].as_str() {
+            Some("ownTrades") => Ok(Fill::deserialize_fills_kraken(&array[0])?
+                .into_iter()
+                .map(AccountEvent::Fill)
+                .collect()),
+            Some("openOrders") => Ok(
+                OrderUpdate::deserialize_orders_kraken(&array[0], timestamp)?
+                    .into_iter()
+                    .map(AccountEvent::Order)
+                    .collect(),
+            ),
+            _ => Err(anyhow!("Unknown Kraken private channel: {}", array[1])),
+        }
+    }
+
+    //user data stream payload, one event per message
+    pub fn deserialize_binance(json_string: &str) -> Result<Vec<AccountEvent>> {
+        let value: Value = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+
+        match value["e"].as_str() {
+            Some("executionReport") => {
+                let mut events = vec![AccountEvent::Order(OrderUpdate::deserialize_order_binance(
+                    &value,
+                )?)];
+                if value["x"] == "TRADE" {
+                    events.push(AccountEvent::Fill(Fill::deserialize_fill_binance(&value)?));
+                }
+                Ok(events)
+            }
+            Some("outboundAccountPosition") => value["B"]
+                .as_array()
+                .ok_or_else(|| anyhow!("Missing balances"))?
+                .iter()
+                .map(|balance| {
+                    Ok(AccountEvent::Balance(Balance {
+                        currency: balance["a"]
+                            .as_str()
+                            .ok_or_else(|| anyhow!("Missing asset"))?
+                            .to_string(),
+                        free: parse_str_decimal(balance, "f")?,
+                        locked: parse_str_decimal(balance, "l")?,
+                        exchange: "Binance".to_string(),
+                    }))
+                })
+                .collect(),
+            Some("balanceUpdate") => Ok(vec![AccountEvent::BalanceDelta(Balance {
+                currency: value["a"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Missing asset"))?
+                    .to_string(),
+                free: parse_str_decimal(&value, "d")?,
+                locked: Decimal::ZERO,
+                exchange: "Binance".to_string(),
+            })]),
+            Some("listenKeyExpired") => Ok(Vec::new()),
+            _ => Err(anyhow!("Unknown Binance user data event: {}", value["e"])),
+        }
+    }
+}
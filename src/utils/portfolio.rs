@@ -0,0 +1,253 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use rust_decimal::{prelude::Signed, Decimal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    order::{Fill, Side},
+    tick::Tick,
+};
+
+//position in one asset pair on one venue, pnl is in the quote currency
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Position {
+    pub exchange: String,
+    pub asset: String,
+    pub base_qty: Decimal,
+    pub quote_qty: Decimal,
+    pub avg_price: Decimal,
+    pub realized_pnl: Decimal,
+    //fees paid in base or quote, valued in quote at the fill price
+    pub fees: Decimal,
+    //fees paid in any other asset (e.g. BNB), not part of the pnl
+    pub other_fees: HashMap<String, Decimal>,
+    pub mark_price: Option<Decimal>,
+}
+
+//totals for one base currency over all venues
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AggregatePosition {
+    pub base: String,
+    pub base_qty: Decimal,
+    pub quote_qty: Decimal,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub fees: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EquitySnapshot {
+    pub timestamp: u64,
+    pub realized_pnl: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub fees: Decimal,
+    pub net_pnl: Decimal,
+    pub positions: Vec<Position>,
+    //per base currency, ordered by base
+    #[serde(default)]
+    pub aggregates: Vec<AggregatePosition>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Portfolio {
+    positions: HashMap<(String, String), Position>,
+    booked: HashSet<(String, String)>,
+}
+
+impl Position {
+    pub fn new(exchange: &str, asset: &str) -> Position {
+        Position {
+            exchange: exchange.to_string(),
+            asset: asset.to_string(),
+            base_qty: Decimal::ZERO,
+            quote_qty: Decimal::ZERO,
+            avg_price: Decimal::ZERO,
+            realized_pnl: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            other_fees: HashMap::new(),
+            mark_price: None,
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        self.asset.split('/').next().unwrap_or_default()
+    }
+
+    pub fn quote(&self) -> &str {
+        self.asset.split('/').nth(1).unwrap_or_default()
+    }
+
+    pub fn unrealized_pnl(&self) -> Decimal {
+        match self.mark_price {
+            Some(mark) => self.base_qty * (mark - self.avg_price),
+            None => Decimal::ZERO,
+        }
+    }
+
+    pub fn net_pnl(&self) -> Decimal {
+        self.realized_pnl + self.unrealized_pnl() - self.fees
+    }
+
+    //average cost: trades that reduce the position realize pnl against avg_price,
+    //trades that extend it move avg_price
+    fn apply_trade(&mut self, signed_qty: Decimal, price: Decimal) {
+        let reducing = !self.base_qty.is_zero() && self.base_qty.signum() != signed_qty.signum();
+        if reducing {
+            let closed = signed_qty.abs().min(self.base_qty.abs());
+            self.realized_pnl += closed * (price - self.avg_price) * self.base_qty.signum();
+            let remaining = signed_qty + closed * self.base_qty.signum();
+            self.base_qty -= closed * self.base_qty.signum();
+            if self.base_qty.is_zero() {
+                self.avg_price = Decimal::ZERO;
+            }
+            //flipped through zero, the rest opens a new position at this price
+            if !remaining.is_zero() {
+                self.base_qty = remaining;
+                self.avg_price = price;
+            }
+        } else {
+            let new_qty = self.base_qty + signed_qty;
+            self.avg_price =
+                (self.base_qty.abs() * self.avg_price + signed_qty.abs() * price) / new_qty.abs();
+            self.base_qty = new_qty;
+        }
+    }
+
+    pub fn book(&mut self, fill: &Fill) {
+        let signed_qty = match fill.side {
+            Side::Buy => fill.qty,
+            Side::Sell => -fill.qty,
+        };
+        self.apply_trade(signed_qty, fill.price);
+        self.quote_qty -= signed_qty * fill.price;
+
+        if fill.fee_asset == self.quote() {
+            self.quote_qty -= fill.fee;
+            self.fees += fill.fee;
+        } else if fill.fee_asset == self.base() {
+            //a base fee shrinks the position without a trade
+            self.base_qty -= fill.fee;
+            self.fees += fill.fee * fill.price;
+        } else if fill.fee > Decimal::ZERO {
+            *self
+                .other_fees
+                .entry(fill.fee_asset.clone())
+                .or_insert(Decimal::ZERO) += fill.fee;
+        }
+    }
+}
+
+impl Portfolio {
+    pub fn new() -> Portfolio {
+        Portfolio {
+            positions: HashMap::new(),
+            booked: HashSet::new(),
+        }
+    }
+
+    //fills are deduplicated by trade id so replays from a reconnect are not booked twice
+    pub fn book_fill(&mut self, fill: &Fill) -> bool {
+        if !self
+            .booked
+            .insert((fill.exchange.clone(), fill.trade_id.clone()))
+        {
+            return false;
+        }
+        self.positions
+            .entry((fill.exchange.clone(), fill.asset.clone()))
+            .or_insert_with(|| Position::new(&fill.exchange, &fill.asset))
+            .book(fill);
+        true
+    }
+
+    //counted as booked without touching the positions
+    pub fn mark_booked(&mut self, fill: &Fill) {
+        self.booked
+            .insert((fill.exchange.clone(), fill.trade_id.clone()));
+    }
+
+    pub fn is_booked(&self, exchange: &str, trade_id: &str) -> bool {
+        self.booked
+            .contains(&(exchange.to_string(), trade_id.to_string()))
+    }
+
+    //called for every tick, the few open positions are scanned instead of building a key
+    pub fn mark(&mut self, tick: &Tick) {
+        let (exchange, asset) = (tick.exchange.name(), tick.asset.name());
+        if let Some(position) = self
+            .positions
+            .iter_mut()
+            .find(|((e, a), _)| e == exchange && a == asset)
+            .map(|(_, position)| position)
+        {
+            position.mark_price = Some(tick.avg);
+        }
+    }
+
+    pub fn position(&self, exchange: &str, asset: &str) -> Option<&Position> {
+        self.positions
+            .get(&(exchange.to_string(), asset.to_string()))
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &Position> {
+        self.positions.values()
+    }
+
+    //quote currencies are summed as-is, so USD and USDT are treated as equal
+    pub fn aggregate(&self) -> Vec<AggregatePosition> {
+        let mut aggregates: HashMap<String, AggregatePosition> = HashMap::new();
+        for position in self.positions.values() {
+            let aggregate = aggregates
+                .entry(position.base().to_string())
+                .or_insert_with(|| AggregatePosition {
+                    base: position.base().to_string(),
+                    ..Default::default()
+                });
+            aggregate.base_qty += position.base_qty;
+            aggregate.quote_qty += position.quote_qty;
+            aggregate.realized_pnl += position.realized_pnl;
+            aggregate.unrealized_pnl += position.unrealized_pnl();
+            aggregate.fees += position.fees;
+        }
+        let mut aggregates: Vec<AggregatePosition> = aggregates.into_values().collect();
+        aggregates.sort_by(|a, b| a.base.cmp(&b.base));
+        aggregates
+    }
+
+    pub fn snapshot(&self, timestamp: u64) -> EquitySnapshot {
+        let realized_pnl = self.positions.values().map(|p| p.realized_pnl).sum();
+        let unrealized_pnl = self.positions.values().map(|p| p.unrealized_pnl()).sum();
+        let fees = self.positions.values().map(|p| p.fees).sum();
+        EquitySnapshot {
+            timestamp,
+            realized_pnl,
+            unrealized_pnl,
+            fees,
+            net_pnl: realized_pnl + unrealized_pnl - fees,
+            positions: self.positions.values().cloned().collect(),
+            aggregates: self.aggregate(),
+        }
+    }
+
+    //one json snapshot per line so a day of equity can be appended to one file
+    pub fn append_snapshot(&self, file_path: &str, timestamp: u64) -> std::io::Result<()> {
+        let json_string =
+            serde_json::to_string(&self.snapshot(timestamp)).map_err(std::io::Error::other)?;
+
+        if let Some(dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)?;
+        writeln!(file, "{}", json_string)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,716 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::{
+    balance::BalanceManager,
+    exchanges::{binance::Binance, kraken::Kraken, kraken::KrakenUtils, url_encode, RestClient},
+};
+
+//a manual deposit into Kraken matches a transfer when it is at most this much smaller,
+//the sending venue's fee comes off the amount
+const DEPOSIT_MATCH_TOLERANCE: Decimal = dec!(0.02);
+//proposals nobody approved are dropped after this, balances will have moved
+const PROPOSAL_EXPIRY_MS: u64 = 60 * 60 * 1000;
+//binance deposit history status for a credited deposit
+const BINANCE_DEPOSIT_SUCCESS: i64 = 1;
+//binance withdraw history statuses: cancelled, rejected, failure
+const BINANCE_WITHDRAW_FAILED: [i64; 3] = [1, 3, 5];
+//binance errors that leave the execution status unknown
+const BINANCE_STATUS_UNKNOWN: [i64; 2] = [-1006, -1007];
+//a withdrawal with an unknown outcome that the sending venue still doesn't list after
+//this long was never taken
+const UNKNOWN_EXPIRY_MS: u64 = 30 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RebalanceMode {
+    //proposals are only printed
+    DryRun,
+    //proposals wait until their id is added to the approval file
+    ManualApproval,
+    //proposals are executed right away
+    Auto,
+}
+
+//share of an asset's total inventory that should sit on Kraken, the rest on Binance
+#[derive(Debug, Clone)]
+pub struct InventoryTarget {
+    pub asset: String,
+    pub kraken_share: Decimal,
+    //how far the share may drift before a transfer is proposed
+    pub band: Decimal,
+    pub min_transfer: Decimal,
+}
+
+//transfers only ever go to addresses listed here
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhitelistedAddress {
+    pub asset: String,
+    //venue the address belongs to
+    pub to: String,
+    pub address: String,
+    //name of the withdrawal key saved on Kraken for this address, out of Kraken
+    #[serde(default)]
+    pub kraken_withdraw_key: Option<String>,
+    //Kraken deposit method the address belongs to, into Kraken
+    #[serde(default)]
+    pub kraken_deposit_method: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TransferStatus {
+    Proposed,
+    Approved,
+    //the withdrawal request got no clear answer and may have been taken, it stays in
+    //flight until the sending venue's withdrawal history settles it
+    Unknown(String),
+    //left the sending venue, refid is its withdrawal reference
+    Withdrawn,
+    //on chain, txid known
+    Sent,
+    Credited,
+    Failed(String),
+}
+
+impl TransferStatus {
+    pub fn is_final(&self) -> bool {
+        matches!(self, TransferStatus::Credited | TransferStatus::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: String,
+    pub asset: String,
+    pub from: String,
+    pub to: String,
+    pub amount: Decimal,
+    pub address: String,
+    pub fee: Option<Decimal>,
+    pub status: TransferStatus,
+    pub refid: Option<String>,
+    pub txid: Option<String>,
+    pub created: u64,
+    pub updated: u64,
+}
+
+impl Transfer {
+    fn set_status(&mut self, status: TransferStatus, timestamp: u64) {
+        println!(
+            "Transfer {} {} {} {} -> {}: {:?}",
+            self.id, self.amount, self.asset, self.from, self.to, status
+        );
+        self.status = status;
+        self.updated = timestamp;
+    }
+
+    //the sending venue still has no record of it, the request never got through
+    fn expire_unknown(&mut self, timestamp: u64) {
+        if timestamp.saturating_sub(self.updated) > UNKNOWN_EXPIRY_MS {
+            self.set_status(
+                TransferStatus::Failed(format!("{} has no record of the withdrawal", self.from)),
+                timestamp,
+            );
+        }
+    }
+}
+
+//Kraken still names some assets the legacy way in funding requests
+fn kraken_asset(asset: &str) -> &str {
+    match asset {
+        "BTC" => "XBT",
+        "DOGE" => "XDG",
+        _ => asset,
+    }
+}
+
+fn other_venue(venue: &str) -> &'static str {
+    if venue == "Kraken" {
+        "Binance"
+    } else {
+        "Kraken"
+    }
+}
+
+//no answer, or a venue side failure after the request may already have been taken
+fn kraken_outcome_unknown(response: &str) -> bool {
+    match serde_json::from_str::<Value>(response) {
+        Ok(value) => value["error"].as_array().is_some_and(|errors| {
+            errors
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|error| error.starts_with("EService:") || error == "EGeneral:Internal error")
+        }),
+        Err(_) => true,
+    }
+}
+
+fn binance_outcome_unknown(response: &str) -> bool {
+    match serde_json::from_str::<Value>(response) {
+        Ok(value) => value["code"]
+            .as_i64()
+            .is_some_and(|code| BINANCE_STATUS_UNKNOWN.contains(&code)),
+        Err(_) => true,
+    }
+}
+
+fn decimal_field(value: &Value, field: &str) -> Option<Decimal> {
+    match &value[field] {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+//keeps inventory on both venues near target, one transfer per asset in flight at a time.
+//withdrawals are made on the sending venue, transfers out of Binance are then tracked on
+//Kraken's side until credited
+pub struct Rebalancer {
+    pub mode: RebalanceMode,
+    pub targets: Vec<InventoryTarget>,
+    pub whitelist: Vec<WhitelistedAddress>,
+    pub approval_file: String,
+    pub state_file: String,
+    kraken: Kraken,
+    binance: Binance,
+    transfers: Vec<Transfer>,
+}
+
+impl Rebalancer {
+    //transfers still in flight from a previous run are picked up from the state file
+    pub fn new(
+        mode: RebalanceMode,
+        targets: Vec<InventoryTarget>,
+        whitelist: Vec<WhitelistedAddress>,
+        approval_file: &str,
+        state_file: &str,
+        kraken: Kraken,
+        binance: Binance,
+    ) -> Result<Rebalancer> {
+        let transfers = if Path::new(state_file).exists() {
+            serde_json::from_str(&fs::read_to_string(state_file)?)?
+        } else {
+            Vec::new()
+        };
+        Ok(Rebalancer {
+            mode,
+            targets,
+            whitelist,
+            approval_file: approval_file.to_string(),
+            state_file: state_file.to_string(),
+            kraken,
+            binance,
+            transfers,
+        })
+    }
+
+    //[{"asset": "PEPE", "to": "Binance", "address": "0x...", "kraken_withdraw_key": "binance-pepe"}]
+    pub fn load_whitelist(file_path: &str) -> Result<Vec<WhitelistedAddress>> {
+        if !Path::new(file_path).exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(file_path)?)?)
+    }
+
+    pub fn transfers(&self) -> &[Transfer] {
+        &self.transfers
+    }
+
+    fn whitelisted(&self, asset: &str, to: &str) -> Option<&WhitelistedAddress> {
+        self.whitelist
+            .iter()
+            .find(|entry| entry.asset == asset && entry.to == to)
+    }
+
+    fn in_flight(&self, asset: &str) -> bool {
+        self.transfers
+            .iter()
+            .any(|transfer| transfer.asset == asset && !transfer.status.is_final())
+    }
+
+    //what would bring each asset back to target, from current balances only
+    pub fn propose(&self, balances: &BalanceManager, timestamp: u64) -> Vec<Transfer> {
+        let mut proposals = Vec::new();
+        for target in &self.targets {
+            if self.in_flight(&target.asset) {
+                continue;
+            }
+            let kraken = balances.get("Kraken", &target.asset);
+            let binance = balances.get("Binance", &target.asset);
+            let total = kraken.total() + binance.total();
+            if total.is_zero() {
+                continue;
+            }
+            let share = kraken.total() / total;
+            if (share - target.kraken_share).abs() <= target.band {
+                continue;
+            }
+
+            let excess = kraken.total() - total * target.kraken_share;
+            let (from, free) = if excess.is_sign_positive() {
+                ("Kraken", kraken.free)
+            } else {
+                ("Binance", binance.free)
+            };
+            let to = other_venue(from);
+            let amount = excess.abs().min(free);
+            if amount < target.min_transfer {
+                continue;
+            }
+            let address = match self.whitelisted(&target.asset, to) {
+                Some(entry) => entry.address.clone(),
+                None => {
+                    eprintln!(
+                        "Rebalance {} {} -> {} needed but no whitelisted address",
+                        target.asset, from, to
+                    );
+                    continue;
+                }
+            };
+            proposals.push(Transfer {
+                id: format!("rb-{}-{}", target.asset, timestamp),
+                asset: target.asset.clone(),
+                from: from.to_string(),
+                to: to.to_string(),
+                amount,
+                address,
+                fee: None,
+                status: TransferStatus::Proposed,
+                refid: None,
+                txid: None,
+                created: timestamp,
+                updated: timestamp,
+            });
+        }
+        proposals
+    }
+
+    //one round: advance transfers in flight, propose new ones, execute what is approved
+    pub async fn run(&mut self, balances: &RwLock<BalanceManager>) -> Result<()> {
+        let timestamp = chrono::Utc::now().timestamp_millis() as u64;
+
+        for i in 0..self.transfers.len() {
+            if let Err(e) = self.track(i, timestamp).await {
+                eprintln!("Failed to track transfer {}: {}", self.transfers[i].id, e);
+            }
+        }
+
+        let proposals = self.propose(&*balances.read().await, timestamp);
+        for mut proposal in proposals {
+            //a dry run works with a read only key, the venue checks need funding permissions
+            if self.mode == RebalanceMode::DryRun {
+                println!(
+                    "Rebalance dry run, venue checks skipped, would transfer: {:?}",
+                    proposal
+                );
+                continue;
+            }
+            if let Err(e) = self.prepare(&mut proposal).await {
+                eprintln!("Rebalance proposal {} rejected: {}", proposal.id, e);
+                continue;
+            }
+            match self.mode {
+                RebalanceMode::DryRun => {}
+                RebalanceMode::ManualApproval => {
+                    println!(
+                        "Rebalance proposal {}: {} {} {} -> {} to {}, fee {:?}, approve by adding the id to {}",
+                        proposal.id,
+                        proposal.amount,
+                        proposal.asset,
+                        proposal.from,
+                        proposal.to,
+                        proposal.address,
+                        proposal.fee,
+                        self.approval_file
+                    );
+                    self.transfers.push(proposal);
+                }
+                RebalanceMode::Auto => {
+                    proposal.set_status(TransferStatus::Approved, timestamp);
+                    self.transfers.push(proposal);
+                }
+            }
+        }
+
+        self.read_approvals(timestamp)?;
+        for i in 0..self.transfers.len() {
+            if self.transfers[i].status == TransferStatus::Approved {
+                if let Err(e) = self.execute(i, timestamp).await {
+                    let message = e.to_string();
+                    self.transfers[i].set_status(TransferStatus::Failed(message), timestamp);
+                }
+            }
+        }
+
+        self.save()
+    }
+
+    //checks the venue agrees with the whitelist and fills in fee and limits
+    async fn prepare(&self, transfer: &mut Transfer) -> Result<()> {
+        let entry = self
+            .whitelisted(&transfer.asset, &transfer.to)
+            .ok_or_else(|| anyhow!("No whitelisted address"))?;
+        let asset = kraken_asset(&transfer.asset);
+
+        if transfer.from == "Kraken" {
+            let key = entry
+                .kraken_withdraw_key
+                .as_deref()
+                .ok_or_else(|| anyhow!("No Kraken withdrawal key for {}", entry.address))?;
+
+            //WithdrawAddresses: [{"address": "...", "asset": "PEPE", "key": "...", "verified": true}]
+            let response = self
+                .kraken
+                .query(
+                    "WithdrawAddresses",
+                    &url_encode(&[
+                        ("asset", asset.to_string()),
+                        ("key", key.to_string()),
+                        ("verified", "true".to_string()),
+                    ]),
+                )
+                .await;
+            let addresses = KrakenUtils::parse_rest_result(&response)?;
+            let matches = addresses.as_array().is_some_and(|addresses| {
+                addresses
+                    .iter()
+                    .any(|a| a["address"].as_str() == Some(entry.address.as_str()))
+            });
+            if !matches {
+                return Err(anyhow!(
+                    "Kraken withdrawal key {} does not point to whitelisted address {}",
+                    key,
+                    entry.address
+                ));
+            }
+
+            //WithdrawInfo: {"method": "...", "limit": "...", "amount": "...", "fee": "..."}
+            let response = self
+                .kraken
+                .query(
+                    "WithdrawInfo",
+                    &url_encode(&[
+                        ("asset", asset.to_string()),
+                        ("key", key.to_string()),
+                        ("amount", transfer.amount.to_string()),
+                    ]),
+                )
+                .await;
+            let info = KrakenUtils::parse_rest_result(&response)?;
+            if let Some(limit) = decimal_field(&info, "limit") {
+                transfer.amount = transfer.amount.min(limit);
+            }
+            transfer.fee = decimal_field(&info, "fee");
+        } else {
+            let method = entry
+                .kraken_deposit_method
+                .as_deref()
+                .ok_or_else(|| anyhow!("No Kraken deposit method for {}", entry.address))?;
+
+            //DepositAddresses: [{"address": "...", "expiretm": "0", "new": true}]
+            let response = self
+                .kraken
+                .query(
+                    "DepositAddresses",
+                    &url_encode(&[("asset", asset.to_string()), ("method", method.to_string())]),
+                )
+                .await;
+            let addresses = KrakenUtils::parse_rest_result(&response)?;
+            let matches = addresses.as_array().is_some_and(|addresses| {
+                addresses
+                    .iter()
+                    .any(|a| a["address"].as_str() == Some(entry.address.as_str()))
+            });
+            if !matches {
+                return Err(anyhow!(
+                    "{} is not a Kraken deposit address for {}",
+                    entry.address,
+                    transfer.asset
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    //one id per line, only proposals still waiting are affected
+    fn read_approvals(&mut self, timestamp: u64) -> Result<()> {
+        for transfer in self.transfers.iter_mut() {
+            if transfer.status == TransferStatus::Proposed
+                && timestamp.saturating_sub(transfer.created) > PROPOSAL_EXPIRY_MS
+            {
+                transfer.set_status(
+                    TransferStatus::Failed("Not approved in time".to_string()),
+                    timestamp,
+                );
+            }
+        }
+        if self.mode != RebalanceMode::ManualApproval || !Path::new(&self.approval_file).exists() {
+            return Ok(());
+        }
+        let approved = fs::read_to_string(&self.approval_file)?;
+        for id in approved.lines().map(str::trim) {
+            if let Some(transfer) = self
+                .transfers
+                .iter_mut()
+                .find(|t| t.id == id && t.status == TransferStatus::Proposed)
+            {
+                transfer.set_status(TransferStatus::Approved, timestamp);
+            }
+        }
+        Ok(())
+    }
+
+    //a request without a clear answer leaves the transfer Unknown instead of Failed, it
+    //may have gone through and a new proposal would withdraw the funds a second time
+    async fn execute(&mut self, i: usize, timestamp: u64) -> Result<()> {
+        let transfer = &self.transfers[i];
+        if transfer.from == "Kraken" {
+            let key = self
+                .whitelisted(&transfer.asset, &transfer.to)
+                .and_then(|entry| entry.kraken_withdraw_key.clone())
+                .ok_or_else(|| anyhow!("Whitelist entry removed"))?;
+            //Withdraw: {"refid": "..."}
+            let response = self
+                .kraken
+                .query(
+                    "Withdraw",
+                    &url_encode(&[
+                        ("asset", kraken_asset(&transfer.asset).to_string()),
+                        ("key", key),
+                        ("amount", transfer.amount.to_string()),
+                    ]),
+                )
+                .await;
+            if kraken_outcome_unknown(&response) {
+                self.transfers[i].set_status(TransferStatus::Unknown(response), timestamp);
+                return Ok(());
+            }
+            let result = KrakenUtils::parse_rest_result(&response)?;
+            let refid = result["refid"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Missing refid in response: {}", response))?;
+            let transfer = &mut self.transfers[i];
+            transfer.refid = Some(refid.to_string());
+            transfer.set_status(TransferStatus::Withdrawn, timestamp);
+        } else {
+            //withdraw: {"id": "..."}, the withdraw history is looked up by withdrawOrderId
+            //when the answer is lost
+            let response = self
+                .binance
+                .query(
+                    "withdraw",
+                    &url_encode(&[
+                        ("coin", transfer.asset.clone()),
+                        ("address", transfer.address.clone()),
+                        ("amount", transfer.amount.to_string()),
+                        ("withdrawOrderId", transfer.id.clone()),
+                    ]),
+                )
+                .await;
+            if binance_outcome_unknown(&response) {
+                self.transfers[i].set_status(TransferStatus::Unknown(response), timestamp);
+                return Ok(());
+            }
+            let result: Value = serde_json::from_str(&response)
+                .map_err(|e| anyhow!("{}, response: {}", e, response))?;
+            let id = result["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Binance withdrawal rejected: {}", response))?;
+            let transfer = &mut self.transfers[i];
+            transfer.refid = Some(id.to_string());
+            transfer.set_status(TransferStatus::Withdrawn, timestamp);
+        }
+        Ok(())
+    }
+
+    //Kraken withdrawals are followed through WithdrawStatus and Binance's deposit history,
+    //transfers into Kraken through DepositStatus. withdrawals with an unknown outcome are
+    //looked up on the sending venue first
+    async fn track(&mut self, i: usize, timestamp: u64) -> Result<()> {
+        let transfer = self.transfers[i].clone();
+        let asset = kraken_asset(&transfer.asset);
+        match (&transfer.status, transfer.from.as_str()) {
+            (TransferStatus::Unknown(_), "Kraken") => {
+                //Kraken's answer carried the refid, the withdrawal is matched by address,
+                //amount and time instead
+                let response = self
+                    .kraken
+                    .query(
+                        "WithdrawStatus",
+                        &url_encode(&[("asset", asset.to_string())]),
+                    )
+                    .await;
+                let withdrawals = KrakenUtils::parse_rest_result(&response)?;
+                let claimed: Vec<&str> = self
+                    .transfers
+                    .iter()
+                    .filter_map(|t| t.refid.as_deref())
+                    .collect();
+                let min_amount = transfer.amount * (Decimal::ONE - DEPOSIT_MATCH_TOLERANCE);
+                let withdrawal = withdrawals.as_array().and_then(|withdrawals| {
+                    withdrawals.iter().find(|w| {
+                        let amount = decimal_field(w, "amount").unwrap_or_default();
+                        w["info"].as_str() == Some(transfer.address.as_str())
+                            && w["time"].as_u64().unwrap_or(0) >= transfer.updated / 1000
+                            && amount >= min_amount
+                            && amount <= transfer.amount
+                            && !claimed.contains(&w["refid"].as_str().unwrap_or_default())
+                    })
+                });
+                let refid = withdrawal.and_then(|w| w["refid"].as_str().map(|r| r.to_string()));
+                let transfer = &mut self.transfers[i];
+                match refid {
+                    Some(refid) => {
+                        transfer.refid = Some(refid);
+                        transfer.set_status(TransferStatus::Withdrawn, timestamp);
+                    }
+                    None => transfer.expire_unknown(timestamp),
+                }
+            }
+            (TransferStatus::Unknown(_), _) => {
+                //withdrawHistory: [{"id": "...", "withdrawOrderId": "...", "status": 6, ...}]
+                let response = self
+                    .binance
+                    .query(
+                        "withdrawHistory",
+                        &url_encode(&[
+                            ("coin", transfer.asset.clone()),
+                            ("withdrawOrderId", transfer.id.clone()),
+                        ]),
+                    )
+                    .await;
+                let withdrawals: Value = serde_json::from_str(&response)
+                    .map_err(|e| anyhow!("{}, response: {}", e, response))?;
+                let withdrawal = withdrawals.as_array().and_then(|withdrawals| {
+                    withdrawals
+                        .iter()
+                        .find(|w| w["withdrawOrderId"].as_str() == Some(transfer.id.as_str()))
+                });
+                let transfer = &mut self.transfers[i];
+                match withdrawal {
+                    Some(w)
+                        if w["status"]
+                            .as_i64()
+                            .is_some_and(|status| BINANCE_WITHDRAW_FAILED.contains(&status)) =>
+                    {
+                        transfer.set_status(
+                            TransferStatus::Failed(format!("Binance withdrawal failed: {}", w)),
+                            timestamp,
+                        )
+                    }
+                    Some(w) => {
+                        transfer.refid = w["id"].as_str().map(|id| id.to_string());
+                        transfer.set_status(TransferStatus::Withdrawn, timestamp);
+                    }
+                    None => transfer.expire_unknown(timestamp),
+                }
+            }
+            (TransferStatus::Withdrawn, "Kraken") => {
+                //WithdrawStatus: [{"refid": "...", "txid": "...", "status": "Success", ...}]
+                let response = self
+                    .kraken
+                    .query(
+                        "WithdrawStatus",
+                        &url_encode(&[("asset", asset.to_string())]),
+                    )
+                    .await;
+                let withdrawals = KrakenUtils::parse_rest_result(&response)?;
+                let withdrawal = withdrawals.as_array().and_then(|withdrawals| {
+                    withdrawals
+                        .iter()
+                        .find(|w| w["refid"].as_str() == transfer.refid.as_deref())
+                });
+                let withdrawal = match withdrawal {
+                    Some(withdrawal) => withdrawal,
+                    None => return Ok(()),
+                };
+                let transfer = &mut self.transfers[i];
+                match withdrawal["status"].as_str() {
+                    Some("Success") => {
+                        transfer.txid = withdrawal["txid"].as_str().map(|t| t.to_string());
+                        transfer.set_status(TransferStatus::Sent, timestamp);
+                    }
+                    Some("Failure") => transfer.set_status(
+                        TransferStatus::Failed("Kraken withdrawal failed".to_string()),
+                        timestamp,
+                    ),
+                    _ => {}
+                }
+            }
+            (TransferStatus::Sent, "Kraken") => {
+                //depositHistory: [{"coin": "PEPE", "amount": "...", "txId": "...", "status": 1}]
+                let response = self
+                    .binance
+                    .query(
+                        "depositHistory",
+                        &url_encode(&[("coin", transfer.asset.clone())]),
+                    )
+                    .await;
+                let deposits: Value = serde_json::from_str(&response)
+                    .map_err(|e| anyhow!("{}, response: {}", e, response))?;
+                let credited = deposits.as_array().is_some_and(|deposits| {
+                    deposits.iter().any(|d| {
+                        d["txId"].as_str().is_some()
+                            && d["txId"].as_str() == transfer.txid.as_deref()
+                            && d["status"].as_i64() == Some(BINANCE_DEPOSIT_SUCCESS)
+                    })
+                });
+                if credited {
+                    self.transfers[i].set_status(TransferStatus::Credited, timestamp);
+                }
+            }
+            (TransferStatus::Withdrawn, _) => {
+                //DepositStatus: [{"amount": "...", "txid": "...", "time": 1688992722, "status": "Success", ...}]
+                let response = self
+                    .kraken
+                    .query(
+                        "DepositStatus",
+                        &url_encode(&[("asset", asset.to_string())]),
+                    )
+                    .await;
+                let deposits = KrakenUtils::parse_rest_result(&response)?;
+                let claimed: Vec<&str> = self
+                    .transfers
+                    .iter()
+                    .filter_map(|t| t.txid.as_deref())
+                    .collect();
+                let min_amount = transfer.amount * (Decimal::ONE - DEPOSIT_MATCH_TOLERANCE);
+                let deposit = deposits.as_array().and_then(|deposits| {
+                    deposits.iter().find(|d| {
+                        let amount = decimal_field(d, "amount").unwrap_or_default();
+                        d["status"].as_str() == Some("Success")
+                            && d["time"].as_u64().unwrap_or(0) * 1000 >= transfer.updated
+                            && amount >= min_amount
+                            && amount <= transfer.amount
+                            && !claimed.contains(&d["txid"].as_str().unwrap_or_default())
+                    })
+                });
+                if let Some(deposit) = deposit {
+                    let txid = deposit["txid"].as_str().map(|t| t.to_string());
+                    let transfer = &mut self.transfers[i];
+                    transfer.txid = txid;
+                    transfer.set_status(TransferStatus::Credited, timestamp);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    //finished transfers are kept, the file doubles as a transfer log
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = Path::new(&self.state_file).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            &self.state_file,
+            serde_json::to_string_pretty(&self.transfers)?,
+        )?;
+        Ok(())
+    }
+}
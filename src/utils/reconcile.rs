@@ -0,0 +1,410 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use tokio::sync::RwLock;
+
+use crate::{
+    balance::{Balance, BalanceManager},
+    exchanges::{
+        binance::Binance,
+        kraken::{Kraken, KrakenUtils},
+        RestClient,
+    },
+    oms::Oms,
+    order::{Fill, OrderStatus, OrderUpdate},
+    portfolio::Portfolio,
+};
+
+//balances closer than this are considered equal, venues round differently
+const BALANCE_TOLERANCE: Decimal = dec!(0.00000001);
+//binance's answer to an order lookup for an id it has no record of
+const BINANCE_UNKNOWN_ORDER: i64 = -2013;
+
+//strategies only trade while the gate is open, name says who holds it in the logs
+#[derive(Debug, Default)]
+pub struct TradingGate {
+    name: &'static str,
+    open: AtomicBool,
+}
+
+impl TradingGate {
+    pub fn new(name: &'static str) -> TradingGate {
+        TradingGate {
+            name,
+            open: AtomicBool::new(false),
+        }
+    }
+
+    pub fn open(&self) {
+        if !self.open.swap(true, Ordering::SeqCst) {
+            println!("Trading enabled by {}", self.name);
+        }
+    }
+
+    pub fn close(&self) {
+        if self.open.swap(false, Ordering::SeqCst) {
+            println!("Trading paused by {}", self.name);
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+}
+
+//each variant carries the venue's view, which is what a repair copies in
+#[derive(Debug, Clone)]
+pub enum Discrepancy {
+    //open on the venue, unknown to the oms
+    UntrackedOrder(OrderUpdate),
+    //known to both with a different status
+    OrderState {
+        local: OrderStatus,
+        venue: OrderUpdate,
+    },
+    //open in the oms, the venue has no record of it even when asked for it directly.
+    //a repair closes it locally as expired
+    MissingOrder {
+        exchange: String,
+        order_id: String,
+    },
+    //in the venue's trade history, not in the portfolio
+    UnbookedFill(Fill),
+    BalanceMismatch {
+        local: Balance,
+        venue: Balance,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ReconcileReport {
+    pub timestamp: u64,
+    pub discrepancies: Vec<Discrepancy>,
+    pub repaired: bool,
+}
+
+impl ReconcileReport {
+    //trading resumes only on a consistent report
+    pub fn update_gate(&self, gate: &TradingGate) {
+        if self.is_consistent() {
+            gate.open();
+        } else {
+            gate.close();
+        }
+    }
+
+    //every discrepancy is fixed by a repair
+    pub fn is_consistent(&self) -> bool {
+        self.repaired || self.discrepancies.is_empty()
+    }
+
+    pub fn print(&self) {
+        for discrepancy in &self.discrepancies {
+            eprintln!("Reconcile discrepancy: {:?}", discrepancy);
+        }
+        if !self.discrepancies.is_empty() {
+            eprintln!(
+                "Reconcile found {} discrepancies, repaired: {}",
+                self.discrepancies.len(),
+                self.repaired
+            );
+        }
+    }
+}
+
+//what the venues say, fetched in one go before anything is compared
+struct VenueState {
+    orders: Vec<OrderUpdate>,
+    fills: Vec<Fill>,
+    balances: Vec<Balance>,
+    //local time the balance request went out, later fills are not in the snapshot
+    balances_timestamp: u64,
+}
+
+pub struct Reconciler {
+    pub repair: bool,
+    pub lookback_ms: u64,
+    pub assets_kraken: Vec<String>,
+    pub assets_binance: Vec<String>,
+    pub kraken: Kraken,
+    pub binance: Binance,
+}
+
+impl Reconciler {
+    //marks the trade history of the lookback as booked, the balances seeded at startup
+    //already contain it and booking it again would count it twice
+    pub async fn seed_fills(&self, portfolio: &RwLock<Portfolio>) -> Result<usize> {
+        let since = (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(self.lookback_ms);
+        let fills = self.fetch_fills(since).await?;
+        let mut portfolio = portfolio.write().await;
+        for fill in &fills {
+            portfolio.mark_booked(fill);
+        }
+        Ok(fills.len())
+    }
+
+    //the venues are fetched with no lock held, a slow REST call must not stall trading or
+    //the account stream. anything a stream event changed after the fetch started is newer
+    //than the venue's answer and is left out of the comparison, so a repair never undoes it
+    pub async fn run(
+        &self,
+        oms: &RwLock<Oms>,
+        portfolio: &RwLock<Portfolio>,
+        balances: &RwLock<BalanceManager>,
+    ) -> Result<ReconcileReport> {
+        //the fetch starts at timestamp, read under the lock so older updates are all in
+        let (timestamp, open_orders) = {
+            let oms = oms.read().await;
+            let open_orders: Vec<(String, String, String)> = oms
+                .all_open_orders()
+                .iter()
+                .filter_map(|order| {
+                    Some((
+                        order.exchange.clone(),
+                        order.order_id.clone()?,
+                        order.request.asset.replace("/", ""),
+                    ))
+                })
+                .collect();
+            (chrono::Utc::now().timestamp_millis() as u64, open_orders)
+        };
+        let since = timestamp.saturating_sub(self.lookback_ms);
+        let venue = self
+            .fetch_venue_state(&open_orders, since, timestamp)
+            .await?;
+
+        let mut oms = oms.write().await;
+        let mut portfolio = portfolio.write().await;
+        let mut balances = balances.write().await;
+
+        let mut discrepancies = Vec::new();
+        for update in &venue.orders {
+            match oms.get_by_update(update) {
+                None if update.status.is_some_and(|s| !s.is_final()) => {
+                    discrepancies.push(Discrepancy::UntrackedOrder(update.clone()))
+                }
+                Some(local) if local.updated <= timestamp => match update.status {
+                    Some(status) if status.is_final() != local.status.is_final() => discrepancies
+                        .push(Discrepancy::OrderState {
+                            local: local.status,
+                            venue: update.clone(),
+                        }),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        //orders acked since the fetch started were not asked for
+        for order in oms.all_open_orders() {
+            let order_id = match &order.order_id {
+                Some(order_id)
+                    if open_orders
+                        .iter()
+                        .any(|(e, id, _)| e == &order.exchange && id == order_id) =>
+                {
+                    order_id
+                }
+                _ => continue,
+            };
+            let known = venue
+                .orders
+                .iter()
+                .any(|u| &u.order_id == order_id && u.exchange == order.exchange);
+            if !known {
+                discrepancies.push(Discrepancy::MissingOrder {
+                    exchange: order.exchange.clone(),
+                    order_id: order_id.clone(),
+                });
+            }
+        }
+        for fill in &venue.fills {
+            if !portfolio.is_booked(&fill.exchange, &fill.trade_id) {
+                discrepancies.push(Discrepancy::UnbookedFill(fill.clone()));
+            }
+        }
+        for venue_balance in &venue.balances {
+            if balances.updated(&venue_balance.exchange, &venue_balance.currency)
+                >= venue.balances_timestamp
+            {
+                continue;
+            }
+            let local = balances.get(&venue_balance.exchange, &venue_balance.currency);
+            if (local.free - venue_balance.free).abs() > BALANCE_TOLERANCE
+                || (local.locked - venue_balance.locked).abs() > BALANCE_TOLERANCE
+            {
+                discrepancies.push(Discrepancy::BalanceMismatch {
+                    local,
+                    venue: venue_balance.clone(),
+                });
+            }
+        }
+
+        if self.repair && !discrepancies.is_empty() {
+            Reconciler::apply_repairs(
+                &discrepancies,
+                venue.balances_timestamp,
+                &mut oms,
+                &mut portfolio,
+                &mut balances,
+            );
+        }
+
+        Ok(ReconcileReport {
+            timestamp,
+            discrepancies,
+            repaired: self.repair,
+        })
+    }
+
+    //balances first, a fill that lands while the rest is fetched is then either in the
+    //trade history and after the balance snapshot, or in neither.
+    //open_orders are (exchange, order id, symbol) of the orders open before the fetch
+    async fn fetch_venue_state(
+        &self,
+        open_orders: &[(String, String, String)],
+        since: u64,
+        timestamp: u64,
+    ) -> Result<VenueState> {
+        let mut balances = Vec::new();
+        let balances_timestamp = chrono::Utc::now().timestamp_millis() as u64;
+        let response = self.kraken.query("BalanceEx", "").await;
+        balances.extend(
+            Balance::extract_balances_kraken_ex(&response).map_err(|e| anyhow!("Kraken: {}", e))?,
+        );
+        let response = self.binance.query("account", "").await;
+        balances.extend(
+            Balance::extract_balances_binance(&response).map_err(|e| anyhow!("Binance: {}", e))?,
+        );
+
+        let mut orders = Vec::new();
+
+        let response = self.kraken.query("OpenOrders", "").await;
+        orders.extend(OrderUpdate::deserialize_orders_kraken_rest(
+            &response, timestamp,
+        )?);
+        let body = format!("start={}", since / 1000);
+        for response in self.kraken_pages("ClosedOrders", &body, "closed").await? {
+            for update in OrderUpdate::deserialize_orders_kraken_rest(&response, timestamp)? {
+                if !orders.iter().any(|u| u.order_id == update.order_id) {
+                    orders.push(update);
+                }
+            }
+        }
+
+        let response = self.binance.query("openOrders", "").await;
+        orders.extend(OrderUpdate::deserialize_orders_binance_rest(&response)?);
+
+        //orders we think are open but the lists above don't mention are asked for directly
+        let unresolved: Vec<&(String, String, String)> = open_orders
+            .iter()
+            .filter(|(exchange, order_id, _)| {
+                !orders
+                    .iter()
+                    .any(|u| &u.order_id == order_id && &u.exchange == exchange)
+            })
+            .collect();
+
+        let kraken_ids: Vec<&str> = unresolved
+            .iter()
+            .filter(|(exchange, _, _)| exchange == "Kraken")
+            .map(|(_, order_id, _)| order_id.as_str())
+            .collect();
+        if !kraken_ids.is_empty() {
+            let body = format!("txid={}", kraken_ids.join(","));
+            let response = self.kraken.query("QueryOrders", &body).await;
+            orders.extend(OrderUpdate::deserialize_orders_kraken_rest(
+                &response, timestamp,
+            )?);
+        }
+        for (_, order_id, symbol) in unresolved.iter().filter(|(e, _, _)| e == "Binance") {
+            let body = format!("symbol={}&orderId={}", symbol, order_id);
+            let response = self.binance.query("order", &body).await;
+            let value: serde_json::Value = serde_json::from_str(&response)?;
+            if value["code"].as_i64() == Some(BINANCE_UNKNOWN_ORDER) {
+                continue;
+            }
+            orders.extend(OrderUpdate::deserialize_orders_binance_rest(&response)?);
+        }
+
+        let fills = self.fetch_fills(since).await?;
+
+        Ok(VenueState {
+            orders,
+            fills,
+            balances,
+            balances_timestamp,
+        })
+    }
+
+    async fn fetch_fills(&self, since: u64) -> Result<Vec<Fill>> {
+        let mut fills = Vec::new();
+        let body = format!("start={}", since / 1000);
+        for response in self.kraken_pages("TradesHistory", &body, "trades").await? {
+            for fill in Fill::deserialize_fills_kraken_rest(&response, &self.assets_kraken)? {
+                if !fills.iter().any(|f: &Fill| f.trade_id == fill.trade_id) {
+                    fills.push(fill);
+                }
+            }
+        }
+        for asset in &self.assets_binance {
+            let body = format!("symbol={}&startTime={}", asset.replace("/", ""), since);
+            let response = self.binance.query("myTrades", &body).await;
+            fills.extend(Fill::deserialize_fills_binance_rest(&response)?);
+        }
+        Ok(fills)
+    }
+
+    //kraken answers 50 entries at a time with the total in count, ofs skips what we have.
+    //entries arriving meanwhile shift the pages, which repeats entries but never skips one
+    async fn kraken_pages(&self, method: &str, body: &str, key: &str) -> Result<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut fetched = 0;
+        loop {
+            let response = self
+                .kraken
+                .query(method, &format!("{}&ofs={}", body, fetched))
+                .await;
+            let result = KrakenUtils::parse_rest_result(&response)?;
+            let count = result["count"].as_u64().unwrap_or_default();
+            let page = result[key].as_object().map_or(0, |entries| entries.len()) as u64;
+            pages.push(response);
+            fetched += page;
+            if page == 0 || fetched >= count {
+                return Ok(pages);
+            }
+        }
+    }
+
+    //balances are copied in before fills are booked, so fills the snapshot does not
+    //contain yet can be applied on top of it
+    fn apply_repairs(
+        discrepancies: &[Discrepancy],
+        balances_timestamp: u64,
+        oms: &mut Oms,
+        portfolio: &mut Portfolio,
+        balances: &mut BalanceManager,
+    ) {
+        for discrepancy in discrepancies {
+            match discrepancy {
+                Discrepancy::UntrackedOrder(venue) | Discrepancy::OrderState { venue, .. } => {
+                    oms.apply_update(venue)
+                }
+                Discrepancy::BalanceMismatch { venue, .. } => balances.set(venue.clone()),
+                Discrepancy::MissingOrder { exchange, order_id } => {
+                    oms.on_missing(exchange, order_id)
+                }
+                Discrepancy::UnbookedFill(_) => {}
+            }
+        }
+        for discrepancy in discrepancies {
+            if let Discrepancy::UnbookedFill(fill) = discrepancy {
+                if portfolio.book_fill(fill) && fill.timestamp >= balances_timestamp {
+                    balances.apply_fill(fill);
+                }
+                oms.apply_fill(fill);
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::{ids::VenueId, tick::Tick};
+
+//windows are in ms of timestamp2, like the tick buffers
+#[derive(Debug, Clone, Copy)]
+pub struct StatsConfig {
+    pub window_ms: u64,
+    pub ewma_half_life_ms: u64,
+    //the ratio is only sampled when both legs ticked this close together
+    pub max_time_diff_ms: u64,
+}
+
+//mean and variance of the values seen in the last window_ms, welford updates in both directions
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    window_ms: u64,
+    values: VecDeque<(u64, f64)>,
+    mean: f64,
+    m2: f64,
+}
+
+impl RollingStats {
+    pub fn new(window_ms: u64) -> RollingStats {
+        RollingStats {
+            window_ms,
+            values: VecDeque::new(),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, timestamp: u64, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        self.values.push_back((timestamp, value));
+        let n = self.values.len() as f64;
+        let delta = value - self.mean;
+        self.mean += delta / n;
+        self.m2 += delta * (value - self.mean);
+        self.expire(timestamp);
+    }
+
+    //drops values older than the window, called on every update
+    pub fn expire(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.window_ms);
+        while let Some(&(timestamp, value)) = self.values.front() {
+            if timestamp >= cutoff {
+                break;
+            }
+            self.values.pop_front();
+            if self.values.is_empty() {
+                self.mean = 0.0;
+                self.m2 = 0.0;
+                break;
+            }
+            let n = self.values.len() as f64;
+            let delta = value - self.mean;
+            self.mean -= delta / n;
+            self.m2 -= delta * (value - self.mean);
+        }
+        self.m2 = self.m2.max(0.0);
+    }
+
+    pub fn count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (!self.values.is_empty()).then_some(self.mean)
+    }
+
+    //sample variance, needs two values
+    pub fn variance(&self) -> Option<f64> {
+        (self.values.len() > 1).then(|| self.m2 / (self.values.len() - 1) as f64)
+    }
+
+    pub fn std(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn sum_of_squares(&self) -> f64 {
+        self.m2 + self.values.len() as f64 * self.mean * self.mean
+    }
+
+    //how many standard deviations the value is from the window mean
+    pub fn zscore(&self, value: f64) -> Option<f64> {
+        let std = self.std()?;
+        (std > 0.0).then(|| (value - self.mean) / std)
+    }
+}
+
+//exponentially weighted mean and variance, weights decay with time rather than with ticks
+//so bursts of ticks don't shorten the memory
+#[derive(Debug, Clone)]
+pub struct Ewma {
+    half_life_ms: u64,
+    last: Option<u64>,
+    mean: f64,
+    variance: f64,
+}
+
+impl Ewma {
+    pub fn new(half_life_ms: u64) -> Ewma {
+        Ewma {
+            half_life_ms,
+            last: None,
+            mean: 0.0,
+            variance: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, timestamp: u64, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some(timestamp);
+                self.mean = value;
+                return;
+            }
+        };
+        let dt = timestamp.saturating_sub(last) as f64;
+        let alpha = 1.0 - (-dt * std::f64::consts::LN_2 / self.half_life_ms.max(1) as f64).exp();
+        let delta = value - self.mean;
+        self.mean += alpha * delta;
+        self.variance = (1.0 - alpha) * (self.variance + alpha * delta * delta);
+        self.last = Some(timestamp.max(last));
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.last.map(|_| self.mean)
+    }
+
+    pub fn std(&self) -> Option<f64> {
+        self.last.map(|_| self.variance.sqrt())
+    }
+
+    pub fn zscore(&self, value: f64) -> Option<f64> {
+        let std = self.std()?;
+        (std > 0.0).then(|| (value - self.mean) / std)
+    }
+}
+
+//one venue's price: rolling and ewma level, and realized volatility of tick to tick log returns
+#[derive(Debug, Clone)]
+pub struct TickStats {
+    pub price: RollingStats,
+    pub ewma: Ewma,
+    pub returns: RollingStats,
+    last_price: Option<f64>,
+}
+
+impl TickStats {
+    pub fn new(config: &StatsConfig) -> TickStats {
+        TickStats {
+            price: RollingStats::new(config.window_ms),
+            ewma: Ewma::new(config.ewma_half_life_ms),
+            returns: RollingStats::new(config.window_ms),
+            last_price: None,
+        }
+    }
+
+    pub fn update(&mut self, tick: &Tick) {
+        let price = match tick.avg.to_f64() {
+            Some(price) if price > 0.0 => price,
+            _ => return,
+        };
+        self.price.update(tick.timestamp2, price);
+        self.ewma.update(tick.timestamp2, price);
+        //quote streams repeat the mid, unchanged prices add nothing to volatility
+        if let Some(last_price) = self.last_price {
+            if price != last_price {
+                self.returns
+                    .update(tick.timestamp2, (price / last_price).ln());
+            }
+        }
+        self.returns.expire(tick.timestamp2);
+        self.last_price = Some(price);
+    }
+
+    //square root of the summed squared log returns in the window, not annualized
+    pub fn realized_vol(&self) -> f64 {
+        self.returns.sum_of_squares().sqrt()
+    }
+}
+
+//fast / slow price ratio, the number oneleg trades on
+#[derive(Debug, Clone)]
+pub struct RatioStats {
+    pub rolling: RollingStats,
+    pub ewma: Ewma,
+    max_time_diff_ms: u64,
+    fast: Option<(u64, f64)>,
+    slow: Option<(u64, f64)>,
+}
+
+impl RatioStats {
+    pub fn new(config: &StatsConfig) -> RatioStats {
+        RatioStats {
+            rolling: RollingStats::new(config.window_ms),
+            ewma: Ewma::new(config.ewma_half_life_ms),
+            max_time_diff_ms: config.max_time_diff_ms,
+            fast: None,
+            slow: None,
+        }
+    }
+
+    pub fn update(&mut self, tick: &Tick, is_fast: bool) {
+        let price = match tick.avg.to_f64() {
+            Some(price) if price > 0.0 => price,
+            _ => return,
+        };
+        if is_fast {
+            self.fast = Some((tick.timestamp2, price));
+        } else {
+            self.slow = Some((tick.timestamp2, price));
+        }
+        if let Some(ratio) = self.ratio() {
+            self.rolling.update(tick.timestamp2, ratio);
+            self.ewma.update(tick.timestamp2, ratio);
+        }
+    }
+
+    //latest ratio, if both legs are fresh enough to compare
+    pub fn ratio(&self) -> Option<f64> {
+        let ((fast_time, fast), (slow_time, slow)) = (self.fast?, self.slow?);
+        (fast_time.abs_diff(slow_time) < self.max_time_diff_ms).then(|| fast / slow)
+    }
+
+    pub fn zscore(&self) -> Option<f64> {
+        self.rolling.zscore(self.ratio()?)
+    }
+
+    //stds standard deviations of the ratio, as a norm_gap oneleg can use
+    pub fn adaptive_gap(&self, stds: f64) -> Option<Decimal> {
+        Decimal::try_from(stds * self.rolling.std()?).ok()
+    }
+}
+
+//stats for every venue fed to it, plus the ratio between the fast and slow one
+#[derive(Debug, Clone)]
+pub struct MarketStats {
+    config: StatsConfig,
+    fast_exchange: VenueId,
+    slow_exchange: VenueId,
+    pub venues: HashMap<VenueId, TickStats>,
+    pub ratio: RatioStats,
+}
+
+impl MarketStats {
+    pub fn new(config: StatsConfig, fast_exchange: &str, slow_exchange: &str) -> MarketStats {
+        MarketStats {
+            config,
+            fast_exchange: VenueId::intern(fast_exchange),
+            slow_exchange: VenueId::intern(slow_exchange),
+            venues: HashMap::new(),
+            ratio: RatioStats::new(&config),
+        }
+    }
+
+    pub fn update(&mut self, tick: &Tick) {
+        let config = &self.config;
+        self.venues
+            .entry(tick.exchange)
+            .or_insert_with(|| TickStats::new(config))
+            .update(tick);
+        if tick.exchange == self.fast_exchange {
+            self.ratio.update(tick, true);
+        } else if tick.exchange == self.slow_exchange {
+            self.ratio.update(tick, false);
+        }
+    }
+
+    pub fn venue(&self, exchange: VenueId) -> Option<&TickStats> {
+        self.venues.get(&exchange)
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use arrow_array::{
+    cast::AsArray, types::Decimal128Type, types::UInt64Type, ArrayRef, Decimal128Array,
+    RecordBatch, StringArray, UInt64Array,
+};
+use arrow_ipc::{reader::StreamReader, writer::StreamWriter};
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::{
+    bars::Bar,
+    ids::{SymbolId, VenueId},
+    tick::Tick,
+};
+
+//prices and quantities are stored as decimal128(38, 18), exact and readable as decimals
+//in pandas and polars
+const PRECISION: u8 = 38;
+const SCALE: u32 = 18;
+
+pub const TICKS: &str = "ticks";
+pub const BOOK: &str = "book";
+pub const BARS: &str = "bars";
+
+//one side of a book update, a qty of zero removes the level
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookRow {
+    pub timestamp2: u64,
+    pub exchange: String,
+    pub asset: String,
+    pub bid: bool,
+    pub price: Decimal,
+    pub qty: Decimal,
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    //quotes and trades, trades are the ticks with a volume
+    Tick(Tick),
+    Book(BookRow),
+    //finished bars of every kind, partitioned by the day they start
+    Bar(Bar),
+}
+
+impl Record {
+    fn partition(&self) -> Partition {
+        match self {
+            Record::Tick(tick) => Partition::new(
+                TICKS,
+                tick.exchange.name(),
+                tick.asset.name(),
+                tick.timestamp2,
+            ),
+            Record::Book(row) => Partition::new(BOOK, &row.exchange, &row.asset, row.timestamp2),
+            Record::Bar(bar) => Partition::new(BARS, &bar.exchange, &bar.asset, bar.start),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Partition {
+    table: &'static str,
+    exchange: String,
+    asset: String,
+    day: String,
+}
+
+impl Partition {
+    fn new(table: &'static str, exchange: &str, asset: &str, timestamp2: u64) -> Partition {
+        Partition {
+            table,
+            exchange: exchange.to_string(),
+            asset: asset.to_string(),
+            day: day(timestamp2),
+        }
+    }
+
+    fn dir(&self, root: &str) -> String {
+        format!(
+            "{}/{}",
+            partition_dir(root, self.table, &self.exchange, &self.asset),
+            self.day
+        )
+    }
+}
+
+//{root}/{table}/{exchange}/{base}-{quote}, one directory per utc day below it
+pub fn partition_dir(root: &str, table: &str, exchange: &str, asset: &str) -> String {
+    format!(
+        "{}/{}/{}/{}",
+        root,
+        table,
+        exchange,
+        asset.replace('/', "-")
+    )
+}
+
+fn day(timestamp: u64) -> String {
+    try_day(timestamp).unwrap_or_default()
+}
+
+fn try_day(timestamp: u64) -> Option<String> {
+    let timestamp = i64::try_from(timestamp).ok()?;
+    let datetime = chrono::DateTime::from_timestamp_millis(timestamp)?;
+    Some(datetime.format("%Y-%m-%d").to_string())
+}
+
+fn tick_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("timestamp2", DataType::UInt64, false),
+        Field::new("price", DataType::Decimal128(PRECISION, SCALE as i8), false),
+        Field::new(
+            "volume",
+            DataType::Decimal128(PRECISION, SCALE as i8),
+            false,
+        ),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("asset", DataType::Utf8, false),
+    ])
+}
+
+fn book_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("timestamp2", DataType::UInt64, false),
+        Field::new("side", DataType::Utf8, false),
+        Field::new("price", DataType::Decimal128(PRECISION, SCALE as i8), false),
+        Field::new("qty", DataType::Decimal128(PRECISION, SCALE as i8), false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("asset", DataType::Utf8, false),
+    ])
+}
+
+fn bar_schema() -> Schema {
+    let decimal = DataType::Decimal128(PRECISION, SCALE as i8);
+    Schema::new(vec![
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("start", DataType::UInt64, false),
+        Field::new("end", DataType::UInt64, false),
+        Field::new("open", decimal.clone(), false),
+        Field::new("high", decimal.clone(), false),
+        Field::new("low", decimal.clone(), false),
+        Field::new("close", decimal.clone(), false),
+        Field::new("volume", decimal, false),
+        Field::new("ticks", DataType::UInt64, false),
+        Field::new("exchange", DataType::Utf8, false),
+        Field::new("asset", DataType::Utf8, false),
+    ])
+}
+
+//more than SCALE decimals are rounded half to even, rust_decimal goes to 28 and would
+//otherwise lose the extra digits silently
+fn to_i128(value: Decimal) -> Result<i128> {
+    let value = value.round_dp_with_strategy(SCALE, RoundingStrategy::MidpointNearestEven);
+    value
+        .mantissa()
+        .checked_mul(10i128.pow(SCALE - value.scale()))
+        .ok_or_else(|| {
+            anyhow!(
+                "{} does not fit decimal128({}, {})",
+                value,
+                PRECISION,
+                SCALE
+            )
+        })
+}
+
+fn from_i128(value: i128) -> Result<Decimal> {
+    Decimal::try_from_i128_with_scale(value, SCALE)
+        .map(|value| value.normalize())
+        .map_err(|e| anyhow!("Invalid stored decimal: {}", e))
+}
+
+fn decimal_column(values: Vec<i128>) -> Result<ArrayRef> {
+    Ok(Arc::new(
+        Decimal128Array::from(values).with_precision_and_scale(PRECISION, SCALE as i8)?,
+    ))
+}
+
+fn tick_batch(ticks: &[Tick]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            ticks.iter().map(|t| t.timestamp),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            ticks.iter().map(|t| t.timestamp2),
+        )),
+        decimal_column(
+            ticks
+                .iter()
+                .map(|t| to_i128(t.avg))
+                .collect::<Result<_>>()?,
+        )?,
+        decimal_column(
+            ticks
+                .iter()
+                .map(|t| to_i128(t.volume))
+                .collect::<Result<_>>()?,
+        )?,
+        Arc::new(StringArray::from_iter_values(
+            ticks.iter().map(|t| t.exchange.name()),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            ticks.iter().map(|t| t.asset.name()),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(tick_schema()), columns)?)
+}
+
+fn book_batch(rows: &[BookRow]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|r| r.timestamp2),
+        )),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|r| {
+            if r.bid {
+                "bid"
+            } else {
+                "ask"
+            }
+        }))),
+        decimal_column(
+            rows.iter()
+                .map(|r| to_i128(r.price))
+                .collect::<Result<_>>()?,
+        )?,
+        decimal_column(rows.iter().map(|r| to_i128(r.qty)).collect::<Result<_>>()?)?,
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|r| &r.exchange),
+        )),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|r| &r.asset))),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(book_schema()), columns)?)
+}
+
+fn bar_batch(bars: &[Bar]) -> Result<RecordBatch> {
+    let prices = |price: fn(&Bar) -> Decimal| -> Result<ArrayRef> {
+        decimal_column(
+            bars.iter()
+                .map(|bar| to_i128(price(bar)))
+                .collect::<Result<_>>()?,
+        )
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(bars.iter().map(|b| &b.kind))),
+        Arc::new(UInt64Array::from_iter_values(bars.iter().map(|b| b.start))),
+        Arc::new(UInt64Array::from_iter_values(bars.iter().map(|b| b.end))),
+        prices(|b| b.open)?,
+        prices(|b| b.high)?,
+        prices(|b| b.low)?,
+        prices(|b| b.close)?,
+        prices(|b| b.volume)?,
+        Arc::new(UInt64Array::from_iter_values(
+            bars.iter().map(|b| b.ticks as u64),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            bars.iter().map(|b| &b.exchange),
+        )),
+        Arc::new(StringArray::from_iter_values(bars.iter().map(|b| &b.asset))),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(bar_schema()), columns)?)
+}
+
+//arrow ipc stream segments, {partition_dir}/{day}/{first timestamp2}.arrows. every flush
+//appends one record batch, so a segment is readable while it is still being written.
+//a new segment is started per process and day, the previous day's is closed on rollover
+pub struct TickStore {
+    root: String,
+    ticks: HashMap<Partition, Vec<Tick>>,
+    book: HashMap<Partition, Vec<BookRow>>,
+    bars: HashMap<Partition, Vec<Bar>>,
+    writers: HashMap<Partition, StreamWriter<BufWriter<File>>>,
+}
+
+impl TickStore {
+    pub fn new(root: &str) -> TickStore {
+        TickStore {
+            root: root.to_string(),
+            ticks: HashMap::new(),
+            book: HashMap::new(),
+            bars: HashMap::new(),
+            writers: HashMap::new(),
+        }
+    }
+
+    //buffered until the next flush
+    pub fn record(&mut self, record: Record) {
+        let partition = record.partition();
+        match record {
+            Record::Tick(tick) => self.ticks.entry(partition).or_default().push(tick),
+            Record::Book(row) => self.book.entry(partition).or_default().push(row),
+            Record::Bar(bar) => self.bars.entry(partition).or_default().push(bar),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        let ticks: Vec<(Partition, Vec<Tick>)> = self.ticks.drain().collect();
+        for (partition, ticks) in ticks {
+            if !ticks.is_empty() {
+                let batch = tick_batch(&ticks)?;
+                self.write(partition, ticks[0].timestamp2, &batch)?;
+            }
+        }
+        let book: Vec<(Partition, Vec<BookRow>)> = self.book.drain().collect();
+        for (partition, rows) in book {
+            if !rows.is_empty() {
+                let batch = book_batch(&rows)?;
+                self.write(partition, rows[0].timestamp2, &batch)?;
+            }
+        }
+        let bars: Vec<(Partition, Vec<Bar>)> = self.bars.drain().collect();
+        for (partition, bars) in bars {
+            if !bars.is_empty() {
+                let batch = bar_batch(&bars)?;
+                self.write(partition, bars[0].start, &batch)?;
+            }
+        }
+
+        //segments of past days are finished, late rows for them start a new segment
+        let today = day(chrono::Utc::now().timestamp_millis() as u64);
+        let past: Vec<Partition> = self
+            .writers
+            .keys()
+            .filter(|partition| partition.day < today)
+            .cloned()
+            .collect();
+        for partition in past {
+            if let Some(mut writer) = self.writers.remove(&partition) {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, partition: Partition, first: u64, batch: &RecordBatch) -> Result<()> {
+        if !self.writers.contains_key(&partition) {
+            let dir = partition.dir(&self.root);
+            fs::create_dir_all(&dir)?;
+            //a segment is never appended to by a second writer
+            let mut segment = first;
+            let file = loop {
+                match OpenOptions::new()
+                    .create_new(true)
+                    .write(true)
+                    .open(format!("{}/{}.arrows", dir, segment))
+                {
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => segment += 1,
+                    file => break file?,
+                }
+            };
+            let writer = StreamWriter::try_new(BufWriter::new(file), &batch.schema())?;
+            self.writers.insert(partition.clone(), writer);
+        }
+        let writer = self.writers.get_mut(&partition).unwrap();
+        writer.write(batch)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    //flushes and finishes every open segment
+    pub fn close(&mut self) -> Result<()> {
+        self.flush()?;
+        for (_, mut writer) in self.writers.drain() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+//record batches of every segment of the days overlapping [from, to), oldest first
+fn read_batches(dir: &str, from: u64, to: u64) -> Result<Vec<RecordBatch>> {
+    //an open ended range has no last day
+    let (first_day, last_day) = (day(from), try_day(to.saturating_sub(1)));
+    let mut days: Vec<String> = fs::read_dir(dir)
+        .map_err(|e| anyhow!("{}: {}", dir, e))?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|day| *day >= first_day && last_day.as_ref().is_none_or(|last| day <= last))
+        .collect();
+    days.sort();
+
+    let mut batches = Vec::new();
+    for day in days {
+        let mut segments: Vec<(u64, String)> = fs::read_dir(format!("{}/{}", dir, day))?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "arrows" {
+                    return None;
+                }
+                let first = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((first, path.to_str()?.to_string()))
+            })
+            .collect();
+        segments.sort();
+        for (_, path) in segments {
+            let reader = StreamReader::try_new(BufReader::new(File::open(&path)?), None)
+                .map_err(|e| anyhow!("{}: {}", path, e))?;
+            for batch in reader {
+                match batch {
+                    Ok(batch) => batches.push(batch),
+                    //the segment being written can end in a partial batch
+                    Err(e) => {
+                        eprintln!("Stopped reading {} at a partial batch: {}", path, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(batches)
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| anyhow!("Missing column {}", name))
+}
+
+//ticks with timestamp2 in [from, to) of one partition directory, ordered by timestamp2
+pub fn read_ticks(dir: &str, from: u64, to: u64) -> Result<Vec<Tick>> {
+    let mut ticks = Vec::new();
+    for batch in read_batches(dir, from, to)? {
+        let timestamp = column(&batch, "timestamp")?.as_primitive::<UInt64Type>();
+        let timestamp2 = column(&batch, "timestamp2")?.as_primitive::<UInt64Type>();
+        let price = column(&batch, "price")?.as_primitive::<Decimal128Type>();
+        let volume = column(&batch, "volume")?.as_primitive::<Decimal128Type>();
+        let exchange = column(&batch, "exchange")?.as_string::<i32>();
+        let asset = column(&batch, "asset")?.as_string::<i32>();
+        for i in 0..batch.num_rows() {
+            if timestamp2.value(i) < from || timestamp2.value(i) >= to {
+                continue;
+            }
+            ticks.push(Tick {
+                timestamp: timestamp.value(i),
+                timestamp2: timestamp2.value(i),
+                avg: from_i128(price.value(i))?,
+                exchange: VenueId::intern(exchange.value(i)),
+                asset: SymbolId::intern(asset.value(i)),
+                volume: from_i128(volume.value(i))?,
+            });
+        }
+    }
+    ticks.sort_by_key(|tick| tick.timestamp2);
+    Ok(ticks)
+}
+
+//book rows with timestamp2 in [from, to) of one partition directory, ordered by timestamp2
+pub fn read_book(dir: &str, from: u64, to: u64) -> Result<Vec<BookRow>> {
+    let mut rows = Vec::new();
+    for batch in read_batches(dir, from, to)? {
+        let timestamp2 = column(&batch, "timestamp2")?.as_primitive::<UInt64Type>();
+        let side = column(&batch, "side")?.as_string::<i32>();
+        let price = column(&batch, "price")?.as_primitive::<Decimal128Type>();
+        let qty = column(&batch, "qty")?.as_primitive::<Decimal128Type>();
+        let exchange = column(&batch, "exchange")?.as_string::<i32>();
+        let asset = column(&batch, "asset")?.as_string::<i32>();
+        for i in 0..batch.num_rows() {
+            if timestamp2.value(i) < from || timestamp2.value(i) >= to {
+                continue;
+            }
+            rows.push(BookRow {
+                timestamp2: timestamp2.value(i),
+                exchange: exchange.value(i).to_string(),
+                asset: asset.value(i).to_string(),
+                bid: side.value(i) == "bid",
+                price: from_i128(price.value(i))?,
+                qty: from_i128(qty.value(i))?,
+            });
+        }
+    }
+    rows.sort_by_key(|row| row.timestamp2);
+    Ok(rows)
+}
+
+//bars of every kind starting in [from, to) of one partition directory, ordered by start
+pub fn read_bars(dir: &str, from: u64, to: u64) -> Result<Vec<Bar>> {
+    let mut bars = Vec::new();
+    for batch in read_batches(dir, from, to)? {
+        let kind = column(&batch, "kind")?.as_string::<i32>();
+        let start = column(&batch, "start")?.as_primitive::<UInt64Type>();
+        let end = column(&batch, "end")?.as_primitive::<UInt64Type>();
+        let open = column(&batch, "open")?.as_primitive::<Decimal128Type>();
+        let high = column(&batch, "high")?.as_primitive::<Decimal128Type>();
+        let low = column(&batch, "low")?.as_primitive::<Decimal128Type>();
+        let close = column(&batch, "close")?.as_primitive::<Decimal128Type>();
+        let volume = column(&batch, "volume")?.as_primitive::<Decimal128Type>();
+        let ticks = column(&batch, "ticks")?.as_primitive::<UInt64Type>();
+        let exchange = column(&batch, "exchange")?.as_string::<i32>();
+        let asset = column(&batch, "asset")?.as_string::<i32>();
+        for i in 0..batch.num_rows() {
+            if start.value(i) < from || start.value(i) >= to {
+                continue;
+            }
+            bars.push(Bar {
+                exchange: exchange.value(i).to_string(),
+                asset: asset.value(i).to_string(),
+                kind: kind.value(i).to_string(),
+                start: start.value(i),
+                end: end.value(i),
+                open: from_i128(open.value(i))?,
+                high: from_i128(high.value(i))?,
+                low: from_i128(low.value(i))?,
+                close: from_i128(close.value(i))?,
+                volume: from_i128(volume.value(i))?,
+                ticks: ticks.value(i) as usize,
+            });
+        }
+    }
+    bars.sort_by_key(|bar| bar.start);
+    Ok(bars)
+}
+
+//a store partition directory or a jsonl recording, whichever the path is
+pub fn load_ticks(path: &str) -> Result<Vec<Tick>> {
+    if Path::new(path).is_dir() {
+        read_ticks(path, 0, u64::MAX)
+    } else {
+        Tick::read_jsonl(path)
+    }
+}
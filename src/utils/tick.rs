@@ -1,95 +1,146 @@
-use std::{fs::File, io::Write};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+};
 
 use anyhow::{anyhow, Result};
 use circular_buffer::CircularBuffer;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use rust_decimal::Decimal;
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::ids::{SymbolId, VenueId};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tick {
     pub timestamp: u64,
     pub timestamp2: u64,
-    pub avg: f64,
-    pub exchange: String,
-    pub asset: String,
+    pub avg: Decimal,
+    pub exchange: VenueId,
+    pub asset: SymbolId,
+    //traded quantity for trade ticks, zero for quote ticks like kraken's spread
+    #[serde(default)]
+    pub volume: Decimal,
 }
 #[derive(Debug, Clone)]
 pub struct TickBuffer<const SIZE: usize> {
     pub buffer: CircularBuffer<SIZE, Tick>,
-    pub exchange: String,
+    pub exchange: VenueId,
+}
+
+//frames are deserialized straight into these, the price strings borrow from the frame
+//kraken spread: [channelID, ["bid", "ask", "timestamp", "bidVolume", "askVolume"], "spread", "XBT/USD"]
+#[derive(Deserialize)]
+struct KrakenSpreadMessage<'a>(
+    IgnoredAny,
+    #[serde(borrow)] KrakenSpread<'a>,
+    IgnoredAny,
+    IgnoredAny,
+);
+
+#[derive(Deserialize)]
+struct KrakenSpread<'a>(&'a str, &'a str, IgnoredAny, IgnoredAny, IgnoredAny);
+
+//binance combined stream: {"stream": "pepeusdt@trade", "data": {"e": "trade", "p": "...", "T": ...}}
+#[derive(Deserialize)]
+struct BinanceStreamMessage<'a> {
+    #[serde(borrow)]
+    data: BinanceTrade<'a>,
+}
+
+#[derive(Deserialize)]
+struct BinanceTrade<'a> {
+    #[serde(rename = "p")]
+    price: &'a str,
+    #[serde(rename = "q")]
+    qty: &'a str,
+    #[serde(rename = "T")]
+    trade_time: u64,
 }
 
 impl Tick {
+    //the ids come from the caller, the hot path neither allocates nor takes the interner lock
     pub fn deserialize_tick_kraken(
         json_string: &str,
-        asset: String,
+        exchange: VenueId,
+        asset: SymbolId,
         timestamp2: u64,
     ) -> Result<Tick, anyhow::Error> {
-        let value: serde_json::Value = serde_json::from_str(json_string)
-            .map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
-
-        // Assuming the format is always as shown, with prices at specific indices
-        if let Some(array) = value.as_array() {
-            // Safely access elements within the nested structure
-            if array.len() > 1 && array[1].is_array() {
-                let prices = &array[1].as_array().unwrap();
-
-                if prices.len() >= 2 {
-                    let bid: f64 = prices[0]
-                        .as_str()
-                        .and_then(|s| s.parse().ok())
-                        .ok_or_else(|| anyhow!("Invalid format for bid price"))?;
-                    let ask: f64 = prices[1]
-                        .as_str()
-                        .and_then(|s| s.parse().ok())
-                        .ok_or_else(|| anyhow!("Invalid format for ask price"))?;
-
-                    let avg = (bid + ask) / 2.0;
-
-                    // Construct the Tick object here with 'avg' and other necessary fields
-                    return Ok(Tick {
-                        exchange: "Kraken".to_string(),
-                        timestamp: 0, // Set appropriate timestamp
-                        avg,
-                        asset,
-                        timestamp2,
-                    });
-                }
-            }
-        }
+        let message: KrakenSpreadMessage = serde_json::from_str(json_string)
+            .map_err(|e| anyhow!("Invalid format for Kraken message: {}", e))?;
+        let KrakenSpread(bid, ask, ..) = message.1;
 
-        Err(anyhow!("Invalid format for Kraken message"))
-    }
+        let bid: Decimal = bid
+            .parse()
+            .map_err(|_| anyhow!("Invalid format for bid price"))?;
+        let ask: Decimal = ask
+            .parse()
+            .map_err(|_| anyhow!("Invalid format for ask price"))?;
 
-    pub fn deserialize_tick_binance(message: &str, asset: String, timestamp2: u64) -> Result<Tick> {
-        let value = serde_json::from_str::<Value>(message)
-            .map_err(|e| anyhow!("Deserialization error: {}", e))?;
-        let data = value.get("data").ok_or_else(|| anyhow!("Missing data"))?;
+        Ok(Tick {
+            exchange,
+            timestamp: 0, // Set appropriate timestamp
+            avg: (bid + ask) / Decimal::TWO,
+            asset,
+            timestamp2,
+            volume: Decimal::ZERO,
+        })
+    }
 
-        let price = data["p"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Missing price"))?
-            .parse::<f64>()
+    pub fn deserialize_tick_binance(
+        message: &str,
+        exchange: VenueId,
+        asset: SymbolId,
+        timestamp2: u64,
+    ) -> Result<Tick> {
+        let message: BinanceStreamMessage =
+            serde_json::from_str(message).map_err(|e| anyhow!("Deserialization error: {}", e))?;
+
+        let price = message
+            .data
+            .price
+            .parse::<Decimal>()
             .map_err(|_| anyhow!("Invalid price format"))?;
-        let timestamp = data["T"]
-            .as_u64()
-            .ok_or_else(|| anyhow!("Missing timestamp"))?;
+        let volume = message
+            .data
+            .qty
+            .parse::<Decimal>()
+            .map_err(|_| anyhow!("Invalid quantity format"))?;
 
         Ok(Tick {
-            exchange: "Binance".to_string(),
+            exchange,
             avg: price,
-            timestamp,
+            timestamp: message.data.trade_time,
             timestamp2,
             asset,
+            volume,
         })
     }
 }
 
+impl Tick {
+    //legacy recordings from before the arrow store, one json tick per line
+    pub fn read_jsonl(file_path: &str) -> Result<Vec<Tick>> {
+        let reader = BufReader::new(File::open(file_path)?);
+        let mut ticks = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let tick: Tick = serde_json::from_str(&line)
+                .map_err(|e| anyhow!("{} line {}: {}", file_path, i + 1, e))?;
+            ticks.push(tick);
+        }
+        Ok(ticks)
+    }
+}
+
 impl<const SIZE: usize> TickBuffer<SIZE> {
-    pub fn new(exchange: String) -> TickBuffer<SIZE> {
+    pub fn new(exchange: VenueId) -> TickBuffer<SIZE> {
         TickBuffer {
             buffer: CircularBuffer::<SIZE, Tick>::new(),
-            exchange: exchange,
+            exchange,
         }
     }
 
@@ -105,9 +156,7 @@ impl<const SIZE: usize> TickBuffer<SIZE> {
     }
 
     pub fn save_to_file(&self, file_path: &str) -> std::io::Result<()> {
-        let json_string = self
-            .serialize_to_json()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let json_string = self.serialize_to_json().map_err(std::io::Error::other)?;
 
         //handle case if directory does not exist
 
@@ -116,3 +165,89 @@ impl<const SIZE: usize> TickBuffer<SIZE> {
         Ok(())
     }
 }
+
+//ticks of one exchange ordered by timestamp2, kept for retention_ms behind the newest one
+#[derive(Debug, Clone)]
+pub struct TickHistory {
+    pub exchange: VenueId,
+    pub retention_ms: u64,
+    ticks: VecDeque<Tick>,
+}
+
+impl TickHistory {
+    pub fn new(exchange: VenueId, retention_ms: u64) -> TickHistory {
+        TickHistory {
+            exchange,
+            retention_ms,
+            ticks: VecDeque::new(),
+        }
+    }
+
+    //late ticks are inserted in order, ticks older than the retention are dropped
+    pub fn add_tick(&mut self, tick: Tick) {
+        if self.exchange != tick.exchange {
+            return;
+        }
+        match self.ticks.back() {
+            Some(back) if tick.timestamp2 < back.timestamp2 => {
+                let i = self
+                    .ticks
+                    .partition_point(|t| t.timestamp2 <= tick.timestamp2);
+                self.ticks.insert(i, tick);
+            }
+            _ => self.ticks.push_back(tick),
+        }
+        let cutoff = self
+            .ticks
+            .back()
+            .map_or(0, |back| back.timestamp2.saturating_sub(self.retention_ms));
+        while self.ticks.front().is_some_and(|t| t.timestamp2 < cutoff) {
+            self.ticks.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    pub fn back(&self) -> Option<&Tick> {
+        self.ticks.back()
+    }
+
+    //the last tick at or before ts
+    pub fn tick_at(&self, ts: u64) -> Option<&Tick> {
+        let i = self.ticks.partition_point(|t| t.timestamp2 <= ts);
+        if i == 0 {
+            None
+        } else {
+            self.ticks.get(i - 1)
+        }
+    }
+
+    //price as of ts, none before the first retained tick
+    pub fn price_at(&self, ts: u64) -> Option<Decimal> {
+        self.tick_at(ts).map(|t| t.avg)
+    }
+
+    //ticks in [from, to)
+    pub fn range(&self, from: u64, to: u64) -> impl Iterator<Item = &Tick> {
+        let first = self.ticks.partition_point(|t| t.timestamp2 < from);
+        let last = self.ticks.partition_point(|t| t.timestamp2 < to).max(first);
+        self.ticks.range(first..last)
+    }
+
+    //false once no tick is younger than max_age_ms, now must be on the timestamp2 clock
+    pub fn is_fresh(&self, now: u64, max_age_ms: u64) -> bool {
+        self.since(now.saturating_sub(max_age_ms)).next().is_some()
+    }
+
+    //ticks at or after ts
+    pub fn since(&self, ts: u64) -> impl Iterator<Item = &Tick> {
+        let first = self.ticks.partition_point(|t| t.timestamp2 < ts);
+        self.ticks.range(first..)
+    }
+}
@@ -0,0 +1,176 @@
+use rust_decimal::Decimal;
+use sdla::utils::{
+    analytics::{cross_correlation, follow_stats, gap_stats, spread_series, SpreadPoint},
+    ids::{SymbolId, VenueId},
+    tick::Tick,
+};
+
+fn tick(exchange: &str, timestamp2: u64, price: f64) -> Tick {
+    Tick {
+        timestamp: timestamp2,
+        timestamp2,
+        avg: Decimal::try_from(price).unwrap(),
+        exchange: VenueId::intern(exchange),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+fn spread_point(spread: f64) -> SpreadPoint {
+    SpreadPoint {
+        timestamp: 0,
+        kraken: 1.0,
+        binance: 1.0 + spread,
+        spread,
+    }
+}
+
+//a random walk from a fixed seed, one price every 100 ms
+fn walk(steps: usize) -> Vec<f64> {
+    let mut seed = 42u64;
+    let mut price = 100.0;
+    (0..steps)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let r = (seed >> 33) as f64 / (1u64 << 31) as f64;
+            price *= 1.0 + (r - 0.5) / 50.0;
+            price
+        })
+        .collect()
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn spread_is_sampled_where_both_venues_have_a_price() {
+    //out of order on purpose, both sides are sorted by timestamp2 first
+    let kraken = [
+        tick("Kraken", 1150, 102.0),
+        tick("Kraken", 1000, 100.0),
+        tick("Kraken", 1400, 104.0),
+    ];
+    let binance = [
+        tick("Binance", 1300, 103.0),
+        tick("Binance", 1100, 101.0),
+        tick("Binance", 1500, 105.0),
+    ];
+    let series = spread_series(&kraken, &binance, 100);
+
+    //from the later first tick to the earlier last one, last price as of each step
+    let expected = [
+        (1100, 100.0, 101.0),
+        (1200, 102.0, 101.0),
+        (1300, 102.0, 103.0),
+        (1400, 104.0, 103.0),
+    ];
+    assert_eq!(series.len(), expected.len());
+    for (point, (timestamp, k, b)) in series.iter().zip(expected) {
+        assert_eq!(point.timestamp, timestamp);
+        assert_eq!(point.kraken, k);
+        assert_eq!(point.binance, b);
+        assert_close(point.spread, b / k - 1.0);
+    }
+
+    assert!(spread_series(&kraken, &[], 100).is_empty());
+}
+
+#[test]
+fn correlation_peaks_at_the_lag_between_the_venues() {
+    //Kraken repeats every Binance price 300 ms later
+    let prices = walk(200);
+    let binance: Vec<Tick> = prices
+        .iter()
+        .enumerate()
+        .map(|(i, p)| tick("Binance", 10_000 + i as u64 * 100, *p))
+        .collect();
+    let kraken: Vec<Tick> = prices
+        .iter()
+        .enumerate()
+        .map(|(i, p)| tick("Kraken", 10_300 + i as u64 * 100, *p))
+        .collect();
+
+    let series = spread_series(&kraken, &binance, 100);
+    let correlations = cross_correlation(&series, 100, 500);
+    assert_eq!(correlations.len(), 11);
+    assert_eq!(correlations[0].lag_ms, -500);
+    assert_eq!(correlations[10].lag_ms, 500);
+
+    let best = correlations
+        .iter()
+        .max_by(|a, b| a.correlation.total_cmp(&b.correlation))
+        .unwrap();
+    assert_eq!(best.lag_ms, 300);
+    assert_close(best.correlation, 1.0);
+
+    //the other way round Kraken leads, the peak moves to the negative side
+    let series = spread_series(&binance, &kraken, 100);
+    let best = cross_correlation(&series, 100, 500)
+        .into_iter()
+        .max_by(|a, b| a.correlation.total_cmp(&b.correlation))
+        .unwrap();
+    assert_eq!(best.lag_ms, -300);
+}
+
+#[test]
+fn gaps_are_runs_above_the_threshold() {
+    //at the threshold is not above it, a run still open at the end counts
+    let spreads = [0.0, 0.02, 0.03, 0.01, -0.02, 0.0, 0.015, -0.02, 0.02];
+    let series: Vec<SpreadPoint> = spreads.iter().map(|s| spread_point(*s)).collect();
+    let gaps = gap_stats(&series, 0.01, 100);
+
+    assert_eq!(gaps.durations_ms, [200, 100, 300]);
+    assert_eq!(gaps.count, 3);
+    assert_eq!(gaps.total_ms, 600);
+    assert_close(gaps.mean_ms, 200.0);
+    assert_eq!(gaps.p50_ms, 200);
+    assert_eq!(gaps.p90_ms, 300);
+    assert_eq!(gaps.p99_ms, 300);
+    assert_eq!(gaps.max_ms, 300);
+
+    let none = gap_stats(&series, 0.05, 100);
+    assert_eq!(none.count, 0);
+    assert_eq!(none.max_ms, 0);
+}
+
+#[test]
+fn binance_moves_are_followed_opposed_or_ignored() {
+    let binance = [
+        tick("Binance", 1000, 100.0),
+        //up 1%, Kraken follows 300 ms later
+        tick("Binance", 2000, 101.0),
+        //down, Kraken moves up instead
+        tick("Binance", 3000, 100.0),
+        //below min_move, not a move
+        tick("Binance", 4000, 100.0001),
+        //up 2%, Kraken only moves after the window
+        tick("Binance", 5000, 102.0),
+    ];
+    let kraken = [
+        tick("Kraken", 500, 50.0),
+        tick("Kraken", 2300, 50.5),
+        tick("Kraken", 3200, 50.6),
+        tick("Kraken", 5600, 51.0),
+    ];
+    let stats = follow_stats(&kraken, &binance, 0.001, 500);
+
+    assert_eq!(stats.binance_moves, 3);
+    assert_eq!(stats.followed, 1);
+    assert_eq!(stats.opposite, 1);
+    assert_eq!(stats.no_kraken_move, 1);
+    assert_close(stats.follow_rate, 1.0 / 3.0);
+    assert_close(stats.mean_delay_ms, 300.0);
+
+    //a wider window catches the late Kraken move too
+    let stats = follow_stats(&kraken, &binance, 0.001, 1000);
+    assert_eq!(stats.followed, 2);
+    assert_close(stats.mean_delay_ms, 450.0);
+}
@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::{
+    strats::{
+        backtest::{self, BacktestConfig, MarketData, OnelegParams},
+        optimize::{sweep, walk_forward_splits},
+    },
+    utils::{
+        ids::{SymbolId, VenueId},
+        order::Side,
+        tick::Tick,
+    },
+};
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+fn tick(exchange: &str, timestamp2: u64, price: &str) -> Tick {
+    Tick {
+        timestamp: timestamp2,
+        timestamp2,
+        avg: dec(price),
+        exchange: VenueId::intern(exchange),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+//half the balance per trade, past 1% + fee, out a second later
+fn params() -> OnelegParams {
+    OnelegParams {
+        norm_trade_size: dec("0.5"),
+        norm_gap: dec("0.01"),
+        time_gap_ms: 1000,
+        max_time_diff_ms: 500,
+    }
+}
+
+//2000 of capital at the first slow price
+fn config() -> BacktestConfig {
+    BacktestConfig {
+        round_trip_fee: dec("0.002"),
+        initial_quote: dec("1000"),
+        initial_base: dec("100"),
+        sharpe_bucket_ms: 1000,
+    }
+}
+
+#[test]
+fn replay_matches_the_hand_computed_round_trips() {
+    let fast = [
+        //1.005, inside the 1.2% threshold
+        tick("Binance", 100, "10.05"),
+        //1.02, buy 1000 * 0.5 / 10 = 50 on the slow leg
+        tick("Binance", 200, "10.2"),
+        //still in the first round trip
+        tick("Binance", 1150, "10.3"),
+        //10 / 10.3, sell 100 * 0.5 = 50
+        tick("Binance", 1300, "10.0"),
+        //the slow price is 600 ms old, no trade
+        tick("Binance", 2600, "11.0"),
+    ];
+    let slow = [
+        tick("Kraken", 0, "10"),
+        tick("Kraken", 1100, "10.3"),
+        tick("Kraken", 2000, "10.5"),
+    ];
+    let data = MarketData::new(&fast, &slow);
+    let result = backtest::run(&data, &params(), &config());
+
+    //buy 50 at 10, out at 10.3 by 1200: 15 - (500 + 515) * 0.001
+    //sell 50 at 10.3, out at 10.5 by 2300: -10 - (515 + 525) * 0.001
+    assert_eq!(result.trades, 2);
+    let first = &result.trade_log[0];
+    assert_eq!(
+        (first.entry_time, first.exit_time, first.side),
+        (200, 1200, Side::Buy)
+    );
+    assert_eq!(first.qty, dec("50"));
+    assert_eq!(
+        (first.entry_price, first.exit_price),
+        (dec("10"), dec("10.3"))
+    );
+    assert_eq!(first.fee, dec("1.015"));
+    assert_eq!(first.pnl, dec("13.985"));
+    let second = &result.trade_log[1];
+    assert_eq!(
+        (second.entry_time, second.exit_time, second.side),
+        (1300, 2300, Side::Sell)
+    );
+    assert_eq!(second.qty, dec("50"));
+    assert_eq!(second.fee, dec("1.04"));
+    assert_eq!(second.pnl, dec("-11.04"));
+
+    assert_eq!(result.pnl, dec("2.945"));
+    assert_eq!(result.fees, dec("2.055"));
+    assert_eq!(result.wins, 1);
+
+    //from the 13.985 peak down to 2.945, on 2000 of capital
+    assert!((result.max_drawdown - 11.04 / 2000.0).abs() < 1e-12);
+
+    //three 1s buckets from 0 to 2600: nothing, the win, the loss, annualized
+    let returns = [0.0, 13.985 / 2000.0, -11.04 / 2000.0];
+    let mean = returns.iter().sum::<f64>() / 3.0;
+    let std = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 3.0).sqrt();
+    let year_buckets = 365.0 * 24.0 * 60.0 * 60.0;
+    assert!((result.sharpe - mean / std * f64::sqrt(year_buckets)).abs() < 1e-6);
+    assert!((result.sharpe - 538.355).abs() < 1e-3);
+
+    //same data, same result
+    let again = backtest::run(&data, &params(), &config());
+    assert_eq!(again.pnl, result.pnl);
+    assert_eq!(again.sharpe, result.sharpe);
+}
+
+#[test]
+fn walk_forward_windows_do_not_overlap() {
+    //a tick every 10 ms on both legs, the slow one 5 ms behind, 0 to 9995
+    let fast: Vec<Tick> = (0..1000).map(|i| tick("Binance", i * 10, "10")).collect();
+    let slow: Vec<Tick> = (0..1000)
+        .map(|i| tick("Kraken", i * 10 + 5, "10"))
+        .collect();
+    let data = MarketData::new(&fast, &slow);
+
+    let splits = walk_forward_splits(&data, 4);
+    assert_eq!(splits.len(), 4);
+    for split in &splits {
+        //half open windows, testing starts where training stopped
+        assert!(split.train_from < split.train_to);
+        assert_eq!(split.train_to, split.test_from);
+        assert!(split.test_from < split.test_to);
+
+        let train = data.window(split.train_from, split.train_to);
+        let test = data.window(split.test_from, split.test_to);
+        assert!(!train.is_empty() && !test.is_empty());
+        assert!(train.end() < test.start());
+    }
+    for pair in splits.windows(2) {
+        //rolling: each test slice is the next split's training slice
+        assert_eq!(pair[0].test_from, pair[1].train_from);
+        assert_eq!(pair[0].test_to, pair[1].train_to);
+        assert!(pair[0].train_to <= pair[1].train_from);
+    }
+    assert_eq!(splits[0].train_from, data.start());
+    //the last test window runs through the last tick
+    assert_eq!(splits[3].test_to, data.end() + 1);
+    assert_eq!(
+        data.window(splits[3].test_from, splits[3].test_to).end(),
+        9995
+    );
+
+    //the sweep reports the same splits, in order
+    let report = sweep(&data, &[params()], &config(), 4, 2).unwrap();
+    let reported: Vec<(u64, u64)> = report
+        .walk_forward
+        .iter()
+        .map(|step| (step.split.train_from, step.split.test_to))
+        .collect();
+    let expected: Vec<(u64, u64)> = splits
+        .iter()
+        .map(|split| (split.train_from, split.test_to))
+        .collect();
+    assert_eq!(reported, expected);
+}
@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::utils::{
+    bars::{BarKind, BarManager},
+    ids::{SymbolId, VenueId},
+    tick::Tick,
+};
+
+fn tick(timestamp2: u64, avg: &str) -> Tick {
+    Tick {
+        timestamp: timestamp2,
+        timestamp2,
+        avg: Decimal::from_str(avg).unwrap(),
+        exchange: VenueId::intern("Kraken"),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+#[test]
+fn late_ticks_for_a_finished_time_bar_are_dropped_and_counted() {
+    let kind = BarKind::Time(1000);
+    let mut bars = BarManager::new(vec![kind], 10);
+    assert!(bars.update(&tick(1000, "10")).is_empty());
+    assert!(bars.update(&tick(1500, "11")).is_empty());
+    let done = bars.update(&tick(2100, "12"));
+    assert_eq!(done.len(), 1);
+    assert_eq!((done[0].start, done[0].end), (1000, 2000));
+    assert_eq!(done[0].close, Decimal::from(11));
+
+    //belongs to the bar already out, and must not reach the one being built
+    assert!(bars.update(&tick(1900, "1")).is_empty());
+    assert_eq!(bars.late("Kraken", "PEPE/USD", &kind), 1);
+    let current = bars.current("Kraken", "PEPE/USD", &kind).unwrap();
+    assert_eq!((current.low, current.ticks), (Decimal::from(12), 1));
+
+    //late within the current bucket is still part of it
+    assert!(bars.update(&tick(2050, "13")).is_empty());
+    let current = bars.current("Kraken", "PEPE/USD", &kind).unwrap();
+    assert_eq!(
+        (current.high, current.close),
+        (Decimal::from(13), Decimal::from(13))
+    );
+    assert_eq!(bars.late("Kraken", "PEPE/USD", &kind), 1);
+}
+
+#[test]
+fn tick_bars_close_on_the_count() {
+    let kind = BarKind::Ticks(2);
+    let mut bars = BarManager::new(vec![kind], 1);
+    for (timestamp2, avg) in [(1000, "1"), (1001, "2"), (1002, "3"), (1003, "4")] {
+        bars.update(&tick(timestamp2, avg));
+    }
+    //the buffer keeps only the newest bar
+    let kept = bars.bars("Kraken", "PEPE/USD", &kind).unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(
+        (kept[0].open, kept[0].close),
+        (Decimal::from(3), Decimal::from(4))
+    );
+    assert_eq!(bars.late("Kraken", "PEPE/USD", &kind), 0);
+}
@@ -0,0 +1,303 @@
+mod mock_exchange;
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use mock_exchange::{
+    binance_signature, configure_clients, wait_for_account_event, wait_until, BINANCE_API_SECRET,
+};
+use rust_decimal::Decimal;
+use sdla::{
+    exchanges::{
+        binance::{BinanceClient, BinanceConfig},
+        feed::{connect_and_run, connect_and_run_private},
+        ExchangeEvent, ExchangeMessage, RestClient,
+    },
+    utils::{
+        balance::{Balance, BalanceManager},
+        fees::{FeeRate, FeeSchedule},
+        ids::{SymbolId, VenueId},
+        importer::{Importer, Source},
+        instrument::InstrumentRegistry,
+        order::{AccountEvent, Fill, Side},
+        store,
+    },
+};
+use tokio::sync::{mpsc, Notify};
+
+#[tokio::test]
+async fn public_request() {
+    configure_clients();
+    let response = BinanceClient::api_request("exchangeInfo", "", None)
+        .await
+        .unwrap();
+    let instruments = InstrumentRegistry::parse_binance_exchange_info(&response).unwrap();
+    assert_eq!(instruments[0].asset, "PEPE/USDT");
+    assert_eq!(instruments[0].lot_size, Decimal::ONE);
+}
+
+#[tokio::test]
+async fn signed_private_request_is_accepted() {
+    let (_, binance) = configure_clients();
+    let response = binance.query("account", "").await;
+    let balances = Balance::extract_balances_binance(&response).unwrap();
+    let pepe = balances.iter().find(|b| b.currency == "PEPE").unwrap();
+    assert_eq!(pepe.free, Decimal::from(50_000_000));
+    assert_eq!(pepe.locked, Decimal::from(1_000_000));
+}
+
+#[tokio::test]
+async fn signed_request_with_parameters_is_accepted() {
+    let (_, binance) = configure_clients();
+    let response = binance.query("myTrades", "symbol=PEPEUSDT").await;
+    let fills = Fill::deserialize_fills_binance_rest(&response).unwrap();
+    assert_eq!(fills.len(), 1);
+    assert_eq!(fills[0].asset, "PEPE/USDT");
+    assert_eq!(fills[0].side, Side::Buy);
+    assert_eq!(fills[0].price, Decimal::from_str("0.00001148").unwrap());
+}
+
+#[tokio::test]
+async fn bad_signature_is_rejected() {
+    let mock = mock_exchange::start();
+    let query = "timestamp=1713447297462";
+    let signature = binance_signature("timestamp=1", BINANCE_API_SECRET);
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/api/v3/account?{}&signature={}",
+            mock.http_url, query, signature
+        ))
+        .header("X-MBX-APIKEY", mock_exchange::API_KEY)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(response.contains("-1022"), "{}", response);
+}
+
+#[tokio::test]
+async fn key_permissions_come_from_api_restrictions() {
+    let (_, binance) = configure_clients();
+    let permissions = binance.key_permissions().await.unwrap();
+    assert!(permissions.query_funds && permissions.trade);
+    assert!(!permissions.withdraw);
+    assert_eq!(permissions.ip_restricted, Some(false));
+    assert!(permissions.check(false).is_ok());
+}
+
+#[test]
+fn account_fees_prefer_commission_rates_over_basis_points() {
+    let assets = vec!["PEPE/USDT".to_string()];
+    let dec = |value: &str| Decimal::from_str(value).unwrap();
+
+    //both present, the legacy fields are stale and ignored
+    let current = r#"{"makerCommission": 10, "takerCommission": 10,
+        "commissionRates": {"maker": "0.00075000", "taker": "0.00090000",
+            "buyer": "0.00000000", "seller": "0.00000000"}}"#;
+    let tiers = FeeSchedule::parse_binance_account(current, &assets).unwrap();
+    assert_eq!(tiers.len(), 1);
+    assert_eq!(tiers[0].asset, "PEPE/USDT");
+    assert_eq!(
+        tiers[0].rate,
+        FeeRate {
+            maker: dec("0.00075"),
+            taker: dec("0.0009"),
+        }
+    );
+
+    //older responses, 10 basis points is 0.001
+    let legacy = r#"{"makerCommission": 10, "takerCommission": 12}"#;
+    let legacy_tiers = FeeSchedule::parse_binance_account(legacy, &assets).unwrap();
+    assert_eq!(
+        legacy_tiers[0].rate,
+        FeeRate {
+            maker: dec("0.001"),
+            taker: dec("0.0012"),
+        }
+    );
+
+    let error = r#"{"code": -2015, "msg": "Invalid API-key, IP, or permissions for action."}"#;
+    assert!(FeeSchedule::parse_binance_account(error, &assets).is_err());
+    assert!(FeeSchedule::parse_binance_account("{}", &assets).is_err());
+
+    //two takers, a quarter off when paying in BNB
+    let mut schedule = FeeSchedule::new(false);
+    schedule.set(tiers[0].clone());
+    assert_eq!(
+        schedule.round_trip_taker("Binance", "PEPE/USDT"),
+        dec("0.0018")
+    );
+    schedule.pay_fees_in_bnb = true;
+    assert_eq!(
+        schedule.round_trip_taker("Binance", "PEPE/USDT"),
+        dec("0.00135")
+    );
+    //no tier yet, the default rate
+    assert_eq!(
+        schedule.round_trip_taker("Binance", "BTC/USDT"),
+        dec("0.0015")
+    );
+}
+
+#[test]
+fn balances_follow_the_account_position_whatever_the_order() {
+    let fill = r#"{"e": "executionReport", "E": 1713447297500, "s": "PEPEUSDT",
+        "c": "mock-1", "S": "BUY", "o": "MARKET", "q": "1000000.00", "p": "0.00000000",
+        "x": "TRADE", "X": "FILLED", "i": 100234, "l": "1000000.00", "z": "1000000.00",
+        "L": "0.00001148", "n": "0.01148", "N": "USDT", "T": 1713447297462, "t": 28457,
+        "C": ""}"#;
+    let position = r#"{"e": "outboundAccountPosition", "E": 1713447297501,
+        "u": 1713447297462, "B": [{"a": "USDT", "f": "988.52000000", "l": "0.00000000"},
+        {"a": "PEPE", "f": "51000000.00", "l": "0.00"}]}"#;
+    let deposit = r#"{"e": "balanceUpdate", "E": 1713447297600, "a": "USDT",
+        "d": "100.00000000", "T": 1713447297599}"#;
+
+    //the fill is already in the position, and a deposit is followed by a position of its
+    //own, applying either on top would count it twice
+    let events = |messages: &[&str]| -> Vec<AccountEvent> {
+        messages
+            .iter()
+            .flat_map(|m| AccountEvent::deserialize_binance(m).unwrap())
+            .collect()
+    };
+    for order in [[fill, position, deposit], [position, fill, deposit]] {
+        let mut balances = BalanceManager::new();
+        for event in events(&order) {
+            match event {
+                AccountEvent::Fill(fill) => balances.apply_fill(&fill),
+                AccountEvent::Balance(balance) => balances.set(balance),
+                AccountEvent::BalanceDelta(delta) => balances.apply_delta(&delta),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            balances.get("Binance", "USDT").free,
+            Decimal::from_str("988.52").unwrap()
+        );
+        assert_eq!(
+            balances.get("Binance", "PEPE").free,
+            Decimal::from_str("51000000").unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn feed_emits_subscription_and_trades() {
+    let (_, binance) = configure_clients();
+    let (tx, mut rx) = mpsc::channel::<ExchangeMessage>(100);
+    tokio::spawn(async move {
+        connect_and_run("Binance", binance, tx, "PEPE/USDT", Arc::new(Notify::new())).await;
+    });
+
+    let mut subscribed = false;
+    let trade = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let message = rx.recv().await.expect("feed closed");
+            match message.event {
+                ExchangeEvent::SubscriptionStatus { error: None, .. } => subscribed = true,
+                ExchangeEvent::Trade(_) => return message,
+                ExchangeEvent::Error { message, raw } => panic!("{}: {}", message, raw),
+                _ => {}
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for a trade");
+
+    assert!(subscribed);
+    assert_eq!(trade.venue, VenueId::intern("Binance"));
+    match trade.event {
+        ExchangeEvent::Trade(tick) => {
+            assert_eq!(tick.asset, SymbolId::intern("PEPE/USDT"));
+            assert_eq!(tick.avg, Decimal::from_str("0.00001148").unwrap());
+            assert_eq!(tick.timestamp, 1713447297462);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn agg_trade_import_pages_by_id_and_resumes() {
+    configure_clients();
+    let root = std::env::temp_dir().join(format!("sdla-binance-aggtrades-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let root = root.to_str().unwrap();
+    for _ in 0..2 {
+        let mut importer = Importer::new(
+            Source::BinanceAggTrades,
+            "PEPE/USDT",
+            None,
+            root,
+            0,
+            Some(u64::MAX),
+        )
+        .unwrap();
+        importer.page_delay_ms = 0;
+        assert_eq!(importer.run().await.unwrap(), 3);
+    }
+
+    let dir = store::partition_dir(root, store::TICKS, "Binance", "PEPE/USDT");
+    let ticks = store::read_ticks(&dir, 0, u64::MAX).unwrap();
+    let timestamps: Vec<u64> = ticks.iter().map(|tick| tick.timestamp2).collect();
+    assert_eq!(timestamps, [1700000000100, 1700000000200, 1700000000300]);
+    assert_eq!(ticks[2].avg, Decimal::from_str("0.0000115").unwrap());
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn user_data_stream_keeps_its_listen_key_alive_and_renews_it_on_expiry() {
+    let mock = mock_exchange::start();
+    let (_, binance) = configure_clients();
+    BinanceConfig::set_listen_key_keepalive(Duration::from_millis(100));
+    let (tx, mut rx) = mpsc::channel(100);
+    tokio::spawn(async move { connect_and_run_private("Binance", binance, tx).await });
+
+    wait_for_account_event(&mut rx, |e| matches!(e, AccountEvent::OpenOrders { .. })).await;
+    let listen_key = mock.listen_keys().last().unwrap().0.clone();
+    wait_until(|| mock.private_streams(&listen_key) > 0).await;
+    wait_until(|| {
+        mock.listen_keys()
+            .iter()
+            .any(|(key, keepalives)| *key == listen_key && *keepalives >= 2)
+    })
+    .await;
+
+    let order_id = mock.fill_binance_order("PEPEUSDT", "BUY", "1000000.00");
+    let fill = wait_for_account_event(
+        &mut rx,
+        |e| matches!(e, AccountEvent::Fill(fill) if fill.order_id == order_id.to_string()),
+    )
+    .await;
+    match fill {
+        AccountEvent::Fill(fill) => {
+            assert_eq!(fill.asset, "PEPE/USDT");
+            assert_eq!(fill.side, Side::Buy);
+            assert_eq!(fill.qty, Decimal::from(1000000));
+            assert_eq!(fill.fee_asset, "USDT");
+        }
+        _ => unreachable!(),
+    }
+    let balance = wait_for_account_event(
+        &mut rx,
+        |e| matches!(e, AccountEvent::Balance(balance) if balance.currency == "PEPE"),
+    )
+    .await;
+    match balance {
+        AccountEvent::Balance(balance) => assert_eq!(balance.free, Decimal::from(51_000_000)),
+        _ => unreachable!(),
+    }
+
+    //an expired key cannot be kept alive, the feed reconnects with a new one
+    mock.expire_listen_keys();
+    wait_for_account_event(&mut rx, |e| matches!(e, AccountEvent::OpenOrders { .. })).await;
+    let renewed = mock.listen_keys().last().unwrap().0.clone();
+    assert_ne!(renewed, listen_key);
+    wait_until(|| mock.private_streams(&renewed) > 0).await;
+    let order_id = mock.fill_binance_order("PEPEUSDT", "SELL", "1000000.00");
+    wait_for_account_event(
+        &mut rx,
+        |e| matches!(e, AccountEvent::Fill(fill) if fill.order_id == order_id.to_string()),
+    )
+    .await;
+}
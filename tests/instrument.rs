@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::utils::{
+    instrument::{Instrument, InstrumentRegistry},
+    order::{OrderRequest, OrderType, Side},
+};
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+//1000 PEPE lots, 0.5 USDT minimum notional
+fn pepe() -> Instrument {
+    Instrument {
+        exchange: "Binance".to_string(),
+        asset: "PEPE/USDT".to_string(),
+        symbol: "PEPEUSDT".to_string(),
+        tick_size: dec("0.00000001"),
+        lot_size: dec("1000"),
+        min_qty: dec("10000"),
+        min_notional: dec("0.5"),
+    }
+}
+
+fn order(side: Side, qty: &str, price: Option<&str>) -> OrderRequest {
+    OrderRequest {
+        asset: "PEPE/USDT".to_string(),
+        side,
+        order_type: if price.is_some() {
+            OrderType::Limit
+        } else {
+            OrderType::Market
+        },
+        qty: dec(qty),
+        price: price.map(dec),
+        client_order_id: None,
+    }
+}
+
+#[test]
+fn quantities_round_down_to_the_lot() {
+    let pepe = pepe();
+    assert_eq!(pepe.round_qty(dec("123999.99")), dec("123000"));
+    assert_eq!(pepe.round_qty(dec("124000")), dec("124000"));
+
+    //exact in decimal, 0.3 is three steps of 0.1 and not two
+    let mut fine = pepe.clone();
+    fine.lot_size = dec("0.1");
+    assert_eq!(fine.round_qty(dec("0.3")), dec("0.3"));
+    assert_eq!(fine.round_qty(dec("0.39")), dec("0.3"));
+}
+
+#[test]
+fn prices_round_away_from_the_market() {
+    let pepe = pepe();
+    assert_eq!(
+        pepe.round_price(dec("0.000011489"), Side::Buy),
+        dec("0.00001148")
+    );
+    assert_eq!(
+        pepe.round_price(dec("0.000011481"), Side::Sell),
+        dec("0.00001149")
+    );
+    //already on the grid, nothing moves
+    assert_eq!(
+        pepe.round_price(dec("0.00001148"), Side::Sell),
+        dec("0.00001148")
+    );
+}
+
+#[test]
+fn normalize_snaps_the_order_to_the_grid() {
+    let normalized = pepe()
+        .normalize(
+            &order(Side::Buy, "1000500", Some("0.000011489")),
+            Decimal::ZERO,
+        )
+        .unwrap();
+    assert_eq!(normalized.qty, dec("1000000"));
+    assert_eq!(normalized.price, Some(dec("0.00001148")));
+    assert_eq!(normalized.side, Side::Buy);
+}
+
+#[test]
+fn normalize_rejects_orders_under_the_minimum_qty() {
+    let pepe = pepe();
+    //rounding takes 10999 down to 10000, still the minimum
+    assert!(pepe
+        .normalize(&order(Side::Buy, "10999", None), dec("100"))
+        .is_ok());
+    let error = pepe
+        .normalize(&order(Side::Buy, "9999", None), dec("100"))
+        .unwrap_err();
+    assert!(error.to_string().contains("qty 9000 below minimum"));
+    //less than one lot rounds to nothing
+    assert!(pepe
+        .normalize(&order(Side::Sell, "999", None), dec("100"))
+        .is_err());
+}
+
+#[test]
+fn normalize_rejects_orders_under_the_minimum_notional() {
+    let pepe = pepe();
+    //40000 at 0.00001148 is 0.4592, the limit price counts over the reference price
+    let error = pepe
+        .normalize(&order(Side::Buy, "40000", Some("0.00001148")), dec("1"))
+        .unwrap_err();
+    assert!(error.to_string().contains("notional 0.4592"));
+    assert!(pepe
+        .normalize(&order(Side::Buy, "44000", Some("0.00001148")), dec("1"))
+        .is_ok());
+
+    //market orders are checked at the reference price
+    assert!(pepe
+        .normalize(&order(Side::Sell, "40000", None), dec("0.00001148"))
+        .is_err());
+    assert!(pepe
+        .normalize(&order(Side::Sell, "40000", None), dec("0.0000125"))
+        .is_ok());
+}
+
+#[test]
+fn kraken_decimals_become_steps() {
+    let response = r#"{"error": [], "result": {
+        "PEPEUSD": {"wsname": "PEPE/USD", "pair_decimals": 9, "lot_decimals": 2,
+            "ordermin": "1000000", "costmin": "0.5"},
+        "XBTUSD": {"wsname": "XBT/USD", "pair_decimals": 1, "lot_decimals": 8,
+            "ordermin": "0.0001", "tick_size": "0.5"}
+    }}"#;
+    let mut registry = InstrumentRegistry::new();
+    for instrument in InstrumentRegistry::parse_kraken_asset_pairs(response).unwrap() {
+        registry.add(instrument);
+    }
+
+    //no tick_size, the tick is one price decimal
+    let pepe = registry.get("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(pepe.tick_size, dec("0.000000001"));
+    assert_eq!(pepe.lot_size, dec("0.01"));
+    assert_eq!(pepe.min_qty, dec("1000000"));
+    assert_eq!(pepe.min_notional, dec("0.5"));
+
+    //tick_size wins over pair_decimals, no costmin means no notional minimum
+    let xbt = registry.get("Kraken", "XBT/USD").unwrap();
+    assert_eq!(xbt.tick_size, dec("0.5"));
+    assert_eq!(xbt.lot_size, dec("0.00000001"));
+    assert_eq!(xbt.min_notional, Decimal::ZERO);
+}
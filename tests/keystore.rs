@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use sdla::utils::api_key_man::{Credentials, Keystore};
+
+const KRAKEN_API_SECRET: &str = "a3Jha2VuLW1vY2stc2VjcmV0LTAxMjM0NTY3ODlhYmNkZWY=";
+
+fn keystore_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sdla-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("keystore.json")
+}
+
+fn sample_keystore() -> Keystore {
+    let mut keystore = Keystore::new();
+    keystore.set(
+        "Kraken",
+        Credentials::new("kraken-key".to_string(), KRAKEN_API_SECRET.to_string()),
+    );
+    keystore.set(
+        "Binance",
+        Credentials::new("binance-key".to_string(), "binance-secret".to_string()),
+    );
+    keystore
+}
+
+#[test]
+fn saved_keystore_loads_with_the_same_passphrase() {
+    let path = keystore_path("keystore-round-trip");
+    let path = path.to_str().unwrap();
+    sample_keystore().save(path, "correct horse").unwrap();
+
+    //nothing readable on disk
+    let on_disk = std::fs::read_to_string(path).unwrap();
+    assert!(!on_disk.contains("kraken-key") && !on_disk.contains(KRAKEN_API_SECRET));
+
+    let keystore = Keystore::load(path, "correct horse").unwrap();
+    assert_eq!(
+        keystore.venues().cloned().collect::<Vec<_>>(),
+        vec!["Binance".to_string(), "Kraken".to_string()]
+    );
+    let kraken = keystore.get("Kraken").unwrap();
+    assert_eq!(kraken.api_key.as_str(), "kraken-key");
+    assert_eq!(kraken.api_sec.as_str(), KRAKEN_API_SECRET);
+    assert!(kraken.validate("Kraken").is_ok());
+    assert_eq!(
+        keystore.get("Binance").unwrap().api_sec.as_str(),
+        "binance-secret"
+    );
+}
+
+#[test]
+fn wrong_passphrase_fails_to_load() {
+    let path = keystore_path("keystore-wrong-passphrase");
+    let path = path.to_str().unwrap();
+    sample_keystore().save(path, "correct horse").unwrap();
+
+    let error = Keystore::load(path, "battery staple").err().unwrap();
+    assert!(error.to_string().contains("Wrong passphrase"), "{}", error);
+}
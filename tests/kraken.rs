@@ -0,0 +1,518 @@
+mod mock_exchange;
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use mock_exchange::{
+    configure_clients, kraken_signature, wait_for_account_event, wait_until, KRAKEN_API_SECRET,
+    KRAKEN_FILL_PRICE, KRAKEN_PAGE_SIZE,
+};
+use rust_decimal::Decimal;
+use sdla::{
+    exchanges::{
+        feed::{connect_and_run, connect_and_run_private},
+        kraken::{Kraken, KrakenClient, KrakenOrderClient},
+        ExchangeEvent, ExchangeMessage, RestClient, VenueStatus,
+    },
+    utils::{
+        balance::Balance,
+        bars::{BarKind, BarManager, KrakenOhlc},
+        fees::{FeeRate, FeeSchedule},
+        ids::{SymbolId, VenueId},
+        importer::{Importer, Source},
+        order::{AccountEvent, Fill, OrderRequest, OrderStatus, OrderType, Side},
+        store,
+        tick::Tick,
+    },
+};
+use tokio::sync::{mpsc, Notify};
+
+fn market_buy(qty: &str) -> OrderRequest {
+    OrderRequest {
+        asset: "PEPE/USD".to_string(),
+        side: Side::Buy,
+        order_type: OrderType::Market,
+        qty: Decimal::from_str(qty).unwrap(),
+        price: None,
+        client_order_id: Some("42".to_string()),
+    }
+}
+
+//every page of the trade history
+async fn fills_for(kraken: &Kraken, txid: &str) -> Vec<Fill> {
+    let mut fills = Vec::new();
+    for ofs in (0..).step_by(KRAKEN_PAGE_SIZE) {
+        let response = kraken.query("TradesHistory", &format!("ofs={}", ofs)).await;
+        let page =
+            Fill::deserialize_fills_kraken_rest(&response, &["PEPE/USD".to_string()]).unwrap();
+        if page.is_empty() {
+            break;
+        }
+        fills.extend(page.into_iter().filter(|fill| fill.order_id == txid));
+    }
+    fills
+}
+
+//reads events until one matches, failing the test if none arrives in time
+async fn wait_for(
+    rx: &mut mpsc::Receiver<ExchangeMessage>,
+    matches: impl Fn(&ExchangeEvent) -> bool,
+) -> ExchangeMessage {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let message = rx.recv().await.expect("feed closed");
+            if matches(&message.event) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for event")
+}
+
+#[tokio::test]
+async fn public_request() {
+    configure_clients();
+    let response = KrakenClient::api_request("Time", "", None).await.unwrap();
+    let value: serde_json::Value = serde_json::from_str(&response).unwrap();
+    assert!(value["result"]["unixtime"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn signed_private_request_is_accepted() {
+    let (kraken, _) = configure_clients();
+    let response = kraken.query("BalanceEx", "").await;
+    let balances = Balance::extract_balances_kraken_ex(&response).unwrap();
+    let usd = balances.iter().find(|b| b.currency == "USD").unwrap();
+    assert_eq!(usd.free, Decimal::from(975));
+    assert_eq!(usd.locked, Decimal::from(25));
+}
+
+#[tokio::test]
+async fn bad_signature_is_rejected() {
+    let mock = mock_exchange::start();
+    let body = "nonce=1";
+    let wrong_path = kraken_signature("/0/private/Balance", "1", body, KRAKEN_API_SECRET);
+    let response = reqwest::Client::new()
+        .post(format!("{}/0/private/BalanceEx", mock.http_url))
+        .header("API-Key", mock_exchange::API_KEY)
+        .header("API-Sign", wrong_path)
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(response.contains("EAPI:Invalid signature"), "{}", response);
+}
+
+#[tokio::test]
+async fn key_permissions_are_probed_without_side_effects() {
+    let (kraken, _) = configure_clients();
+    let permissions = kraken.key_permissions().await.unwrap();
+    assert!(permissions.query_funds && permissions.query_orders && permissions.trade);
+    assert!(!permissions.withdraw);
+    assert_eq!(permissions.ip_restricted, None);
+    assert!(permissions.check(false).is_ok());
+}
+
+#[test]
+fn trade_volume_fees_are_percentages() {
+    let response = r#"{"error": [], "result": {"currency": "ZUSD", "volume": "12500.5000",
+        "fees": {
+            "PEPEUSD": {"fee": "0.2600", "minfee": "0.1000", "maxfee": "0.2600",
+                "nextfee": "0.2400", "nextvolume": "50000.0000", "tiervolume": "10000.0000"},
+            "XBTUSD": {"fee": "0.4000", "minfee": "0.1000", "maxfee": "0.4000",
+                "nextfee": "0.3500", "nextvolume": "50000.0000", "tiervolume": "10000.0000"}
+        },
+        "fees_maker": {
+            "PEPEUSD": {"fee": "0.1600", "minfee": "0.0000", "maxfee": "0.1600",
+                "nextfee": "0.1400", "nextvolume": "50000.0000", "tiervolume": "10000.0000"}
+        }}}"#;
+    let assets = vec!["PEPE/USD".to_string(), "XBT/USD".to_string()];
+    let tiers = FeeSchedule::parse_kraken_trade_volume(response, &assets).unwrap();
+    assert_eq!(tiers.len(), 2);
+
+    //0.2600 percent is a fraction of 0.0026
+    let pepe = &tiers[0];
+    assert_eq!(pepe.asset, "PEPE/USD");
+    assert_eq!(
+        pepe.rate,
+        FeeRate {
+            maker: Decimal::from_str("0.0016").unwrap(),
+            taker: Decimal::from_str("0.0026").unwrap(),
+        }
+    );
+    assert_eq!(pepe.volume_30d, Some(Decimal::from_str("12500.5").unwrap()));
+    assert_eq!(
+        pepe.next_tier_volume,
+        Some(Decimal::from_str("50000").unwrap())
+    );
+
+    //no maker schedule, taker is charged for both
+    assert_eq!(tiers[1].rate.maker, Decimal::from_str("0.004").unwrap());
+    assert_eq!(tiers[1].rate.taker, Decimal::from_str("0.004").unwrap());
+
+    let mut schedule = FeeSchedule::new(true);
+    for tier in tiers {
+        schedule.set(tier);
+    }
+    //the BNB discount is Binance's only
+    assert_eq!(
+        schedule.round_trip_taker("Kraken", "PEPE/USD"),
+        Decimal::from_str("0.0052").unwrap()
+    );
+    assert_eq!(
+        schedule.round_trip_taker("Kraken", "XBT/USD"),
+        Decimal::from_str("0.008").unwrap()
+    );
+
+    let unknown = vec!["DOGE/USD".to_string()];
+    assert!(FeeSchedule::parse_kraken_trade_volume(response, &unknown).is_err());
+    let error = r#"{"error": ["EGeneral:Permission denied"]}"#;
+    assert!(FeeSchedule::parse_kraken_trade_volume(error, &assets).is_err());
+}
+
+#[tokio::test]
+async fn minute_bars_are_checked_against_ohlc() {
+    configure_clients();
+    let ohlc = KrakenOhlc::fetch("PEPE/USD", 1, 0).await.unwrap();
+    assert_eq!(ohlc.len(), 2);
+    assert_eq!(ohlc[0].start, 1700000040000);
+    assert_eq!(ohlc[0].close, Decimal::from_str("0.0000101").unwrap());
+    assert_eq!(ohlc[1].count, 4);
+
+    //mids close the first minute on kraken's close, the second one 2% above it
+    let kind = BarKind::Time(60 * 1000);
+    let mut bars = BarManager::new(vec![kind], 10);
+    for (timestamp2, avg) in [
+        (1700000041000, "0.0000100"),
+        (1700000099000, "0.0000101"),
+        (1700000101000, "0.0000101"),
+        (1700000159000, "0.0000102"),
+        (1700000161000, "0.0000102"),
+    ] {
+        bars.update(&Tick {
+            timestamp: 0,
+            timestamp2,
+            avg: Decimal::from_str(avg).unwrap(),
+            exchange: VenueId::intern("Kraken"),
+            asset: SymbolId::intern("PEPE/USD"),
+            volume: Decimal::ZERO,
+        });
+    }
+    let local: Vec<_> = bars
+        .bars("Kraken", "PEPE/USD", &kind)
+        .unwrap()
+        .iter()
+        .cloned()
+        .collect();
+    assert_eq!(local.len(), 2);
+    assert_eq!(local[0].ticks, 2);
+
+    let mismatches = KrakenOhlc::cross_check(&local, &ohlc, Decimal::from_str("0.005").unwrap());
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].start, 1700000100000);
+}
+
+fn import_root(name: &str) -> String {
+    let root = std::env::temp_dir().join(format!("sdla-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    root.to_str().unwrap().to_string()
+}
+
+async fn import(source: Source, file: Option<&str>, root: &str) -> u64 {
+    let mut importer = Importer::new(source, "PEPE/USD", file, root, 0, Some(u64::MAX)).unwrap();
+    importer.page_delay_ms = 0;
+    importer.run().await.unwrap()
+}
+
+fn imported_ticks(root: &str) -> Vec<Tick> {
+    let dir = store::partition_dir(root, store::TICKS, "Kraken", "PEPE/USD");
+    store::read_ticks(&dir, 0, u64::MAX).unwrap()
+}
+
+#[tokio::test]
+async fn trade_history_import_resumes_without_duplicates() {
+    configure_clients();
+    let root = import_root("kraken-trades");
+    assert_eq!(import(Source::KrakenTrades, None, &root).await, 3);
+    //the second run starts at the saved cursor and finds nothing new
+    assert_eq!(import(Source::KrakenTrades, None, &root).await, 3);
+
+    let ticks = imported_ticks(&root);
+    let timestamps: Vec<u64> = ticks.iter().map(|tick| tick.timestamp2).collect();
+    assert_eq!(timestamps, [1700000000100, 1700000000500, 1700000001200]);
+    assert_eq!(ticks[2].avg, Decimal::from_str("0.0000115").unwrap());
+    assert_eq!(ticks[2].volume, Decimal::from(3000000));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn csv_import_picks_up_appended_lines() {
+    let root = import_root("kraken-csv");
+    std::fs::create_dir_all(&root).unwrap();
+    let file = format!("{}/PEPEUSD.csv", root);
+    std::fs::write(
+        &file,
+        "1700000000,0.00001148,1000000\n1700000001,0.00001149,2000000\n",
+    )
+    .unwrap();
+    assert_eq!(import(Source::KrakenCsv, Some(&file), &root).await, 2);
+
+    let mut csv = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&file)
+        .unwrap();
+    std::io::Write::write_all(&mut csv, b"1700000002,0.0000115,500000\n").unwrap();
+    assert_eq!(import(Source::KrakenCsv, Some(&file), &root).await, 3);
+
+    let ticks = imported_ticks(&root);
+    assert_eq!(ticks.len(), 3);
+    assert_eq!(ticks[2].timestamp2, 1700000002000);
+    assert_eq!(ticks[2].volume, Decimal::from(500000));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn csv_import_of_another_file_starts_at_its_top() {
+    let root = import_root("kraken-csv-files");
+    std::fs::create_dir_all(&root).unwrap();
+    let first = format!("{}/PEPEUSD-1.csv", root);
+    let second = format!("{}/PEPEUSD-2.csv", root);
+    std::fs::write(
+        &first,
+        "1700000000,0.00001148,1000000\n1700000001,0.00001149,2000000\n",
+    )
+    .unwrap();
+    //shorter than the first file, the old offset would be past its end
+    std::fs::write(&second, "1700000002,0.0000115,500000\n").unwrap();
+
+    assert_eq!(import(Source::KrakenCsv, Some(&first), &root).await, 2);
+    assert_eq!(import(Source::KrakenCsv, Some(&second), &root).await, 3);
+
+    //rewriting the first file shorter than the cursor also starts it over
+    std::fs::write(&first, "1700000003,0.0000116,700000\n").unwrap();
+    assert_eq!(import(Source::KrakenCsv, Some(&first), &root).await, 4);
+
+    let timestamps: Vec<u64> = imported_ticks(&root)
+        .iter()
+        .map(|tick| tick.timestamp2)
+        .collect();
+    assert_eq!(
+        timestamps,
+        [1700000000000, 1700000001000, 1700000002000, 1700000003000]
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn import_rerun_with_another_range_is_refused() {
+    configure_clients();
+    let root = import_root("kraken-trades-range");
+    let new = |to| Importer::new(Source::KrakenTrades, "PEPE/USD", None, &root, 0, to);
+    let mut importer = new(Some(1700000000400)).unwrap();
+    importer.page_delay_ms = 0;
+    assert_eq!(importer.run().await.unwrap(), 1);
+
+    let error = new(Some(u64::MAX)).err().unwrap();
+    assert!(
+        error.to_string().contains("rerun with that range"),
+        "{}",
+        error
+    );
+    let error = Importer::new(Source::KrakenTrades, "PEPE/USD", None, &root, 1, None)
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("rerun with that range"),
+        "{}",
+        error
+    );
+
+    //without --to the saved range is kept
+    let importer = new(None).unwrap();
+    assert_eq!((importer.from, importer.to), (0, 1700000000400));
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn order_entry_falls_back_to_rest_and_fills() {
+    let (kraken, _) = configure_clients();
+    let order_client = KrakenOrderClient::new(kraken.clone());
+    assert!(!order_client.is_connected());
+
+    let txid = order_client
+        .add_order(&market_buy("1000000"))
+        .await
+        .unwrap();
+    let fills = fills_for(&kraken, &txid).await;
+    assert_eq!(fills.len(), 1);
+    assert_eq!(fills[0].asset, "PEPE/USD");
+    assert_eq!(fills[0].side, Side::Buy);
+    assert_eq!(fills[0].qty, Decimal::from(1_000_000));
+    assert_eq!(fills[0].timestamp, 1713447297462);
+
+    order_client.cancel_order(&txid).await.unwrap();
+}
+
+#[tokio::test]
+async fn entry_fill_is_confirmed_over_rest() {
+    let (kraken, _) = configure_clients();
+    let order_client = KrakenOrderClient::new(kraken);
+    let txid = order_client
+        .add_order(&market_buy("3000000"))
+        .await
+        .unwrap();
+
+    let update = order_client.query_order(&txid).await.unwrap();
+    assert_eq!(update.order_id, txid);
+    assert_eq!(update.status, Some(OrderStatus::Filled));
+    assert_eq!(update.filled_qty, Some(Decimal::from(3_000_000)));
+
+    let error = order_client
+        .query_order("OUNKNOWN-AAAAA-BBBBBB")
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("no order"), "{}", error);
+}
+
+#[tokio::test]
+async fn order_entry_over_websocket() {
+    let (kraken, _) = configure_clients();
+    let order_client = Arc::new(KrakenOrderClient::new(kraken.clone()));
+    tokio::spawn({
+        let order_client = order_client.clone();
+        async move { order_client.run().await }
+    });
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !order_client.is_connected() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("order socket did not connect");
+
+    let txid = order_client
+        .add_order(&market_buy("2000000"))
+        .await
+        .unwrap();
+    let fills = fills_for(&kraken, &txid).await;
+    assert_eq!(fills.len(), 1);
+    assert_eq!(fills[0].qty, Decimal::from(2_000_000));
+
+    order_client.cancel_order(&txid).await.unwrap();
+}
+
+#[tokio::test]
+async fn feed_emits_status_and_ticks() {
+    let (kraken, _) = configure_clients();
+    let (tx, mut rx) = mpsc::channel(100);
+    tokio::spawn(async move {
+        connect_and_run("Kraken", kraken, tx, "PEPE/USD", Arc::new(Notify::new())).await;
+    });
+
+    let status = wait_for(&mut rx, |e| matches!(e, ExchangeEvent::SystemStatus { .. })).await;
+    assert!(matches!(
+        status.event,
+        ExchangeEvent::SystemStatus {
+            status: VenueStatus::Online
+        }
+    ));
+    assert_eq!(status.venue, VenueId::intern("Kraken"));
+
+    wait_for(&mut rx, |e| {
+        matches!(e, ExchangeEvent::SubscriptionStatus { error: None, .. })
+    })
+    .await;
+    wait_for(&mut rx, |e| matches!(e, ExchangeEvent::Heartbeat)).await;
+
+    let ticker = wait_for(&mut rx, |e| matches!(e, ExchangeEvent::Ticker(_))).await;
+    assert_eq!(ticker.symbol, SymbolId::intern("PEPE/USD"));
+    match ticker.event {
+        ExchangeEvent::Ticker(tick) => {
+            assert_eq!(tick.exchange, VenueId::intern("Kraken"));
+            assert_eq!(tick.asset, ticker.symbol);
+            assert_eq!(tick.avg, Decimal::from_str("0.000011485").unwrap());
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn feed_retries_failed_subscription() {
+    let (kraken, _) = configure_clients();
+    let (tx, mut rx) = mpsc::channel(100);
+    tokio::spawn(async move {
+        connect_and_run(
+            "Kraken",
+            kraken,
+            tx,
+            mock_exchange::KRAKEN_RETRY_PAIR,
+            Arc::new(Notify::new()),
+        )
+        .await;
+    });
+
+    wait_for(&mut rx, |e| e.is_failed_subscription()).await;
+    wait_for(&mut rx, |e| {
+        matches!(e, ExchangeEvent::SubscriptionStatus { error: None, .. })
+    })
+    .await;
+    wait_for(&mut rx, |e| matches!(e, ExchangeEvent::Ticker(_))).await;
+}
+
+#[tokio::test]
+async fn private_feed_streams_fills_and_resyncs_open_orders_on_reconnect() {
+    let mock = mock_exchange::start();
+    let (kraken, _) = configure_clients();
+    let (tx, mut rx) = mpsc::channel(100);
+    tokio::spawn(async move { connect_and_run_private("Kraken", kraken, tx).await });
+
+    wait_for_account_event(&mut rx, |e| matches!(e, AccountEvent::OpenOrders { .. })).await;
+    wait_until(|| mock.private_streams("ownTrades") > 0 && mock.private_streams("openOrders") > 0)
+        .await;
+
+    let (kraken, _) = configure_clients();
+    let response = kraken
+        .query(
+            "AddOrder",
+            "ordertype=market&type=buy&volume=1000000&pair=PEPE/USD",
+        )
+        .await;
+    let value: serde_json::Value = serde_json::from_str(&response).unwrap();
+    let txid = value["result"]["txid"][0].as_str().unwrap().to_string();
+
+    let fill = wait_for_account_event(
+        &mut rx,
+        |e| matches!(e, AccountEvent::Fill(fill) if fill.order_id == txid),
+    )
+    .await;
+    match fill {
+        AccountEvent::Fill(fill) => {
+            assert_eq!(fill.asset, "PEPE/USD");
+            assert_eq!(fill.side, Side::Buy);
+            assert_eq!(fill.qty, Decimal::from(1000000));
+            assert_eq!(fill.price, Decimal::from_str(KRAKEN_FILL_PRICE).unwrap());
+        }
+        _ => unreachable!(),
+    }
+    wait_for_account_event(&mut rx, |e| {
+        matches!(e, AccountEvent::Order(update)
+            if update.order_id == txid && update.status == Some(OrderStatus::Filled))
+    })
+    .await;
+
+    //an order placed while the socket is down only shows in the snapshot after reconnecting
+    mock.drop_private_streams();
+    let resting = mock.add_kraken_open_order("PEPE/USD", "sell", "2000000", "0.0000120");
+    let snapshot = wait_for_account_event(&mut rx, |e| {
+        matches!(e, AccountEvent::OpenOrders { orders, .. }
+            if orders.iter().any(|order| order.order_id == resting))
+    })
+    .await;
+    match snapshot {
+        AccountEvent::OpenOrders { exchange, .. } => assert_eq!(exchange, "Kraken"),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,1050 @@
+//in-process stand-in for the Kraken and Binance endpoints the clients use,
+//HTTP and WebSocket on localhost with the venues' signature checks
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use sdla::{
+    exchanges::{
+        binance::{Binance, BinanceConfig},
+        kraken::{Kraken, KrakenConfig},
+        Client,
+    },
+    utils::{api_key_man::Credentials, order::AccountEvent},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256, Sha512};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    Message,
+};
+
+pub const API_KEY: &str = "mock-api-key";
+//kraken secrets are base64, binance secrets are used as is
+pub const KRAKEN_API_SECRET: &str = "a3Jha2VuLW1vY2stc2VjcmV0LTAxMjM0NTY3ODlhYmNkZWY=";
+pub const BINANCE_API_SECRET: &str = "binance-mock-secret-0123456789abcdef";
+
+//every order fills in full at these prices
+pub const KRAKEN_BID: &str = "0.000011480";
+pub const KRAKEN_ASK: &str = "0.000011490";
+pub const KRAKEN_FILL_PRICE: &str = "0.000011485";
+pub const BINANCE_TRADE_PRICE: &str = "0.00001148";
+
+//the only address DepositAddresses returns
+pub const MOCK_KRAKEN_DEPOSIT_ADDRESS: &str = "0xkrakenpepedeposit";
+
+//entries per page of the kraken history endpoints, kraken's own is 50
+pub const KRAKEN_PAGE_SIZE: usize = 2;
+
+//subscribing to this pair fails once before it succeeds
+pub const KRAKEN_RETRY_PAIR: &str = "RETRY/USD";
+
+pub struct MockExchange {
+    pub http_url: String,
+    pub ws_url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    kraken_trades: Vec<(String, Value)>,
+    //every order by txid, OpenOrders and ClosedOrders split them by status
+    kraken_orders: Vec<(String, Value)>,
+    //private sockets by channel: kraken's ownTrades and openOrders, binance's listenKey
+    private_streams: Vec<(String, mpsc::UnboundedSender<Message>)>,
+    //live binance listenKeys and how often each was kept alive
+    listen_keys: Vec<(String, u32)>,
+    //http paths that answer only after this many ms
+    delays: HashMap<String, u64>,
+    //coins whose binance withdrawals get no clear answer, and whether they are taken anyway
+    lost_withdrawals: HashMap<String, bool>,
+    //binance withdraw history
+    binance_withdrawals: Vec<Value>,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn kraken_order(status: &str, pair: &str, side: &str, volume: &str, price: &str) -> Value {
+        let vol_exec = if status == "closed" { volume } else { "0" };
+        json!({
+            "status": status,
+            "userref": 0,
+            "vol": volume,
+            "vol_exec": vol_exec,
+            "descr": { "pair": pair.replace('/', ""), "type": side, "price": price }
+        })
+    }
+
+    //market orders fill right away, the trade shows up in TradesHistory and is pushed
+    //to the private sockets subscribed to ownTrades and openOrders
+    fn add_kraken_order(&mut self, pair: &str, side: &str, volume: &str) -> String {
+        let id = self.next_id();
+        let txid = format!("OMOCK{:02}-AAAAA-BBBBBB", id);
+        let trade_id = format!("TMOCK{:02}-CCCCC-DDDDDD", id);
+        self.kraken_orders.push((
+            txid.clone(),
+            State::kraken_order("closed", pair, side, volume, "0"),
+        ));
+        let trade = json!({
+            "ordertxid": txid,
+            "postxid": "TKH2SE-M7IF5-CFI7LT",
+            "pair": pair,
+            "time": "1713447297.462300",
+            "type": side,
+            "ordertype": "market",
+            "price": KRAKEN_FILL_PRICE,
+            "cost": "1.0",
+            "fee": "0.0026",
+            "vol": volume,
+            "margin": "0.00000"
+        });
+        //rest trades carry the pair without separator and the time as a number
+        let mut rest_trade = trade.clone();
+        rest_trade["pair"] = json!(pair.replace('/', ""));
+        rest_trade["time"] = json!(1713447297.4623);
+        rest_trade["misc"] = json!("");
+        self.kraken_trades.push((trade_id.clone(), rest_trade));
+
+        let sequence = self.next_id();
+        self.push(
+            "ownTrades",
+            json!([[{ trade_id: trade }], "ownTrades", { "sequence": sequence }]),
+        );
+        self.push(
+            "openOrders",
+            json!([
+                [{ txid.clone(): { "status": "closed", "vol_exec": volume } }],
+                "openOrders",
+                { "sequence": sequence }
+            ]),
+        );
+        txid
+    }
+
+    //sockets that went away are dropped on the way
+    fn push(&mut self, channel: &str, message: Value) {
+        self.private_streams.retain(|(subscribed, stream)| {
+            subscribed != channel || stream.send(Message::Text(message.to_string())).is_ok()
+        });
+    }
+
+    //one page as kraken answers it, with the total in count
+    fn kraken_page(
+        entries: Vec<(String, Value)>,
+        form: &HashMap<String, String>,
+        key: &str,
+    ) -> (&'static str, String) {
+        let ofs: usize = form
+            .get("ofs")
+            .and_then(|ofs| ofs.parse().ok())
+            .unwrap_or(0);
+        let count = entries.len();
+        let page: serde_json::Map<String, Value> = entries
+            .into_iter()
+            .skip(ofs)
+            .take(KRAKEN_PAGE_SIZE)
+            .collect();
+        kraken_result(json!({ key: page, "count": count }))
+    }
+
+    fn kraken_orders_with(
+        &self,
+        filter: impl Fn(&Value) -> bool,
+    ) -> serde_json::Map<String, Value> {
+        self.kraken_orders
+            .iter()
+            .filter(|(_, order)| filter(order))
+            .cloned()
+            .collect()
+    }
+}
+
+//the server runs on its own thread so it outlives the runtime of any single test
+pub fn start() -> &'static MockExchange {
+    static MOCK: OnceLock<MockExchange> = OnceLock::new();
+    MOCK.get_or_init(|| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let ws = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let state = Arc::new(Mutex::new(State::default()));
+                tx.send((
+                    http.local_addr().unwrap(),
+                    ws.local_addr().unwrap(),
+                    state.clone(),
+                ))
+                .unwrap();
+                tokio::spawn(serve_ws(ws, state.clone()));
+                serve_http(http, state).await;
+            });
+        });
+        let (http, ws, state): (SocketAddr, SocketAddr, _) = rx.recv().unwrap();
+        MockExchange {
+            http_url: format!("http://{}", http),
+            ws_url: format!("ws://{}", ws),
+            state,
+        }
+    })
+}
+
+impl MockExchange {
+    //a slow venue, 0 answers right away again
+    pub fn delay(&self, path: &str, ms: u64) {
+        self.state
+            .lock()
+            .unwrap()
+            .delays
+            .insert(path.to_string(), ms);
+    }
+
+    //private sockets currently subscribed to a channel, see State::private_streams
+    pub fn private_streams(&self, channel: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .private_streams
+            .iter()
+            .filter(|(subscribed, stream)| subscribed == channel && !stream.is_closed())
+            .count()
+    }
+
+    //the venue drops every private socket, as on a restart
+    pub fn drop_private_streams(&self) {
+        let mut state = self.state.lock().unwrap();
+        for (_, stream) in state.private_streams.drain(..) {
+            let _ = stream.send(Message::Close(None));
+        }
+    }
+
+    //live listenKeys with their keepalive count, oldest first
+    pub fn listen_keys(&self) -> Vec<(String, u32)> {
+        self.state.lock().unwrap().listen_keys.clone()
+    }
+
+    //every listenKey expires, their streams get listenKeyExpired and keepalives fail
+    pub fn expire_listen_keys(&self) {
+        let mut state = self.state.lock().unwrap();
+        for (listen_key, _) in std::mem::take(&mut state.listen_keys) {
+            let expired =
+                json!({ "e": "listenKeyExpired", "E": now_millis(), "listenKey": listen_key });
+            state.push(&listen_key, expired);
+        }
+    }
+
+    //binance withdrawals of this coin answer "execution status unknown", taken ones still
+    //show up in the withdraw history
+    pub fn lose_binance_withdraw_answers(&self, coin: &str, taken: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .lost_withdrawals
+            .insert(coin.to_string(), taken);
+    }
+
+    //withdrawals binance took, in the withdraw history's format
+    pub fn binance_withdrawals(&self) -> Vec<Value> {
+        self.state.lock().unwrap().binance_withdrawals.clone()
+    }
+
+    //an order filled on binance, the user data streams get the execution and the balances
+    pub fn fill_binance_order(&self, symbol: &str, side: &str, qty: &str) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let order_id = 200000 + state.next_id();
+        let trade_id = 300000 + state.next_id();
+        let time = now_millis();
+        let report = json!({
+            "e": "executionReport",
+            "E": time,
+            "s": symbol,
+            "c": format!("mock-{}", order_id),
+            "S": side,
+            "o": "MARKET",
+            "q": qty,
+            "p": "0.00000000",
+            "x": "TRADE",
+            "X": "FILLED",
+            "i": order_id,
+            "l": qty,
+            "z": qty,
+            "L": BINANCE_TRADE_PRICE,
+            "n": "0.01148",
+            "N": "USDT",
+            "T": time,
+            "t": trade_id,
+            "C": ""
+        });
+        let position = json!({
+            "e": "outboundAccountPosition",
+            "E": time,
+            "u": time,
+            "B": [
+                { "a": "USDT", "f": "988.52000000", "l": "0.00000000" },
+                { "a": "PEPE", "f": "51000000.00", "l": "1000000.00" }
+            ]
+        });
+        let listen_keys: Vec<String> = state.listen_keys.iter().map(|(k, _)| k.clone()).collect();
+        for listen_key in listen_keys {
+            state.push(&listen_key, report.clone());
+            state.push(&listen_key, position.clone());
+        }
+        order_id
+    }
+
+    //a resting limit order placed outside of this process
+    pub fn add_kraken_open_order(
+        &self,
+        pair: &str,
+        side: &str,
+        volume: &str,
+        price: &str,
+    ) -> String {
+        let mut state = self.state.lock().unwrap();
+        let txid = format!("OREST{:02}-AAAAA-BBBBBB", state.next_id());
+        let order = State::kraken_order("open", pair, side, volume, price);
+        state.kraken_orders.push((txid.clone(), order));
+        txid
+    }
+}
+
+//points both clients at the mock with its credentials, the client config is global
+//so this happens once per test binary
+pub fn configure_clients() -> (Kraken, Binance) {
+    static CLIENTS: OnceLock<(Kraken, Binance)> = OnceLock::new();
+    CLIENTS
+        .get_or_init(|| {
+            let mock = start();
+            KrakenConfig::set_kraken_urls(
+                mock.http_url.clone(),
+                format!("{}/kraken", mock.ws_url),
+                format!("{}/kraken-auth", mock.ws_url),
+            );
+            BinanceConfig::set_binance_urls(mock.http_url.clone(), mock.ws_url.clone());
+            (
+                Kraken::new(Credentials::new(
+                    API_KEY.to_string(),
+                    KRAKEN_API_SECRET.to_string(),
+                )),
+                Binance::new(Credentials::new(
+                    API_KEY.to_string(),
+                    BINANCE_API_SECRET.to_string(),
+                )),
+            )
+        })
+        .clone()
+}
+
+//private feeds reconnect after seconds, the waits allow for one reconnect
+const PRIVATE_WAIT_SECS: u64 = 15;
+
+pub async fn wait_for_account_event(
+    rx: &mut mpsc::Receiver<AccountEvent>,
+    matches: impl Fn(&AccountEvent) -> bool,
+) -> AccountEvent {
+    tokio::time::timeout(Duration::from_secs(PRIVATE_WAIT_SECS), async {
+        loop {
+            let event = rx.recv().await.expect("private feed closed");
+            if matches(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for account event")
+}
+
+//for mock state the client changes from its own tasks
+pub async fn wait_until(condition: impl Fn() -> bool) {
+    tokio::time::timeout(Duration::from_secs(PRIVATE_WAIT_SECS), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the mock")
+}
+
+//signatures are computed here independently of the clients, from the venues' docs
+pub fn kraken_signature(path: &str, nonce: &str, body: &str, secret: &str) -> String {
+    let hash = Sha256::digest(format!("{}{}", nonce, body).as_bytes());
+    let mut mac = Hmac::<Sha512>::new_from_slice(&STANDARD.decode(secret).unwrap()).unwrap();
+    mac.update(path.as_bytes());
+    mac.update(&hash);
+    STANDARD.encode(mac.finalize().into_bytes())
+}
+
+pub fn binance_signature(query: &str, secret: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(query.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+fn parse_form(body: &str) -> HashMap<String, String> {
+    form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+//http
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: String,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(&name.to_lowercase())
+            .map(|value| value.as_str())
+            .unwrap_or_default()
+    }
+}
+
+async fn serve_http(listener: TcpListener, state: Arc<Mutex<State>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle_http(stream, state.clone()));
+    }
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Option<HttpRequest> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let method = line.split_whitespace().next()?.to_string();
+    let target = line.split_whitespace().nth(1)?.to_string();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target, String::new()),
+    };
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or_default();
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.ok()?;
+
+    Some(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+async fn handle_http(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut stream = BufReader::new(stream);
+    let request = match read_request(&mut stream).await {
+        Some(request) => request,
+        None => return,
+    };
+    let delay = state.lock().unwrap().delays.get(&request.path).copied();
+    if let Some(ms) = delay {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+    }
+    let (status, body) = route(&request, &state);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.get_mut().write_all(response.as_bytes()).await;
+    let _ = stream.get_mut().shutdown().await;
+}
+
+fn route(request: &HttpRequest, state: &Mutex<State>) -> (&'static str, String) {
+    match request.path.as_str() {
+        "/0/public/Time" => kraken_result(json!({
+            "unixtime": now_millis() / 1000,
+            "rfc1123": "Thu, 18 Apr 24 13:34:57 +0000"
+        })),
+        "/0/public/AssetPairs" => kraken_result(json!({
+            "PEPEUSD": {
+                "altname": "PEPEUSD",
+                "wsname": "PEPE/USD",
+                "base": "PEPE",
+                "quote": "ZUSD",
+                "pair_decimals": 9,
+                "lot_decimals": 2,
+                "ordermin": "1000000",
+                "costmin": "0.5",
+                "tick_size": "0.000000001"
+            }
+        })),
+        //two 1m bars starting at 1700000040
+        "/0/public/OHLC" => kraken_result(json!({
+            "PEPEUSD": [
+                [1700000040, "0.000010000", "0.000010200", "0.000009900", "0.000010100",
+                    "0.000010050", "150000000.00", 12],
+                [1700000100, "0.000010100", "0.000010100", "0.000010000", "0.000010000",
+                    "0.000010040", "50000000.00", 4]
+            ],
+            "last": 1700000100
+        })),
+        "/0/public/Trades" => kraken_trades(request),
+        path if path.starts_with("/0/private/") => kraken_private(request, state),
+        "/api/v3/time" => ("200 OK", json!({ "serverTime": now_millis() }).to_string()),
+        "/api/v3/aggTrades" => binance_agg_trades(request),
+        "/api/v3/exchangeInfo" => (
+            "200 OK",
+            json!({
+                "symbols": [{
+                    "symbol": "PEPEUSDT",
+                    "baseAsset": "PEPE",
+                    "quoteAsset": "USDT",
+                    "filters": [
+                        {"filterType": "PRICE_FILTER", "tickSize": "0.00000001"},
+                        {"filterType": "LOT_SIZE", "stepSize": "1.00", "minQty": "1.00"},
+                        {"filterType": "NOTIONAL", "minNotional": "5.00000000"}
+                    ]
+                }]
+            })
+            .to_string(),
+        ),
+        "/api/v3/userDataStream" => binance_user_data_stream(request, state),
+        path if path.starts_with("/api/v3/") || path.starts_with("/sapi/v1/") => {
+            binance_private(request, state)
+        }
+        _ => ("404 Not Found", String::new()),
+    }
+}
+
+//two pages of history that overlap on trade 2, then nothing new
+fn kraken_trades(request: &HttpRequest) -> (&'static str, String) {
+    let since = parse_form(&request.query)
+        .get("since")
+        .cloned()
+        .unwrap_or_default();
+    let (trades, last) = match since.as_str() {
+        "" | "0" => (
+            json!([
+                ["0.000011480", "1000000.00", 1700000000.1, "b", "m", "", 1],
+                ["0.000011490", "2000000.00", 1700000000.5, "s", "l", "", 2]
+            ]),
+            "1700000000500000000".to_string(),
+        ),
+        "1700000000500000000" => (
+            json!([
+                ["0.000011490", "2000000.00", 1700000000.5, "s", "l", "", 2],
+                ["0.000011500", "3000000.00", 1700000001.2, "b", "m", "", 3]
+            ]),
+            "1700000001200000000".to_string(),
+        ),
+        _ => (json!([]), since),
+    };
+    kraken_result(json!({ "PEPEUSD": trades, "last": last }))
+}
+
+//ids 100 and 101 from the start time, 102 after them, then nothing new
+fn binance_agg_trades(request: &HttpRequest) -> (&'static str, String) {
+    let params = parse_form(&request.query);
+    let trade = |id: u64, price: &str, time: u64| json!({"a": id, "p": price, "q": "1000000.00", "f": id, "l": id, "T": time, "m": false});
+    let trades = match params.get("fromId").map(|id| id.as_str()) {
+        None => json!([
+            trade(100, "0.00001148", 1700000000100),
+            trade(101, "0.00001149", 1700000000200)
+        ]),
+        Some("102") => json!([trade(102, "0.00001150", 1700000000300)]),
+        Some(_) => json!([]),
+    };
+    ("200 OK", trades.to_string())
+}
+
+fn kraken_result(result: Value) -> (&'static str, String) {
+    (
+        "200 OK",
+        json!({ "error": [], "result": result }).to_string(),
+    )
+}
+
+fn kraken_error(error: &str) -> (&'static str, String) {
+    ("200 OK", json!({ "error": [error] }).to_string())
+}
+
+fn kraken_private(request: &HttpRequest, state: &Mutex<State>) -> (&'static str, String) {
+    if request.header("API-Key") != API_KEY {
+        return kraken_error("EAPI:Invalid key");
+    }
+    let form = parse_form(&request.body);
+    let nonce = match form.get("nonce") {
+        Some(nonce) => nonce,
+        None => return kraken_error("EAPI:Invalid nonce"),
+    };
+    let expected = kraken_signature(&request.path, nonce, &request.body, KRAKEN_API_SECRET);
+    if request.header("API-Sign") != expected {
+        return kraken_error("EAPI:Invalid signature");
+    }
+
+    let mut state = state.lock().unwrap();
+    match request.path.trim_start_matches("/0/private/") {
+        "Balance" => kraken_result(json!({ "ZUSD": "1000.0000", "PEPE": "50000000.00" })),
+        "BalanceEx" => kraken_result(json!({
+            "ZUSD": { "balance": "1000.0000", "hold_trade": "25.0000" },
+            "PEPE": { "balance": "50000000.00", "hold_trade": "0.00" }
+        })),
+        "GetWebSocketsToken" => kraken_result(json!({ "token": "mock-ws-token", "expires": 900 })),
+        "AddOrder" => {
+            let (pair, side, volume) =
+                match (form.get("pair"), form.get("type"), form.get("volume")) {
+                    (Some(pair), Some(side), Some(volume)) => (pair, side, volume),
+                    _ => return kraken_error("EGeneral:Invalid arguments"),
+                };
+            //validate only checks the order, nothing is placed
+            if form.get("validate").map(String::as_str) == Some("true") {
+                return kraken_result(json!({
+                    "descr": { "order": format!("{} {} {} @ limit", side, volume, pair) }
+                }));
+            }
+            let txid = state.add_kraken_order(pair, side, volume);
+            kraken_result(json!({
+                "descr": { "order": format!("{} {} {} @ market", side, volume, pair) },
+                "txid": [txid]
+            }))
+        }
+        "CancelOrder" => kraken_result(json!({ "count": 1 })),
+        "OpenOrders" => kraken_result(json!({ "open": state.kraken_orders_with(|order| {
+            order["status"] == "open"
+        }) })),
+        "ClosedOrders" => {
+            let closed = state.kraken_orders_with(|order| order["status"] == "closed");
+            State::kraken_page(closed.into_iter().collect(), &form, "closed")
+        }
+        //unknown txids are left out
+        "QueryOrders" => {
+            let txids: Vec<&str> = form
+                .get("txid")
+                .map_or(Vec::new(), |t| t.split(',').collect());
+            let orders = state
+                .kraken_orders
+                .iter()
+                .filter(|(txid, _)| txids.contains(&txid.as_str()));
+            kraken_result(Value::Object(orders.cloned().collect()))
+        }
+        //the mock key is a trading key without funding permissions
+        "WithdrawInfo" => kraken_error("EGeneral:Permission denied"),
+        "DepositAddresses" => kraken_result(json!([
+            { "address": MOCK_KRAKEN_DEPOSIT_ADDRESS, "expiretm": "0", "new": false }
+        ])),
+        "TradesHistory" => State::kraken_page(state.kraken_trades.clone(), &form, "trades"),
+        _ => kraken_error("EGeneral:Unknown method"),
+    }
+}
+
+fn binance_error(code: i64, msg: &str) -> (&'static str, String) {
+    (
+        "400 Bad Request",
+        json!({ "code": code, "msg": msg }).to_string(),
+    )
+}
+
+fn binance_private(request: &HttpRequest, state: &Mutex<State>) -> (&'static str, String) {
+    if request.header("X-MBX-APIKEY") != API_KEY {
+        return binance_error(-2015, "Invalid API-key, IP, or permissions for action.");
+    }
+    //the signature covers the query exactly as sent, up to &signature=
+    let (signed, signature) = match request.query.rsplit_once("&signature=") {
+        Some(parts) => parts,
+        None => return binance_error(-1102, "Mandatory parameter 'signature' was not sent."),
+    };
+    if !parse_form(signed).contains_key("timestamp") {
+        return binance_error(-1102, "Mandatory parameter 'timestamp' was not sent.");
+    }
+    if binance_signature(signed, BINANCE_API_SECRET) != signature {
+        return binance_error(-1022, "Signature for this request is not valid.");
+    }
+
+    let params = parse_form(signed);
+    let body = match request.path.trim_start_matches("/api/v3/") {
+        "/sapi/v1/account/apiRestrictions" => json!({
+            "ipRestrict": false,
+            "createTime": 1698645219000u64,
+            "enableReading": true,
+            "enableWithdrawals": false,
+            "enableInternalTransfer": false,
+            "enableMargin": false,
+            "enableFutures": false,
+            "permitsUniversalTransfer": false,
+            "enableVanillaOptions": false,
+            "enableSpotAndMarginTrading": true
+        }),
+        "account" => json!({
+            "makerCommission": 10,
+            "takerCommission": 10,
+            "commissionRates": { "maker": "0.00100000", "taker": "0.00100000" },
+            "canTrade": true,
+            "balances": [
+                { "asset": "USDT", "free": "1000.00000000", "locked": "0.00000000" },
+                { "asset": "PEPE", "free": "50000000.00", "locked": "1000000.00" }
+            ]
+        }),
+        "myTrades" => json!([{
+            "symbol": params.get("symbol").cloned().unwrap_or_default(),
+            "id": 28457,
+            "orderId": 100234,
+            "price": BINANCE_TRADE_PRICE,
+            "qty": "1000000.00",
+            "quoteQty": "11.48",
+            "commission": "0.01148",
+            "commissionAsset": "USDT",
+            "time": 1713447297462u64,
+            "isBuyer": true,
+            "isMaker": false,
+            "isBestMatch": true
+        }]),
+        "openOrders" => json!([]),
+        //the mock places no binance orders, lookups never find one
+        "order" => return binance_error(-2013, "Order does not exist."),
+        //apiRestrictions says withdrawals are off for the mock key, apart from the coins
+        //whose answers are lost
+        "/sapi/v1/capital/withdraw/apply" => {
+            let mut state = state.lock().unwrap();
+            let coin = params.get("coin").cloned().unwrap_or_default();
+            let taken = match state.lost_withdrawals.get(&coin) {
+                Some(taken) => *taken,
+                None => {
+                    return binance_error(-1002, "You are not authorized to execute this request.")
+                }
+            };
+            if taken {
+                let id = format!("mockwithdrawal{:04}", state.next_id());
+                state.binance_withdrawals.push(json!({
+                    "id": id,
+                    "coin": coin,
+                    "amount": params.get("amount").cloned().unwrap_or_default(),
+                    "address": params.get("address").cloned().unwrap_or_default(),
+                    "withdrawOrderId": params.get("withdrawOrderId").cloned().unwrap_or_default(),
+                    "status": 4
+                }));
+            }
+            return binance_error(
+                -1007,
+                "Timeout waiting for response from backend server. Send status unknown; execution status unknown.",
+            );
+        }
+        "/sapi/v1/capital/withdraw/history" => {
+            let state = state.lock().unwrap();
+            let withdrawals: Vec<&Value> = state
+                .binance_withdrawals
+                .iter()
+                .filter(|w| {
+                    params
+                        .get("withdrawOrderId")
+                        .is_none_or(|id| w["withdrawOrderId"] == json!(id))
+                })
+                .collect();
+            json!(withdrawals)
+        }
+        _ => return binance_error(-1100, "Unknown endpoint"),
+    };
+    ("200 OK", body.to_string())
+}
+
+//listenKeys only need the api key, there is no signature
+fn binance_user_data_stream(request: &HttpRequest, state: &Mutex<State>) -> (&'static str, String) {
+    if request.header("X-MBX-APIKEY") != API_KEY {
+        return binance_error(-2015, "Invalid API-key, IP, or permissions for action.");
+    }
+    let mut state = state.lock().unwrap();
+    match request.method.as_str() {
+        "POST" => {
+            let listen_key = format!("mocklistenkey{:04}", state.next_id());
+            state.listen_keys.push((listen_key.clone(), 0));
+            ("200 OK", json!({ "listenKey": listen_key }).to_string())
+        }
+        "PUT" => {
+            let params = parse_form(&request.query);
+            let listen_key = params.get("listenKey").cloned().unwrap_or_default();
+            match state.listen_keys.iter_mut().find(|(k, _)| *k == listen_key) {
+                Some((_, keepalives)) => {
+                    *keepalives += 1;
+                    ("200 OK", json!({}).to_string())
+                }
+                None => binance_error(-1125, "This listenKey does not exist."),
+            }
+        }
+        _ => binance_error(-1100, "Unknown endpoint"),
+    }
+}
+
+//websocket, the path picks the venue
+
+async fn serve_ws(listener: TcpListener, state: Arc<Mutex<State>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle_ws(stream, state.clone()));
+    }
+}
+
+async fn handle_ws(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut path = String::new();
+    //the error type is fixed by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        path = request.uri().path().to_string();
+        Ok(response)
+    };
+    let ws = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(ws) => ws,
+        Err(_) => return,
+    };
+    let (mut write, mut read) = ws.split();
+    let (push_tx, mut push_rx) = mpsc::unbounded_channel();
+
+    //binance user data streams need no subscription, the listenKey in the path is enough
+    if let Some(listen_key) = path.strip_prefix("/ws/") {
+        let mut state = state.lock().unwrap();
+        if !state.listen_keys.iter().any(|(k, _)| k == listen_key) {
+            return;
+        }
+        state
+            .private_streams
+            .push((listen_key.to_string(), push_tx.clone()));
+    }
+
+    if path == "/kraken" || path == "/kraken-auth" {
+        let status = json!({
+            "connectionID": 8628615390848610000u64,
+            "event": "systemStatus",
+            "status": "online",
+            "version": "1.9.1"
+        });
+        if write.send(Message::Text(status.to_string())).await.is_err() {
+            return;
+        }
+    }
+
+    let mut subscribe_attempts = 0;
+    loop {
+        let message = tokio::select! {
+            message = read.next() => match message {
+                Some(Ok(message)) => message,
+                _ => return,
+            },
+            push = push_rx.recv() => match push {
+                Some(Message::Close(frame)) => {
+                    let _ = write.send(Message::Close(frame)).await;
+                    return;
+                }
+                Some(push) => {
+                    if write.send(push).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                None => continue,
+            },
+        };
+        let request: Value = match message {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(request) => request,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        let replies = match path.as_str() {
+            "/kraken" => {
+                subscribe_attempts += 1;
+                kraken_public_replies(&request, subscribe_attempts)
+            }
+            "/kraken-auth" => kraken_auth_replies(&request, &state, &push_tx),
+            "/stream" => binance_stream_replies(&request),
+            _ => Vec::new(),
+        };
+        for reply in replies {
+            if write.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn kraken_public_replies(request: &Value, attempt: u32) -> Vec<Value> {
+    if request["event"] != "subscribe" {
+        return Vec::new();
+    }
+    let pair = request["pair"][0].as_str().unwrap_or_default();
+    if pair == KRAKEN_RETRY_PAIR && attempt == 1 {
+        return vec![json!({
+            "errorMessage": "Subscription depth not supported",
+            "event": "subscriptionStatus",
+            "pair": pair,
+            "status": "error",
+            "subscription": request["subscription"]
+        })];
+    }
+
+    let mut replies = vec![json!({
+        "channelID": 340,
+        "channelName": "spread",
+        "event": "subscriptionStatus",
+        "pair": pair,
+        "status": "subscribed",
+        "subscription": request["subscription"]
+    })];
+    replies.push(json!({ "event": "heartbeat" }));
+    for _ in 0..3 {
+        replies.push(json!([
+            340,
+            [
+                KRAKEN_BID,
+                KRAKEN_ASK,
+                "1713447297.462361",
+                "137016937.12845706",
+                "149874.85683800"
+            ],
+            "spread",
+            pair
+        ]));
+    }
+    replies
+}
+
+fn kraken_auth_replies(
+    request: &Value,
+    state: &Mutex<State>,
+    push_tx: &mpsc::UnboundedSender<Message>,
+) -> Vec<Value> {
+    //private subscriptions carry the token inside the subscription
+    if request["event"] == "subscribe" {
+        return kraken_private_subscribe(&request["subscription"], state, push_tx);
+    }
+    if request["token"] != "mock-ws-token" {
+        return vec![json!({
+            "event": format!("{}Status", request["event"].as_str().unwrap_or_default()),
+            "reqid": request["reqid"],
+            "status": "error",
+            "errorMessage": "EGeneral:Invalid token"
+        })];
+    }
+    match request["event"].as_str() {
+        Some("addOrder") => {
+            let txid = state.lock().unwrap().add_kraken_order(
+                request["pair"].as_str().unwrap_or_default(),
+                request["type"].as_str().unwrap_or_default(),
+                request["volume"].as_str().unwrap_or_default(),
+            );
+            vec![json!({
+                "descr": "market order",
+                "event": "addOrderStatus",
+                "reqid": request["reqid"],
+                "status": "ok",
+                "txid": txid
+            })]
+        }
+        Some("cancelOrder") => vec![json!({
+            "event": "cancelOrderStatus",
+            "reqid": request["reqid"],
+            "status": "ok"
+        })],
+        _ => Vec::new(),
+    }
+}
+
+//openOrders starts with a snapshot of the open orders, ownTrades is subscribed without one
+fn kraken_private_subscribe(
+    subscription: &Value,
+    state: &Mutex<State>,
+    push_tx: &mpsc::UnboundedSender<Message>,
+) -> Vec<Value> {
+    let name = subscription["name"].as_str().unwrap_or_default();
+    if subscription["token"] != "mock-ws-token" {
+        return vec![json!({
+            "event": "subscriptionStatus",
+            "status": "error",
+            "errorMessage": "EGeneral:Invalid token",
+            "subscription": { "name": name }
+        })];
+    }
+    let mut state = state.lock().unwrap();
+    state
+        .private_streams
+        .push((name.to_string(), push_tx.clone()));
+    let mut replies = vec![json!({
+        "channelName": name,
+        "event": "subscriptionStatus",
+        "status": "subscribed",
+        "subscription": { "name": name }
+    })];
+    if name == "openOrders" {
+        let open = state.kraken_orders_with(|order| order["status"] == "open");
+        let sequence = state.next_id();
+        replies.push(json!([
+            open.into_iter()
+                .map(|(txid, order)| json!({ txid: order }))
+                .collect::<Vec<_>>(),
+            "openOrders",
+            { "sequence": sequence }
+        ]));
+    }
+    replies
+}
+
+fn binance_stream_replies(request: &Value) -> Vec<Value> {
+    if request["method"] != "SUBSCRIBE" {
+        return Vec::new();
+    }
+    let mut replies = vec![json!({ "result": null, "id": request["id"] })];
+    for param in request["params"].as_array().into_iter().flatten() {
+        let stream = param.as_str().unwrap_or_default();
+        let symbol = stream.split('@').next().unwrap_or_default().to_uppercase();
+        for trade_id in 0..3u64 {
+            replies.push(json!({
+                "stream": stream,
+                "data": {
+                    "e": "trade",
+                    "E": 1713447297463u64 + trade_id,
+                    "s": symbol,
+                    "t": 153289011 + trade_id,
+                    "p": BINANCE_TRADE_PRICE,
+                    "q": "4356445.00",
+                    "T": 1713447297462u64 + trade_id,
+                    "m": true,
+                    "M": true
+                }
+            }));
+        }
+    }
+    replies
+}
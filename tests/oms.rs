@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::utils::{
+    oms::{Oms, EXTERNAL_STRATEGY},
+    order::{OrderRequest, OrderStatus, OrderType, OrderUpdate, Side},
+};
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+//acked on Kraken as order_id, returns the client order id
+fn placed(oms: &mut Oms, order_id: &str) -> String {
+    let request = oms.create(
+        "oneleg",
+        "Kraken",
+        OrderRequest {
+            asset: "PEPE/USD".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            qty: dec("1000"),
+            price: Some(dec("0.0000115")),
+            client_order_id: None,
+        },
+    );
+    let client_order_id = request.client_order_id.unwrap();
+    oms.on_ack(&client_order_id, order_id);
+    client_order_id
+}
+
+fn update(order_id: &str, status: Option<OrderStatus>, filled_qty: Option<&str>) -> OrderUpdate {
+    OrderUpdate {
+        exchange: "Kraken".to_string(),
+        order_id: order_id.to_string(),
+        client_order_id: None,
+        asset: None,
+        side: None,
+        status,
+        price: None,
+        qty: None,
+        filled_qty: filled_qty.map(dec),
+        timestamp: 0,
+    }
+}
+
+#[test]
+fn status_never_moves_backward() {
+    let mut oms = Oms::new();
+    let id = placed(&mut oms, "O1");
+    assert_eq!(oms.get(&id).unwrap().status, OrderStatus::Acked);
+
+    oms.apply_update(&update(
+        "O1",
+        Some(OrderStatus::PartiallyFilled),
+        Some("400"),
+    ));
+    //a stale open from REST after the partial fill
+    oms.apply_update(&update("O1", Some(OrderStatus::Acked), Some("0")));
+    let order = oms.get(&id).unwrap();
+    assert_eq!(order.status, OrderStatus::PartiallyFilled);
+    assert_eq!(order.filled_qty, dec("400"));
+
+    oms.apply_update(&update("O1", Some(OrderStatus::Filled), Some("1000")));
+    oms.apply_update(&update("O1", Some(OrderStatus::Acked), None));
+    oms.apply_update(&update(
+        "O1",
+        Some(OrderStatus::PartiallyFilled),
+        Some("400"),
+    ));
+    let order = oms.get(&id).unwrap();
+    assert_eq!(order.status, OrderStatus::Filled);
+    assert_eq!(order.filled_qty, dec("1000"));
+    assert!(!order.is_open());
+
+    //a reject after an ack is final too
+    let id = placed(&mut oms, "O2");
+    oms.on_reject(&id, "EOrder:Insufficient funds");
+    oms.on_ack(&id, "O2");
+    assert_eq!(oms.get(&id).unwrap().status, OrderStatus::Rejected);
+}
+
+#[test]
+fn unknown_orders_are_adopted_as_external() {
+    let mut oms = Oms::new();
+    let full = OrderUpdate {
+        asset: Some("PEPE/USD".to_string()),
+        side: Some(Side::Sell),
+        qty: Some(dec("2000")),
+        price: Some(dec("0.000012")),
+        ..update("OEXT1", Some(OrderStatus::Acked), Some("0"))
+    };
+    oms.apply_update(&full);
+
+    let order = oms.get_by_order_id("Kraken", "OEXT1").unwrap();
+    assert_eq!(order.strategy, EXTERNAL_STRATEGY);
+    assert_eq!(order.request.side, Side::Sell);
+    assert_eq!(order.request.qty, dec("2000"));
+    assert!(order.is_open());
+    assert!(oms.open_orders("oneleg").is_empty());
+    assert_eq!(oms.open_orders(EXTERNAL_STRATEGY).len(), 1);
+
+    //later updates land on the adopted order
+    oms.apply_update(&update("OEXT1", None, Some("500")));
+    let order = oms.get_by_order_id("Kraken", "OEXT1").unwrap();
+    assert_eq!(order.status, OrderStatus::PartiallyFilled);
+
+    //without asset, side and qty there is nothing to adopt
+    oms.apply_update(&update("OEXT2", Some(OrderStatus::Acked), None));
+    assert!(oms.get_by_order_id("Kraken", "OEXT2").is_none());
+}
+
+#[test]
+fn open_orders_snapshot_returns_ours_the_venue_no_longer_lists() {
+    let mut oms = Oms::new();
+    placed(&mut oms, "O1");
+    let gone = placed(&mut oms, "O2");
+    let snapshot = [update("O1", Some(OrderStatus::Acked), Some("0"))];
+
+    let missing = oms.apply_open_orders_snapshot("Kraken", &snapshot);
+    assert_eq!(missing, ["O2"]);
+    //left open until its final state is known
+    assert!(oms.get(&gone).unwrap().is_open());
+    //other venues are not affected by a Kraken snapshot
+    assert!(oms.apply_open_orders_snapshot("Binance", &[]).is_empty());
+}
+
+#[test]
+fn client_order_ids_keep_counting_across_restarts_and_skip_the_venues() {
+    let mut oms = Oms::new();
+    let first: u32 = placed(&mut oms, "O1").parse().unwrap();
+    //seconds since 2024-01-01 rather than the time of day, within kraken's int32 userrefs
+    let since_2024 = (chrono::Utc::now().timestamp() - 1_704_067_200) as u32;
+    assert!(first > 86_400);
+    assert!(first <= since_2024 && first + 5 >= since_2024);
+    assert!(first <= i32::MAX as u32);
+
+    //an order from an earlier run, still open under the next id
+    let earlier = OrderUpdate {
+        client_order_id: Some((first + 1).to_string()),
+        asset: Some("PEPE/USD".to_string()),
+        side: Some(Side::Sell),
+        qty: Some(dec("2000")),
+        ..update("OOLD1", Some(OrderStatus::Acked), Some("0"))
+    };
+    oms.apply_update(&earlier);
+    let next: u32 = placed(&mut oms, "O2").parse().unwrap();
+    assert_eq!(next, first + 2);
+    let adopted = oms.get_by_order_id("Kraken", "OOLD1").unwrap();
+    assert_eq!(adopted.strategy, EXTERNAL_STRATEGY);
+    assert_eq!(adopted.request.qty, dec("2000"));
+}
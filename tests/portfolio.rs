@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::utils::{
+    ids::{SymbolId, VenueId},
+    order::{Fill, Side},
+    portfolio::Portfolio,
+    tick::Tick,
+};
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+fn fill(exchange: &str, trade_id: &str, side: Side, qty: &str, price: &str) -> Fill {
+    Fill {
+        exchange: exchange.to_string(),
+        asset: "PEPE/USD".to_string(),
+        order_id: "O1".to_string(),
+        trade_id: trade_id.to_string(),
+        side,
+        price: dec(price),
+        qty: dec(qty),
+        fee: Decimal::ZERO,
+        fee_asset: "USD".to_string(),
+        timestamp: 0,
+    }
+}
+
+fn mark(exchange: &str, avg: &str) -> Tick {
+    Tick {
+        timestamp: 0,
+        timestamp2: 0,
+        avg: dec(avg),
+        exchange: VenueId::intern(exchange),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+#[test]
+fn adding_to_a_position_moves_the_average_cost() {
+    let mut portfolio = Portfolio::new();
+    portfolio.book_fill(&fill("Kraken", "T1", Side::Buy, "100", "10"));
+    portfolio.book_fill(&fill("Kraken", "T2", Side::Buy, "300", "12"));
+
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(position.base_qty, dec("400"));
+    assert_eq!(position.avg_price, dec("11.5"));
+    assert_eq!(position.quote_qty, dec("-4600"));
+    assert_eq!(position.realized_pnl, Decimal::ZERO);
+
+    //a replayed fill is not booked twice
+    assert!(!portfolio.book_fill(&fill("Kraken", "T2", Side::Buy, "300", "12")));
+    assert_eq!(
+        portfolio.position("Kraken", "PEPE/USD").unwrap().base_qty,
+        dec("400")
+    );
+}
+
+#[test]
+fn a_partial_close_realizes_against_the_average_cost() {
+    let mut portfolio = Portfolio::new();
+    portfolio.book_fill(&fill("Kraken", "T1", Side::Buy, "400", "11.5"));
+    portfolio.book_fill(&fill("Kraken", "T2", Side::Sell, "100", "13"));
+
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(position.base_qty, dec("300"));
+    assert_eq!(position.avg_price, dec("11.5"));
+    assert_eq!(position.realized_pnl, dec("150"));
+
+    portfolio.mark(&mark("Kraken", "12"));
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(position.unrealized_pnl(), dec("150"));
+    assert_eq!(position.net_pnl(), dec("300"));
+}
+
+#[test]
+fn flipping_through_zero_opens_the_rest_at_the_fill_price() {
+    let mut portfolio = Portfolio::new();
+    portfolio.book_fill(&fill("Kraken", "T1", Side::Buy, "100", "10"));
+    portfolio.book_fill(&fill("Kraken", "T2", Side::Sell, "150", "9"));
+
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(position.base_qty, dec("-50"));
+    assert_eq!(position.avg_price, dec("9"));
+    assert_eq!(position.realized_pnl, dec("-100"));
+
+    //covering the short below its price is a gain
+    portfolio.book_fill(&fill("Kraken", "T3", Side::Buy, "50", "8"));
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    assert_eq!(position.base_qty, Decimal::ZERO);
+    assert_eq!(position.avg_price, Decimal::ZERO);
+    assert_eq!(position.realized_pnl, dec("-50"));
+}
+
+#[test]
+fn fees_are_valued_in_quote_by_the_asset_they_are_paid_in() {
+    let mut portfolio = Portfolio::new();
+    let mut quote_fee = fill("Kraken", "T1", Side::Buy, "100", "10");
+    quote_fee.fee = dec("2");
+    portfolio.book_fill(&quote_fee);
+
+    let mut base_fee = fill("Kraken", "T2", Side::Buy, "100", "10");
+    base_fee.fee = dec("1");
+    base_fee.fee_asset = "PEPE".to_string();
+    portfolio.book_fill(&base_fee);
+
+    let mut other_fee = fill("Kraken", "T3", Side::Sell, "99", "12");
+    other_fee.fee = dec("0.01");
+    other_fee.fee_asset = "BNB".to_string();
+    portfolio.book_fill(&other_fee);
+
+    let position = portfolio.position("Kraken", "PEPE/USD").unwrap();
+    //the base fee shrank the position without a trade
+    assert_eq!(position.base_qty, dec("100"));
+    assert_eq!(position.quote_qty, dec("-2002") + dec("1188"));
+    assert_eq!(position.fees, dec("12"));
+    assert_eq!(position.other_fees["BNB"], dec("0.01"));
+    assert_eq!(position.realized_pnl, dec("198"));
+    assert_eq!(position.net_pnl(), dec("186"));
+}
+
+#[test]
+fn snapshots_carry_the_aggregate_over_venues() {
+    let mut portfolio = Portfolio::new();
+    portfolio.book_fill(&fill("Kraken", "T1", Side::Buy, "100", "10"));
+    portfolio.book_fill(&fill("Binance", "T1", Side::Sell, "40", "11"));
+    portfolio.mark(&mark("Kraken", "11"));
+    portfolio.mark(&mark("Binance", "11"));
+
+    let snapshot = portfolio.snapshot(1700000000000);
+    assert_eq!(snapshot.positions.len(), 2);
+    assert_eq!(snapshot.aggregates.len(), 1);
+    let pepe = &snapshot.aggregates[0];
+    assert_eq!(pepe.base, "PEPE");
+    assert_eq!(pepe.base_qty, dec("60"));
+    assert_eq!(pepe.quote_qty, dec("-560"));
+    assert_eq!(pepe.unrealized_pnl, dec("100"));
+    assert_eq!(snapshot.net_pnl, dec("100"));
+}
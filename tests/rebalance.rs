@@ -0,0 +1,231 @@
+mod mock_exchange;
+
+use std::{path::PathBuf, str::FromStr};
+
+use mock_exchange::{configure_clients, start, MOCK_KRAKEN_DEPOSIT_ADDRESS};
+use rust_decimal::Decimal;
+use sdla::utils::{
+    balance::{Balance, BalanceManager},
+    rebalance::{InventoryTarget, RebalanceMode, Rebalancer, TransferStatus, WhitelistedAddress},
+};
+use tokio::sync::RwLock;
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sdla-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+//half the asset on each venue, rebalanced once the share is more than 10% off
+fn target(asset: &str) -> InventoryTarget {
+    InventoryTarget {
+        asset: asset.to_string(),
+        kraken_share: dec("0.5"),
+        band: dec("0.1"),
+        min_transfer: dec("1000"),
+    }
+}
+
+fn whitelist_entry(asset: &str, to: &str) -> WhitelistedAddress {
+    WhitelistedAddress {
+        asset: asset.to_string(),
+        to: to.to_string(),
+        address: MOCK_KRAKEN_DEPOSIT_ADDRESS.to_string(),
+        kraken_withdraw_key: None,
+        kraken_deposit_method: Some("Ether (ERC20)".to_string()),
+    }
+}
+
+fn balances(kraken_free: &str, binance_free: &str, binance_locked: &str) -> BalanceManager {
+    asset_balances("PEPE", kraken_free, binance_free, binance_locked)
+}
+
+fn asset_balances(
+    asset: &str,
+    kraken_free: &str,
+    binance_free: &str,
+    binance_locked: &str,
+) -> BalanceManager {
+    let mut balances = BalanceManager::new();
+    let mut kraken = Balance::new("Kraken", asset);
+    kraken.free = dec(kraken_free);
+    balances.set(kraken);
+    let mut binance = Balance::new("Binance", asset);
+    binance.free = dec(binance_free);
+    binance.locked = dec(binance_locked);
+    balances.set(binance);
+    balances
+}
+
+fn rebalancer(
+    mode: RebalanceMode,
+    whitelist: Vec<WhitelistedAddress>,
+    dir: &std::path::Path,
+) -> Rebalancer {
+    asset_rebalancer("PEPE", mode, whitelist, dir)
+}
+
+fn asset_rebalancer(
+    asset: &str,
+    mode: RebalanceMode,
+    whitelist: Vec<WhitelistedAddress>,
+    dir: &std::path::Path,
+) -> Rebalancer {
+    let (kraken, binance) = configure_clients();
+    Rebalancer::new(
+        mode,
+        vec![target(asset)],
+        whitelist,
+        dir.join("approved").to_str().unwrap(),
+        dir.join("transfers.json").to_str().unwrap(),
+        kraken,
+        binance,
+    )
+    .unwrap()
+}
+
+#[test]
+fn proposals_need_a_whitelisted_address_for_the_asset_and_venue() {
+    let dir = temp_dir("rebalance-whitelist");
+    let balances = balances("10000", "90000", "0");
+
+    //wrong asset, wrong receiving venue
+    let whitelist = vec![
+        whitelist_entry("USDT", "Kraken"),
+        whitelist_entry("PEPE", "Binance"),
+    ];
+    let proposals = rebalancer(RebalanceMode::DryRun, whitelist, &dir).propose(&balances, 1);
+    assert!(proposals.is_empty());
+
+    let whitelist = vec![whitelist_entry("PEPE", "Kraken")];
+    let proposals = rebalancer(RebalanceMode::DryRun, whitelist, &dir).propose(&balances, 1);
+    assert_eq!(proposals.len(), 1);
+    assert_eq!(proposals[0].address, MOCK_KRAKEN_DEPOSIT_ADDRESS);
+    assert_eq!(proposals[0].to, "Kraken");
+}
+
+#[test]
+fn proposals_respect_band_min_transfer_and_free_balance() {
+    let dir = temp_dir("rebalance-sizing");
+    let rebalancer = rebalancer(
+        RebalanceMode::DryRun,
+        vec![whitelist_entry("PEPE", "Kraken")],
+        &dir,
+    );
+
+    //45% on Kraken is inside the band
+    assert!(rebalancer
+        .propose(&balances("45000", "55000", "0"), 1)
+        .is_empty());
+
+    //20% on Kraken, 30000 moves to get back to half
+    let proposals = rebalancer.propose(&balances("20000", "80000", "0"), 1);
+    assert_eq!(proposals.len(), 1);
+    assert_eq!(proposals[0].from, "Binance");
+    assert_eq!(proposals[0].amount, dec("30000"));
+    assert_eq!(proposals[0].status, TransferStatus::Proposed);
+
+    //locked funds count towards the share but only free ones can move
+    let proposals = rebalancer.propose(&balances("20000", "10000", "70000"), 1);
+    assert_eq!(proposals[0].amount, dec("10000"));
+
+    //nothing free enough to be worth a transfer
+    assert!(rebalancer
+        .propose(&balances("200", "500", "1000"), 1)
+        .is_empty());
+}
+
+#[tokio::test]
+async fn proposals_survive_a_restart_through_the_state_file() {
+    let dir = temp_dir("rebalance-state");
+    let whitelist = vec![whitelist_entry("PEPE", "Kraken")];
+    let balances = RwLock::new(balances("20000", "80000", "0"));
+
+    let mut first = rebalancer(RebalanceMode::ManualApproval, whitelist.clone(), &dir);
+    first.run(&balances).await.unwrap();
+    assert_eq!(first.transfers().len(), 1);
+    assert_eq!(first.transfers()[0].status, TransferStatus::Proposed);
+
+    //one transfer per asset in flight, a restart doesn't propose it again
+    let mut second = rebalancer(RebalanceMode::ManualApproval, whitelist, &dir);
+    assert_eq!(second.transfers().len(), 1);
+    assert_eq!(second.transfers()[0].id, first.transfers()[0].id);
+    assert_eq!(second.transfers()[0].amount, dec("30000"));
+    second.run(&balances).await.unwrap();
+    assert_eq!(second.transfers().len(), 1);
+}
+
+#[tokio::test]
+async fn approved_binance_withdrawal_is_sent_to_binance() {
+    let dir = temp_dir("rebalance-binance-withdraw");
+    let whitelist = vec![whitelist_entry("PEPE", "Kraken")];
+    let balances = RwLock::new(balances("20000", "80000", "0"));
+
+    //the mock key has withdrawals disabled, so Binance refuses and the transfer fails
+    //instead of being marked withdrawn
+    let mut rebalancer = rebalancer(RebalanceMode::Auto, whitelist, &dir);
+    rebalancer.run(&balances).await.unwrap();
+    let transfer = &rebalancer.transfers()[0];
+    match &transfer.status {
+        TransferStatus::Failed(message) => assert!(message.contains("not authorized")),
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!(transfer.refid, None);
+}
+
+#[tokio::test]
+async fn binance_withdrawal_without_an_answer_is_found_by_its_order_id() {
+    let dir = temp_dir("rebalance-binance-unknown-taken");
+    let whitelist = vec![whitelist_entry("SHIB", "Kraken")];
+    let balances = RwLock::new(asset_balances("SHIB", "20000", "80000", "0"));
+    let mock = start();
+    mock.lose_binance_withdraw_answers("SHIB", true);
+
+    //the venue took the withdrawal but the answer says nothing, it must not count as failed
+    let mut rebalancer = asset_rebalancer("SHIB", RebalanceMode::Auto, whitelist, &dir);
+    rebalancer.run(&balances).await.unwrap();
+    assert_eq!(rebalancer.transfers().len(), 1);
+    let id = rebalancer.transfers()[0].id.clone();
+    assert!(matches!(
+        rebalancer.transfers()[0].status,
+        TransferStatus::Unknown(_)
+    ));
+    assert_eq!(rebalancer.transfers()[0].refid, None);
+
+    //the next round finds it in the withdraw history instead of proposing a new one
+    rebalancer.run(&balances).await.unwrap();
+    assert_eq!(rebalancer.transfers().len(), 1);
+    let taken: Vec<_> = mock
+        .binance_withdrawals()
+        .into_iter()
+        .filter(|w| w["withdrawOrderId"] == id.as_str())
+        .collect();
+    assert_eq!(taken.len(), 1);
+    let transfer = &rebalancer.transfers()[0];
+    assert_eq!(transfer.status, TransferStatus::Withdrawn);
+    assert_eq!(transfer.refid.as_deref(), taken[0]["id"].as_str());
+}
+
+#[tokio::test]
+async fn binance_withdrawal_without_an_answer_stays_in_flight() {
+    let dir = temp_dir("rebalance-binance-unknown-lost");
+    let whitelist = vec![whitelist_entry("FLOKI", "Kraken")];
+    let balances = RwLock::new(asset_balances("FLOKI", "20000", "80000", "0"));
+    start().lose_binance_withdraw_answers("FLOKI", false);
+
+    //not in the withdraw history yet, the transfer waits instead of being sent again
+    let mut rebalancer = asset_rebalancer("FLOKI", RebalanceMode::Auto, whitelist, &dir);
+    for _ in 0..2 {
+        rebalancer.run(&balances).await.unwrap();
+        assert_eq!(rebalancer.transfers().len(), 1);
+        assert!(matches!(
+            rebalancer.transfers()[0].status,
+            TransferStatus::Unknown(_)
+        ));
+    }
+}
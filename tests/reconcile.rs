@@ -0,0 +1,232 @@
+mod mock_exchange;
+
+use std::{str::FromStr, time::Duration};
+
+use mock_exchange::{configure_clients, KRAKEN_PAGE_SIZE};
+use rust_decimal::Decimal;
+use sdla::{
+    exchanges::RestClient,
+    utils::{
+        balance::{Balance, BalanceManager},
+        oms::{Oms, EXTERNAL_STRATEGY},
+        order::{OrderRequest, OrderStatus, OrderType, Side},
+        portfolio::Portfolio,
+        reconcile::{Discrepancy, Reconciler, TradingGate},
+    },
+};
+use tokio::sync::RwLock;
+
+fn reconciler(repair: bool) -> Reconciler {
+    let (kraken, binance) = configure_clients();
+    Reconciler {
+        repair,
+        lookback_ms: 24 * 60 * 60 * 1000,
+        assets_kraken: vec!["PEPE/USD".to_string()],
+        assets_binance: vec!["PEPE/USDT".to_string()],
+        kraken,
+        binance,
+    }
+}
+
+//what main seeds at startup
+async fn seeded_balances(reconciler: &Reconciler) -> RwLock<BalanceManager> {
+    let mut balances = BalanceManager::new();
+    let response = reconciler.kraken.query("BalanceEx", "").await;
+    balances.seed(
+        "Kraken",
+        Balance::extract_balances_kraken_ex(&response).unwrap(),
+    );
+    let response = reconciler.binance.query("account", "").await;
+    balances.seed(
+        "Binance",
+        Balance::extract_balances_binance(&response).unwrap(),
+    );
+    RwLock::new(balances)
+}
+
+fn unbooked(discrepancies: &[Discrepancy]) -> Vec<String> {
+    discrepancies
+        .iter()
+        .filter_map(|d| match d {
+            Discrepancy::UnbookedFill(fill) => Some(fill.trade_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn seeded_history_is_not_booked_again_and_new_fills_are() {
+    let reconciler = reconciler(true);
+    let oms = RwLock::new(Oms::new());
+    let portfolio = RwLock::new(Portfolio::new());
+    let balances = seeded_balances(&reconciler).await;
+    let gate = TradingGate::new("reconcile");
+
+    //the binance history has one trade, the seeded balances already contain it
+    assert_eq!(reconciler.seed_fills(&portfolio).await.unwrap(), 1);
+    let report = reconciler.run(&oms, &portfolio, &balances).await.unwrap();
+    assert!(unbooked(&report.discrepancies).is_empty());
+    assert!(!report
+        .discrepancies
+        .iter()
+        .any(|d| matches!(d, Discrepancy::BalanceMismatch { .. })));
+    report.update_gate(&gate);
+    assert!(gate.is_open());
+    assert_eq!(portfolio.read().await.positions().count(), 0);
+
+    //fills nobody streamed to us are booked, they are older than the balance snapshot
+    //so the balances are left as the venue reports them. there are more than fit
+    //on one page of the trade history
+    let placed = KRAKEN_PAGE_SIZE + 1;
+    for _ in 0..placed {
+        let response = reconciler
+            .kraken
+            .query(
+                "AddOrder",
+                "ordertype=market&type=buy&volume=1000000&pair=PEPE/USD",
+            )
+            .await;
+        assert!(response.contains("\"error\":[]"), "{}", response);
+    }
+    let report = reconciler.run(&oms, &portfolio, &balances).await.unwrap();
+    assert_eq!(unbooked(&report.discrepancies).len(), placed);
+    report.update_gate(&gate);
+    assert!(gate.is_open());
+    let position = portfolio
+        .read()
+        .await
+        .position("Kraken", "PEPE/USD")
+        .cloned()
+        .unwrap();
+    assert_eq!(position.base_qty, Decimal::from(1000000 * placed));
+    assert_eq!(
+        balances.read().await.get("Kraken", "PEPE").free,
+        Decimal::from_str("50000000").unwrap()
+    );
+
+    //and only once
+    let report = reconciler.run(&oms, &portfolio, &balances).await.unwrap();
+    assert!(unbooked(&report.discrepancies).is_empty());
+}
+
+#[tokio::test]
+async fn missing_and_untracked_orders_close_the_gate() {
+    let mock = mock_exchange::start();
+    let oms = RwLock::new(Oms::new());
+    let portfolio = RwLock::new(Portfolio::new());
+    let gate = TradingGate::new("reconcile");
+    gate.open();
+
+    //ours, acked, and gone from the venue
+    {
+        let mut oms = oms.write().await;
+        let request = oms.create(
+            "oneleg",
+            "Kraken",
+            OrderRequest {
+                asset: "PEPE/USD".to_string(),
+                side: Side::Buy,
+                order_type: OrderType::Market,
+                qty: Decimal::from(1000000),
+                price: None,
+                client_order_id: None,
+            },
+        );
+        oms.on_ack(&request.client_order_id.unwrap(), "OGONE1-AAAAA-BBBBBB");
+    }
+    //resting on the venue, placed by someone else
+    let untracked = mock.add_kraken_open_order("PEPE/USD", "sell", "2000000", "0.0000120");
+
+    let check_only = reconciler(false);
+    let balances = seeded_balances(&check_only).await;
+    let report = check_only.run(&oms, &portfolio, &balances).await.unwrap();
+    assert!(report.discrepancies.iter().any(|d| matches!(
+        d,
+        Discrepancy::MissingOrder { exchange, order_id }
+            if exchange == "Kraken" && order_id == "OGONE1-AAAAA-BBBBBB"
+    )));
+    assert!(report.discrepancies.iter().any(|d| matches!(
+        d,
+        Discrepancy::UntrackedOrder(update) if update.order_id == untracked
+    )));
+    report.update_gate(&gate);
+    assert!(!gate.is_open());
+    //nothing is touched without repair
+    assert!(oms
+        .read()
+        .await
+        .get_by_order_id("Kraken", &untracked)
+        .is_none());
+
+    //the untracked order is adopted, the missing one is closed since the venue
+    //did not know it when asked directly, and trading resumes
+    let report = reconciler(true)
+        .run(&oms, &portfolio, &balances)
+        .await
+        .unwrap();
+    assert!(report.is_consistent());
+    report.update_gate(&gate);
+    assert!(gate.is_open());
+    {
+        let oms = oms.read().await;
+        let adopted = oms.get_by_order_id("Kraken", &untracked).unwrap();
+        assert_eq!(adopted.strategy, EXTERNAL_STRATEGY);
+        assert!(adopted.is_open());
+        let missing = oms
+            .get_by_order_id("Kraken", "OGONE1-AAAAA-BBBBBB")
+            .unwrap();
+        assert_eq!(missing.status, OrderStatus::Expired);
+    }
+
+    //and nothing is left to find
+    let report = reconciler(false)
+        .run(&oms, &portfolio, &balances)
+        .await
+        .unwrap();
+    assert!(!report
+        .discrepancies
+        .iter()
+        .any(|d| matches!(d, Discrepancy::MissingOrder { .. })));
+}
+
+#[tokio::test]
+async fn stream_updates_during_the_fetch_are_kept() {
+    let mock = mock_exchange::start();
+    let reconciler = reconciler(true);
+    let oms = RwLock::new(Oms::new());
+    let portfolio = RwLock::new(Portfolio::new());
+    let balances = seeded_balances(&reconciler).await;
+    reconciler.seed_fills(&portfolio).await.unwrap();
+
+    //the balances are fetched first, the venue is slow on what comes after
+    mock.delay("/0/private/ClosedOrders", 500);
+    let stream = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        //no lock is held while the venues are asked
+        let mut balances = tokio::time::timeout(Duration::from_millis(100), balances.write())
+            .await
+            .expect("balances locked during the fetch");
+        let _oms = tokio::time::timeout(Duration::from_millis(100), oms.write())
+            .await
+            .expect("oms locked during the fetch");
+        balances.set(Balance {
+            currency: "USD".to_string(),
+            free: Decimal::from(900),
+            locked: Decimal::from(25),
+            exchange: "Kraken".to_string(),
+        });
+    };
+    let (report, _) = tokio::join!(reconciler.run(&oms, &portfolio, &balances), stream);
+    mock.delay("/0/private/ClosedOrders", 0);
+
+    //the balance snapshot is older than the stream update, it is neither compared nor copied in
+    let report = report.unwrap();
+    assert!(!report.discrepancies.iter().any(|d| matches!(
+        d,
+        Discrepancy::BalanceMismatch { venue, .. } if venue.currency == "USD"
+    )));
+    assert_eq!(
+        balances.read().await.get("Kraken", "USD").free,
+        Decimal::from(900)
+    );
+}
@@ -0,0 +1,91 @@
+//test vectors are the worked examples from the venues' API documentation
+use sdla::exchanges::{binance::BinanceClient, kraken::KrakenClient, SignedPayload};
+
+//docs.kraken.com, REST authentication
+const KRAKEN_SECRET: &str =
+    "kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==";
+const KRAKEN_PATH: &str = "/0/private/AddOrder";
+const KRAKEN_NONCE: u64 = 1616492376594;
+const KRAKEN_BODY: &str = "ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+const KRAKEN_PAYLOAD: &str =
+    "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+const KRAKEN_SIGNATURE: &str =
+    "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ==";
+
+//developers.binance.com, SIGNED endpoint examples for POST /api/v3/order
+const BINANCE_SECRET: &str = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+const BINANCE_BODY: &str =
+    "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000";
+const BINANCE_TIMESTAMP: i64 = 1499827319559;
+const BINANCE_QUERY: &str = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+const BINANCE_SIGNATURE: &str = "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71";
+
+#[test]
+fn kraken_signature_matches_documentation() {
+    let signature = KrakenClient::get_signature(
+        KRAKEN_PATH,
+        &KRAKEN_NONCE.to_string(),
+        KRAKEN_PAYLOAD,
+        KRAKEN_SECRET,
+    );
+    assert_eq!(signature, KRAKEN_SIGNATURE);
+}
+
+#[test]
+fn kraken_request_builder_signs_what_it_sends() {
+    let request =
+        KrakenClient::build_private_request(KRAKEN_PATH, KRAKEN_NONCE, KRAKEN_BODY, KRAKEN_SECRET);
+    assert_eq!(
+        request,
+        SignedPayload {
+            payload: KRAKEN_PAYLOAD.to_string(),
+            signature: KRAKEN_SIGNATURE.to_string(),
+        }
+    );
+}
+
+#[test]
+fn kraken_request_builder_without_body() {
+    let request = KrakenClient::build_private_request(KRAKEN_PATH, KRAKEN_NONCE, "", KRAKEN_SECRET);
+    assert_eq!(request.payload, "nonce=1616492376594");
+    assert_eq!(
+        request.signature,
+        KrakenClient::get_signature(
+            KRAKEN_PATH,
+            "1616492376594",
+            "nonce=1616492376594",
+            KRAKEN_SECRET
+        )
+    );
+}
+
+#[test]
+fn binance_signature_matches_documentation() {
+    assert_eq!(
+        BinanceClient::get_signature(BINANCE_QUERY, BINANCE_SECRET),
+        BINANCE_SIGNATURE
+    );
+}
+
+#[test]
+fn binance_query_builder_signs_what_it_sends() {
+    let request =
+        BinanceClient::build_signed_query(BINANCE_BODY, BINANCE_TIMESTAMP, BINANCE_SECRET);
+    assert_eq!(
+        request,
+        SignedPayload {
+            payload: BINANCE_QUERY.to_string(),
+            signature: BINANCE_SIGNATURE.to_string(),
+        }
+    );
+}
+
+#[test]
+fn binance_query_builder_without_body() {
+    let request = BinanceClient::build_signed_query("", BINANCE_TIMESTAMP, BINANCE_SECRET);
+    assert_eq!(request.payload, "timestamp=1499827319559");
+    assert_eq!(
+        request.signature,
+        BinanceClient::get_signature("timestamp=1499827319559", BINANCE_SECRET)
+    );
+}
@@ -0,0 +1,146 @@
+use rust_decimal::Decimal;
+use sdla::utils::{
+    ids::{SymbolId, VenueId},
+    stats::{Ewma, MarketStats, RatioStats, RollingStats, StatsConfig},
+    tick::Tick,
+};
+
+const CONFIG: StatsConfig = StatsConfig {
+    window_ms: 1000,
+    ewma_half_life_ms: 1000,
+    max_time_diff_ms: 100,
+};
+
+fn tick(exchange: &str, timestamp2: u64, avg: f64) -> Tick {
+    Tick {
+        timestamp: timestamp2,
+        timestamp2,
+        avg: Decimal::try_from(avg).unwrap(),
+        exchange: VenueId::intern(exchange),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} is not {}",
+        actual,
+        expected
+    );
+}
+
+//mean and sample variance computed from scratch
+fn direct(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+#[test]
+fn rolling_stats_add_and_remove_match_a_direct_computation() {
+    let mut stats = RollingStats::new(1000);
+    assert_eq!(stats.mean(), None);
+    let values = [4.0, 7.0, 13.0, 16.0, 2.5, 9.0];
+    for (i, value) in values.iter().enumerate() {
+        stats.update(i as u64 * 300, *value);
+    }
+    //at 1500 everything before 500 has left the window
+    assert_eq!(stats.count(), 4);
+    let (mean, variance) = direct(&values[2..]);
+    assert_close(stats.mean().unwrap(), mean);
+    assert_close(stats.variance().unwrap(), variance);
+    assert_close(stats.zscore(mean + variance.sqrt()).unwrap(), 1.0);
+
+    //one value left has no variance, none left has no mean
+    stats.expire(2400);
+    assert_eq!(stats.count(), 1);
+    assert_close(stats.mean().unwrap(), 9.0);
+    assert_eq!(stats.variance(), None);
+    stats.expire(10000);
+    assert_eq!(stats.mean(), None);
+
+    //non finite values are ignored
+    stats.update(10000, f64::NAN);
+    assert_eq!(stats.count(), 0);
+}
+
+#[test]
+fn ewma_decays_with_time_not_with_ticks() {
+    let mut ewma = Ewma::new(1000);
+    assert_eq!(ewma.mean(), None);
+    ewma.update(0, 0.0);
+    assert_eq!(ewma.mean(), Some(0.0));
+
+    //one half life later the new value gets half the weight
+    ewma.update(1000, 10.0);
+    assert_close(ewma.mean().unwrap(), 5.0);
+    assert_close(ewma.std().unwrap(), 5.0);
+
+    //a burst at the same time moves nothing
+    for _ in 0..100 {
+        ewma.update(1000, 100.0);
+    }
+    assert_close(ewma.mean().unwrap(), 5.0);
+
+    //two half lives give three quarters of the weight
+    ewma.update(3000, 9.0);
+    assert_close(ewma.mean().unwrap(), 8.0);
+    assert!(ewma.zscore(8.0).unwrap().abs() < 1e-9);
+}
+
+#[test]
+fn the_ratio_is_only_sampled_when_the_legs_are_close() {
+    let mut ratio = RatioStats::new(&CONFIG);
+    ratio.update(&tick("Binance", 1000, 1.02), true);
+    assert_eq!(ratio.ratio(), None);
+    ratio.update(&tick("Kraken", 1050, 1.0), false);
+    assert_close(ratio.ratio().unwrap(), 1.02);
+
+    //the slow leg ticks again, too long after the fast one
+    ratio.update(&tick("Kraken", 1200, 1.0), false);
+    assert_eq!(ratio.ratio(), None);
+    assert_eq!(ratio.rolling.count(), 1);
+    assert_eq!(ratio.zscore(), None);
+
+    ratio.update(&tick("Binance", 1210, 0.98), true);
+    ratio.update(&tick("Binance", 1220, 1.0), true);
+    let (mean, variance) = direct(&[1.02, 0.98, 1.0]);
+    assert_close(ratio.rolling.mean().unwrap(), mean);
+    assert_close(ratio.zscore().unwrap(), (1.0 - mean) / variance.sqrt());
+
+    //the gap is stds standard deviations of the ratio, zero stds is zero
+    let gap = ratio.adaptive_gap(2.0).unwrap();
+    assert!((gap - Decimal::try_from(2.0 * variance.sqrt()).unwrap()).abs() < Decimal::new(1, 12));
+    assert_eq!(ratio.adaptive_gap(0.0), Some(Decimal::ZERO));
+}
+
+#[test]
+fn market_stats_feed_the_venues_and_the_ratio() {
+    let mut stats = MarketStats::new(CONFIG, "Binance", "Kraken");
+    stats.update(&tick("Kraken", 1000, 1.0));
+    stats.update(&tick("Binance", 1010, 1.01));
+    stats.update(&tick("Kraken", 1020, 1.02));
+    stats.update(&tick("Coinbase", 1030, 5.0));
+
+    assert_eq!(
+        stats
+            .venue(VenueId::intern("Kraken"))
+            .unwrap()
+            .price
+            .count(),
+        2
+    );
+    assert!(stats.venue(VenueId::intern("Coinbase")).is_some());
+    assert_eq!(stats.ratio.rolling.count(), 2);
+    //one log return, from 1.0 to 1.02
+    assert_close(
+        stats
+            .venue(VenueId::intern("Kraken"))
+            .unwrap()
+            .realized_vol(),
+        (1.02f64).ln().abs(),
+    );
+}
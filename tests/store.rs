@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use sdla::utils::{
+    bars::{BarKind, BarManager},
+    ids::{SymbolId, VenueId},
+    store::{self, BookRow, Record, TickStore},
+    tick::Tick,
+};
+
+//2023-11-14 22:13:20 utc, the next day starts 6400 s later
+const DAY_ONE: u64 = 1700000000000;
+const DAY_TWO: u64 = 1700006400000;
+
+fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+fn store_root(name: &str) -> String {
+    let root = std::env::temp_dir().join(format!("sdla-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    root.to_str().unwrap().to_string()
+}
+
+fn tick(timestamp2: u64, avg: &str, volume: &str) -> Tick {
+    Tick {
+        timestamp: timestamp2 - 5,
+        timestamp2,
+        avg: dec(avg),
+        exchange: VenueId::intern("Kraken"),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: dec(volume),
+    }
+}
+
+fn book_row(timestamp2: u64, bid: bool, price: &str, qty: &str) -> BookRow {
+    BookRow {
+        timestamp2,
+        exchange: "Kraken".to_string(),
+        asset: "PEPE/USD".to_string(),
+        bid,
+        price: dec(price),
+        qty: dec(qty),
+    }
+}
+
+#[test]
+fn ticks_round_trip_across_flushes_and_days() {
+    let root = store_root("store-ticks");
+    let mut store = TickStore::new(&root);
+    store.record(Record::Tick(tick(DAY_ONE, "0.000011485", "0")));
+    store.record(Record::Tick(tick(
+        DAY_ONE + 1000,
+        "0.00001149",
+        "2500000.5",
+    )));
+    store.flush().unwrap();
+    //a second batch in the same segment, and a late tick for the first day
+    store.record(Record::Tick(tick(DAY_TWO, "0.0000115", "0")));
+    store.record(Record::Tick(tick(DAY_ONE + 500, "0.00001148", "100")));
+    store.close().unwrap();
+
+    let dir = store::partition_dir(&root, store::TICKS, "Kraken", "PEPE/USD");
+    let ticks = store::read_ticks(&dir, 0, u64::MAX).unwrap();
+    let timestamps: Vec<u64> = ticks.iter().map(|t| t.timestamp2).collect();
+    assert_eq!(
+        timestamps,
+        [DAY_ONE, DAY_ONE + 500, DAY_ONE + 1000, DAY_TWO]
+    );
+    assert_eq!(ticks[0].timestamp, DAY_ONE - 5);
+    assert_eq!(ticks[0].avg, dec("0.000011485"));
+    assert_eq!(ticks[2].volume, dec("2500000.5"));
+    assert_eq!(ticks[3].exchange, VenueId::intern("Kraken"));
+    assert_eq!(ticks[3].asset, SymbolId::intern("PEPE/USD"));
+
+    //[from, to) on timestamp2, including ranges inside one day
+    let ticks = store::read_ticks(&dir, DAY_ONE + 500, DAY_TWO).unwrap();
+    let timestamps: Vec<u64> = ticks.iter().map(|t| t.timestamp2).collect();
+    assert_eq!(timestamps, [DAY_ONE + 500, DAY_ONE + 1000]);
+    assert!(store::read_ticks(&dir, DAY_TWO + 1, u64::MAX)
+        .unwrap()
+        .is_empty());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn book_rows_round_trip_through_read_book() {
+    let root = store_root("store-book");
+    let mut store = TickStore::new(&root);
+    store.record(Record::Book(book_row(
+        DAY_ONE,
+        true,
+        "0.00001148",
+        "1000000",
+    )));
+    store.record(Record::Book(book_row(DAY_ONE, false, "0.00001149", "0")));
+    //book rows and ticks of the same pair go to separate tables
+    store.record(Record::Tick(tick(DAY_ONE, "0.000011485", "0")));
+    store.close().unwrap();
+
+    let dir = store::partition_dir(&root, store::BOOK, "Kraken", "PEPE/USD");
+    let rows = store::read_book(&dir, 0, u64::MAX).unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            book_row(DAY_ONE, true, "0.00001148", "1000000"),
+            book_row(DAY_ONE, false, "0.00001149", "0"),
+        ]
+    );
+    let dir = store::partition_dir(&root, store::TICKS, "Kraken", "PEPE/USD");
+    assert_eq!(store::read_ticks(&dir, 0, u64::MAX).unwrap().len(), 1);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn decimals_past_the_stored_scale_are_rounded_half_to_even() {
+    let root = store_root("store-rounding");
+    let mut store = TickStore::new(&root);
+    //20 decimals, the stored scale is 18
+    store.record(Record::Tick(tick(DAY_ONE, "0.00000000000000000150", "0")));
+    store.record(Record::Tick(tick(
+        DAY_ONE + 1,
+        "0.00000000000000000250",
+        "0",
+    )));
+    store.record(Record::Tick(tick(
+        DAY_ONE + 2,
+        "0.00000000000000000251",
+        "0",
+    )));
+    store.close().unwrap();
+
+    let dir = store::partition_dir(&root, store::TICKS, "Kraken", "PEPE/USD");
+    let prices: Vec<Decimal> = store::read_ticks(&dir, 0, u64::MAX)
+        .unwrap()
+        .iter()
+        .map(|t| t.avg)
+        .collect();
+    assert_eq!(
+        prices,
+        [
+            dec("0.000000000000000002"),
+            dec("0.000000000000000002"),
+            dec("0.000000000000000003")
+        ]
+    );
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn finished_bars_round_trip_through_read_bars() {
+    let root = store_root("store-bars");
+    let mut store = TickStore::new(&root);
+    let mut bars = BarManager::new(vec![BarKind::Time(1000), BarKind::Ticks(2)], 10);
+    for (timestamp2, avg, volume) in [
+        (DAY_ONE, "0.00001148", "0"),
+        (DAY_ONE + 400, "0.00001150", "2500000.5"),
+        (DAY_ONE + 1200, "0.00001149", "0"),
+    ] {
+        for bar in bars.update(&tick(timestamp2, avg, volume)) {
+            store.record(Record::Bar(bar));
+        }
+    }
+    store.close().unwrap();
+
+    let dir = store::partition_dir(&root, store::BARS, "Kraken", "PEPE/USD");
+    let stored = store::read_bars(&dir, 0, u64::MAX).unwrap();
+    let kinds: Vec<&str> = stored.iter().map(|bar| bar.kind.as_str()).collect();
+    //the two tick bar finished first
+    assert_eq!(kinds, ["t2", "1s"]);
+    assert_eq!(stored[0].close, dec("0.0000115"));
+    let second = &stored[1];
+    assert_eq!((second.start, second.end), (DAY_ONE, DAY_ONE + 1000));
+    assert_eq!(second.high, dec("0.0000115"));
+    assert_eq!(second.volume, dec("2500000.5"));
+    assert_eq!(second.ticks, 2);
+
+    //[from, to) on the bar start
+    assert!(store::read_bars(&dir, DAY_ONE + 1, u64::MAX)
+        .unwrap()
+        .is_empty());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn legacy_jsonl_recordings_still_load() {
+    let root = store_root("store-legacy");
+    std::fs::create_dir_all(&root).unwrap();
+    let file = format!("{}/Kraken_PEPE-USD.jsonl", root);
+    //recordings from before volume was kept have no volume field
+    std::fs::write(
+        &file,
+        r#"{"timestamp":0,"timestamp2":1700000000000,"avg":"0.000011485","exchange":"Kraken","asset":"PEPE/USD"}
+{"timestamp":0,"timestamp2":1700000000100,"avg":"0.00001149","exchange":"Kraken","asset":"PEPE/USD","volume":"0"}
+"#,
+    )
+    .unwrap();
+
+    let ticks = store::load_ticks(&file).unwrap();
+    assert_eq!(ticks.len(), 2);
+    assert_eq!(ticks[0].volume, Decimal::ZERO);
+    assert_eq!(ticks[1].avg, dec("0.00001149"));
+    std::fs::remove_dir_all(&root).unwrap();
+}
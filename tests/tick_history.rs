@@ -0,0 +1,110 @@
+use rust_decimal::Decimal;
+use sdla::{
+    exchanges::feed::feed_time_millis,
+    utils::{
+        ids::{SymbolId, VenueId},
+        tick::{Tick, TickHistory},
+    },
+};
+
+fn tick(exchange: &str, timestamp2: u64) -> Tick {
+    Tick {
+        timestamp: timestamp2,
+        timestamp2,
+        avg: Decimal::from(timestamp2),
+        exchange: VenueId::intern(exchange),
+        asset: SymbolId::intern("PEPE/USD"),
+        volume: Decimal::ZERO,
+    }
+}
+
+fn timestamps<'a>(ticks: impl Iterator<Item = &'a Tick>) -> Vec<u64> {
+    ticks.map(|t| t.timestamp2).collect()
+}
+
+#[test]
+fn late_ticks_are_inserted_in_order() {
+    let mut history = TickHistory::new(VenueId::intern("Kraken"), 60000);
+    for ts in [1000, 3000, 2000, 4000, 1500, 3000] {
+        history.add_tick(tick("Kraken", ts));
+    }
+    //ticks of another venue are ignored
+    history.add_tick(tick("Binance", 2500));
+
+    assert_eq!(
+        timestamps(history.since(0)),
+        [1000, 1500, 2000, 3000, 3000, 4000]
+    );
+    assert_eq!(history.back().unwrap().timestamp2, 4000);
+}
+
+#[test]
+fn ticks_past_the_retention_are_dropped() {
+    let mut history = TickHistory::new(VenueId::intern("Kraken"), 1000);
+    for ts in [1000, 1500, 2000] {
+        history.add_tick(tick("Kraken", ts));
+    }
+    //exactly retention_ms behind the newest is kept
+    assert_eq!(history.len(), 3);
+
+    history.add_tick(tick("Kraken", 2600));
+    assert_eq!(timestamps(history.since(0)), [2000, 2600]);
+
+    //a late tick already past the retention does not survive
+    history.add_tick(tick("Kraken", 1200));
+    assert_eq!(timestamps(history.since(0)), [2000, 2600]);
+}
+
+#[test]
+fn lookups_at_the_boundaries() {
+    let mut history = TickHistory::new(VenueId::intern("Kraken"), 60000);
+    assert!(history.is_empty());
+    assert!(history.tick_at(u64::MAX).is_none());
+
+    for ts in [1000, 2000, 3000] {
+        history.add_tick(tick("Kraken", ts));
+    }
+    //tick_at is the last tick at or before ts
+    assert!(history.tick_at(999).is_none());
+    assert_eq!(history.tick_at(1000).unwrap().timestamp2, 1000);
+    assert_eq!(history.tick_at(2999).unwrap().timestamp2, 2000);
+    assert_eq!(history.tick_at(u64::MAX).unwrap().timestamp2, 3000);
+
+    //price_at is the price of that tick
+    assert!(history.price_at(999).is_none());
+    assert_eq!(history.price_at(2500), Some(Decimal::from(2000)));
+
+    //range is half open
+    assert_eq!(timestamps(history.range(1000, 3000)), [1000, 2000]);
+    assert_eq!(timestamps(history.range(1001, 3001)), [2000, 3000]);
+    assert!(history.range(2000, 2000).next().is_none());
+    assert!(history.range(3000, 1000).next().is_none());
+
+    //since includes ts itself
+    assert_eq!(timestamps(history.since(2000)), [2000, 3000]);
+    assert_eq!(timestamps(history.since(2001)), [3000]);
+    assert!(history.since(3001).next().is_none());
+}
+
+//what main allows between the newest fast tick and now
+const MAX_AGE_MS: u64 = 2000;
+
+#[test]
+fn feed_stamped_ticks_are_fresh_until_they_age() {
+    let mut history = TickHistory::new(VenueId::intern("Binance"), 60000);
+    assert!(!history.is_fresh(feed_time_millis(), MAX_AGE_MS));
+
+    //stamped the way the feed stamps them, checked the way the trading loop checks them
+    let stamped = feed_time_millis();
+    history.add_tick(tick("Binance", stamped));
+    assert!(history.is_fresh(feed_time_millis(), MAX_AGE_MS));
+
+    //wall clock time is ahead of timestamp2 by the venue offset, a tick would look stale
+    let wall_clock = chrono::Utc::now().timestamp_millis() as u64;
+    assert!(wall_clock - stamped > MAX_AGE_MS);
+    assert!(!history.is_fresh(wall_clock, MAX_AGE_MS));
+
+    //a frozen feed goes stale once its newest tick is older than the max age
+    assert!(history.is_fresh(stamped + MAX_AGE_MS, MAX_AGE_MS));
+    assert!(!history.is_fresh(stamped + MAX_AGE_MS + 1, MAX_AGE_MS));
+}